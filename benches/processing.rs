@@ -0,0 +1,228 @@
+//! Throughput of the processing hot path, run with `cargo bench`.
+use std::{hint::black_box, io::Cursor};
+
+use bank::{BATCH_SIZE, Money, State, Transaction, TransactionReader, TransactionType};
+use criterion::{BatchSize, Criterion, Throughput, criterion_group, criterion_main};
+use tokio::{runtime::Runtime, sync::mpsc};
+
+/// Number of transactions in the mixed workload.
+const MIXED_COUNT: u32 = 1_000_000;
+
+/// Number of transactions in the CSV and dispute-heavy workloads.
+const SMALL_COUNT: u32 = 100_000;
+
+/// Deterministic pseudo-random numbers (xorshift64), so every machine runs the same workload.
+struct Generator(u64);
+
+impl Generator {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// A number in `0..bound`.
+    fn below(&mut self, bound: u64) -> u64 {
+        self.next() % bound
+    }
+}
+
+/// Deposits and withdrawals over 1000 clients, with a few disputes, resolves and chargebacks of earlier deposits.
+/// Some withdrawals fail for insufficient funds and some disputes reference resolved transactions, as in real feeds.
+fn mixed_workload(count: u32) -> Vec<Transaction> {
+    let mut generator = Generator(0x5eed);
+    let mut deposits: Vec<(u16, u32)> = Vec::new();
+    (0..count)
+        .map(|tx| {
+            let client = generator.below(1000) as u16;
+            let amount = Some(Money::from_raw(generator.below(1_000_000) as i64 + 1));
+            match generator.below(100) {
+                0..60 => {
+                    deposits.push((client, tx));
+                    Transaction::new(TransactionType::Deposit, client, tx, amount)
+                }
+                60..90 => Transaction::new(TransactionType::Withdrawal, client, tx, amount),
+                roll if !deposits.is_empty() => {
+                    let (client, deposit) =
+                        deposits[generator.below(deposits.len() as u64) as usize];
+                    let tx_type = match roll {
+                        90..95 => TransactionType::Dispute,
+                        95..98 => TransactionType::Resolve,
+                        _ => TransactionType::Chargeback,
+                    };
+                    Transaction::new(tx_type, client, deposit, None)
+                }
+                _ => Transaction::new(TransactionType::Deposit, client, tx, amount),
+            }
+        })
+        .collect()
+}
+
+/// Every deposit is disputed right away, and then resolved or charged back.
+fn dispute_workload(count: u32) -> Vec<Transaction> {
+    let mut generator = Generator(0xd15);
+    (0..count / 3)
+        .flat_map(|tx| {
+            let client = generator.below(1000) as u16;
+            let amount = Some(Money::from_raw(generator.below(1_000_000) as i64 + 1));
+            let settle = if generator.below(4) == 0 {
+                TransactionType::Chargeback
+            } else {
+                TransactionType::Resolve
+            };
+            [
+                Transaction::new(TransactionType::Deposit, client, tx, amount),
+                Transaction::new(TransactionType::Dispute, client, tx, None),
+                Transaction::new(settle, client, tx, None),
+            ]
+        })
+        .collect()
+}
+
+/// Writes the transactions as CSV input.
+fn to_csv(transactions: &[Transaction]) -> String {
+    let mut csv = String::from("type,client,tx,amount\n");
+    for transaction in transactions {
+        let amount = transaction.get_amount().map(|amount| amount.to_string());
+        csv.push_str(&format!(
+            "{},{},{},{}\n",
+            transaction.get_type(),
+            transaction.get_client_id(),
+            transaction.get_transaction_id(),
+            amount.unwrap_or_default()
+        ));
+    }
+    csv
+}
+
+/// Processes the transactions on a fresh state.
+fn process(transactions: Vec<Transaction>) -> State {
+    let mut state = State::new_standalone();
+    state.process_all(transactions.into_iter());
+    state
+}
+
+fn bench_mixed(c: &mut Criterion) {
+    let transactions = mixed_workload(MIXED_COUNT);
+    let mut group = c.benchmark_group("mixed");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(transactions.len() as u64));
+    group.bench_function("process_transaction", |b| {
+        b.iter_batched(
+            || transactions.clone(),
+            |transactions| black_box(process(transactions)),
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+fn bench_csv(c: &mut Criterion) {
+    let input = to_csv(&mixed_workload(SMALL_COUNT));
+    let mut group = c.benchmark_group("csv");
+    group.throughput(Throughput::Bytes(input.len() as u64));
+    group.bench_function("parse_and_process", |b| {
+        b.iter(|| {
+            let reader = TransactionReader::new(Cursor::new(input.as_bytes())).unwrap();
+            let mut state = State::new_standalone();
+            state.process_all(reader.filter_map(Result::ok));
+            black_box(state)
+        })
+    });
+    group.finish();
+}
+
+fn bench_disputes(c: &mut Criterion) {
+    let transactions = dispute_workload(SMALL_COUNT);
+    let mut group = c.benchmark_group("disputes");
+    group.throughput(Throughput::Elements(transactions.len() as u64));
+    group.bench_function("process_transaction", |b| {
+        b.iter_batched(
+            || transactions.clone(),
+            |transactions| black_box(process(transactions)),
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+/// Streams the transactions through the channel of a state running on another task, either one by one or in batches.
+fn stream(runtime: &Runtime, transactions: Vec<Transaction>, batched: bool) -> State {
+    runtime.block_on(async move {
+        let (sender, receiver) = mpsc::channel(bank::DEFAULT_CHANNEL_SIZE);
+        let mut state = State::new(receiver);
+        let handle = tokio::spawn(async move {
+            state.run().await;
+            state
+        });
+        if batched {
+            let mut batch = Vec::with_capacity(BATCH_SIZE);
+            for transaction in transactions {
+                batch.push(transaction);
+                if batch.len() == BATCH_SIZE {
+                    bank::send_batch(&sender, &mut batch).await.unwrap();
+                }
+            }
+            bank::send_batch(&sender, &mut batch).await.unwrap();
+        } else {
+            for transaction in transactions {
+                sender.send(transaction).await.unwrap();
+            }
+        }
+        drop(sender);
+        handle.await.unwrap()
+    })
+}
+
+fn bench_channel(c: &mut Criterion) {
+    let transactions = mixed_workload(MIXED_COUNT);
+    let runtime = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("channel");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(transactions.len() as u64));
+    for (name, batched) in [("per_row", false), ("batched", true)] {
+        group.bench_function(name, |b| {
+            b.iter_batched(
+                || transactions.clone(),
+                |transactions| black_box(stream(&runtime, transactions, batched)),
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+/// The synchronous path the binary takes for regular files against the channel path it takes for other inputs.
+fn bench_process_all_vs_channel(c: &mut Criterion) {
+    let transactions = mixed_workload(MIXED_COUNT);
+    let runtime = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("process_all_vs_channel");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(transactions.len() as u64));
+    group.bench_function("process_all", |b| {
+        b.iter_batched(
+            || transactions.clone(),
+            |transactions| black_box(process(transactions)),
+            BatchSize::LargeInput,
+        )
+    });
+    group.bench_function("channel", |b| {
+        b.iter_batched(
+            || transactions.clone(),
+            |transactions| black_box(stream(&runtime, transactions, false)),
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_mixed,
+    bench_csv,
+    bench_disputes,
+    bench_channel,
+    bench_process_all_vs_channel
+);
+criterion_main!(benches);
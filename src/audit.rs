@@ -0,0 +1,150 @@
+//! Append-only log of the balance changes made by the engine, written while the transactions are processed.
+use std::{fs::File, io::Write, path::Path, sync::Arc};
+
+use bank::{
+    AuditEntry, ClientId, Currency, Money, Transaction, TransactionError, TransactionId,
+    TransactionObserver, TransactionType,
+};
+use serde::Serialize;
+use tokio::sync::mpsc;
+use tracing::warn;
+
+use crate::{exit_with_error, progress::Progress};
+
+/// A row of the audit log: an accepted transaction and the balances of its account right after it.
+#[derive(Serialize, Debug)]
+pub struct AuditRow {
+    /// The client of the transaction.
+    pub client: ClientId,
+    /// The ID of the transaction.
+    pub tx: TransactionId,
+    /// The type of the transaction.
+    #[serde(rename = "type")]
+    pub tx_type: TransactionType,
+    /// The amount of the transaction, if it had one.
+    pub amount: Option<Money>,
+    /// The currency whose balances changed, empty for the implicit one.
+    pub currency: Option<Currency>,
+    /// The available balance after the transaction.
+    pub available: Money,
+    /// The held balance after the transaction.
+    pub held: Money,
+    /// The total balance after the transaction.
+    pub total: Money,
+}
+
+impl From<&AuditEntry> for AuditRow {
+    fn from(entry: &AuditEntry) -> Self {
+        AuditRow {
+            client: entry.client_id,
+            tx: entry.transaction_id,
+            tx_type: entry.tx_type,
+            amount: entry.amount,
+            currency: entry.currency,
+            available: entry.available,
+            held: entry.held,
+            total: entry.total,
+        }
+    }
+}
+
+/// Writes the audit log as CSV, flushing every row so that the file is a usable prefix if the run is killed.
+pub struct AuditLog<W: Write> {
+    /// The CSV writer of the log.
+    writer: csv::Writer<W>,
+}
+
+impl AuditLog<File> {
+    /// Creates the log file, replacing an existing one.
+    pub fn create(path: &Path) -> std::io::Result<Self> {
+        Ok(AuditLog::new(File::create(path)?))
+    }
+}
+
+impl<W: Write> AuditLog<W> {
+    /// Creates a log writing to the writer. The header row is written with the first entry.
+    pub fn new(writer: W) -> Self {
+        AuditLog {
+            writer: csv::Writer::from_writer(writer),
+        }
+    }
+
+    /// Appends the entry and flushes it.
+    pub fn write(&mut self, entry: &AuditEntry) -> csv::Result<()> {
+        self.writer.serialize(AuditRow::from(entry))?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// The observer of the binary: sends the rejected transactions to the rejects channel, or logs them as warnings like
+/// `State` does without an observer, and writes the accepted ones to the audit log.
+#[derive(Default)]
+pub struct RunObserver {
+    /// Where the rejected transactions are sent, for `--rejects` and `--validate`.
+    pub rejects: Option<mpsc::UnboundedSender<(Transaction, TransactionError)>>,
+    /// The log of `--audit-log`.
+    pub audit_log: Option<AuditLog<File>>,
+    /// The progress of `--progress`, which counts the rejected transactions.
+    pub progress: Option<Arc<Progress>>,
+}
+
+impl TransactionObserver for RunObserver {
+    fn on_accepted(&mut self, _transaction: &Transaction, entry: &AuditEntry) {
+        if let Some(audit_log) = &mut self.audit_log
+            && let Err(err) = audit_log.write(entry)
+        {
+            // The log must not miss an accepted transaction, so the run can't go on without it.
+            exit_with_error(format!("Failed to write the audit log: {err}"));
+        }
+    }
+
+    fn on_rejected(&mut self, transaction: &Transaction, error: &TransactionError) {
+        if let Some(progress) = &self.progress {
+            progress.count_rejected();
+        }
+        match &mut self.rejects {
+            Some(rejects) => rejects.on_rejected(transaction, error),
+            None => warn!(
+                client = transaction.get_client_id(),
+                tx = transaction.get_transaction_id(),
+                r#type = %transaction.get_type(),
+                reason = %error,
+                "Transaction rejected"
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bank::{State, TransactionReader};
+
+    use crate::audit::AuditLog;
+
+    #[test]
+    fn test_audit_log() {
+        let input = "type, client, tx, amount\n\
+                     deposit, 1, 1, 2.0\n\
+                     withdrawal, 1, 2, 5.0\n\
+                     deposit, 2, 3, 1.5\n\
+                     dispute, 1, 1,\n";
+        let mut state = State::new_standalone().with_audit();
+        state.process_all(
+            TransactionReader::new(input.as_bytes())
+                .unwrap()
+                .map(Result::unwrap),
+        );
+        let mut log = AuditLog::new(vec![]);
+        for entry in state.audit_log().unwrap() {
+            log.write(entry).unwrap();
+        }
+        assert_eq!(
+            String::from_utf8(log.writer.into_inner().unwrap()).unwrap(),
+            "client,tx,type,amount,currency,available,held,total\n\
+             1,1,deposit,2.0000,,2.0000,0.0000,2.0000\n\
+             2,3,deposit,1.5000,,1.5000,0.0000,1.5000\n\
+             1,1,dispute,,,0.0000,2.0000,2.0000\n"
+        );
+    }
+}
@@ -1,50 +1,151 @@
 //! Account management and transaction processing for a banking system.
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashMap, HashSet, VecDeque};
 
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
 use crate::bank::{
-    DECIMAL_PRECISION, Transaction, TransactionId, TransactionType,
-    types::{ClientId, Money},
+    Balances, Config, Currency, DisputePolicy, FormattedMoney, LockPolicy, Money, Transaction,
+    TransactionId, TransactionType, WithdrawalDisputePolicy,
+    snapshot::{AccountSnapshot, BalancesSnapshot, TransactionSnapshot},
+    types::ClientId,
 };
 
-fn serialize_money<S>(money: &Money, serializer: S) -> Result<S::Ok, S::Error>
-where
-    S: serde::Serializer,
-{
-    (*money as f64 / DECIMAL_PRECISION).serialize(serializer)
+/// Adds two amounts, returning an error if the result would overflow.
+fn checked_add(lhs: Money, rhs: Money) -> Result<Money, TransactionError> {
+    lhs.checked_add(rhs).ok_or(TransactionError::Overflow)
+}
+
+/// Subtracts two amounts, returning an error if the result would overflow.
+fn checked_sub(lhs: Money, rhs: Money) -> Result<Money, TransactionError> {
+    lhs.checked_sub(rhs).ok_or(TransactionError::Overflow)
+}
+
+/// The kind of a recorded transaction, only deposits and withdrawals are recorded.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum StoredKind {
+    Deposit,
+    Withdrawal,
+}
+
+impl From<StoredKind> for TransactionType {
+    fn from(kind: StoredKind) -> Self {
+        match kind {
+            StoredKind::Deposit => TransactionType::Deposit,
+            StoredKind::Withdrawal => TransactionType::Withdrawal,
+        }
+    }
+}
+
+/// A recorded deposit or withdrawal, holding only what disputes and the history need.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct StoredTx {
+    /// The amount of the transaction.
+    pub(crate) amount: Money,
+    /// Whether it was a deposit or a withdrawal.
+    pub(crate) kind: StoredKind,
+    /// The currency of the transaction, which its disputes apply to.
+    pub(crate) currency: Option<Currency>,
+    /// The position of the transaction among the ones recorded by the account.
+    pub(crate) seq: u64,
+    /// Whether its last dispute was resolved.
+    pub(crate) resolved: bool,
+}
+
+/// What happened to a recorded deposit or withdrawal, see `Account::history`.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TxStatus {
+    /// It was never disputed.
+    Recorded,
+    /// It is in dispute.
+    Disputed,
+    /// Its last dispute was resolved, it can be disputed again.
+    Resolved,
+    /// It was charged back and can't be disputed again.
+    ChargedBack,
+}
+
+/// A recorded deposit or withdrawal of an account with its current status, see `Account::history`.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TxRecord {
+    /// The ID of the transaction.
+    pub tx: TransactionId,
+    /// Either `TransactionType::Deposit` or `TransactionType::Withdrawal`.
+    #[serde(rename = "type")]
+    pub kind: TransactionType,
+    /// The amount of the transaction.
+    pub amount: Money,
+    /// The currency of the transaction, `None` for the implicit one.
+    pub currency: Option<Currency>,
+    /// What happened to the transaction since it was recorded.
+    pub status: TxStatus,
 }
 
 /// Represents a bank account for a client.
-#[derive(Serialize, Default)]
+#[derive(Default)]
 pub struct Account {
     /// The unique identifier for the client.
-    #[serde(rename = "client")]
     client_id: ClientId,
 
-    /// The available balance in the account.
-    #[serde(serialize_with = "serialize_money")]
-    available: Money,
-
-    /// The held amount in the account for disputed transactions.
-    #[serde(serialize_with = "serialize_money")]
-    held: Money,
+    /// The balances in each currency the account has used, `None` being the implicit currency of single-currency feeds.
+    /// A currency gets an entry once a transaction in it succeeds.
+    balances: HashMap<Option<Currency>, Balances>,
 
-    /// The total balance in the account, including available and held amounts.
-    #[serde(serialize_with = "serialize_money")]
-    total: Money,
-
-    /// Indicates whether the account is locked.
+    /// Indicates whether the account is locked. The lock applies to all currencies.
     locked: bool,
 
-    /// A map of transactions associated with this account.
-    #[serde(skip)]
-    transactions: HashMap<TransactionId, Transaction>,
+    /// The deposits and withdrawals of this account, kept for disputes.
+    transactions: HashMap<TransactionId, StoredTx>,
 
     /// A set of transaction IDs that are currently in dispute.
-    #[serde(skip)]
     in_dispute: HashSet<TransactionId>,
+
+    /// Disputes of transactions that weren't recorded yet, see `Config::park_unknown_disputes`.
+    pending_disputes: HashSet<TransactionId>,
+
+    /// A set of transaction IDs that were charged back and can't be disputed again.
+    /// With a finality window only the ones still within it are kept.
+    charged_back: HashSet<TransactionId>,
+
+    /// The recorded transactions that are still within `Config::finality_window`, oldest first, to drop them in that
+    /// order. Only kept when the window is set, whether one is in it is checked with `in_window`.
+    recent: VecDeque<TransactionId>,
+
+    /// The number of withdrawals rejected for insufficient funds.
+    rejected_withdrawals: u64,
+
+    /// The `StoredTx::seq` of the next recorded transaction.
+    next_seq: u64,
+
+    /// The engine configuration this account follows.
+    config: Config,
+}
+
+impl Serialize for Account {
+    /// Serializes the summary of the implicit currency with four decimal places.
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.summary(Money::DECIMAL_PLACES).serialize(serializer)
+    }
+}
+
+/// A serializable view of an account in one currency, with the balances formatted to a fixed number of decimal places.
+#[derive(Serialize, Debug)]
+pub struct AccountSummary {
+    /// The unique identifier for the client.
+    pub client: ClientId,
+    /// The currency of the balances, left out for the implicit currency.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub currency: Option<Currency>,
+    /// The available balance in the account.
+    pub available: FormattedMoney,
+    /// The held amount in the account.
+    pub held: FormattedMoney,
+    /// The total balance in the account.
+    pub total: FormattedMoney,
+    /// Indicates whether the account is locked.
+    pub locked: bool,
 }
 
 impl Account {
@@ -56,40 +157,459 @@ impl Account {
         }
     }
 
-    /// Deposits the specified amount into the account.
-    fn deposit(&mut self, amount: Money) {
-        self.available += amount;
-        self.total += amount;
+    /// Creates a new account for the given client ID using the given engine configuration.
+    pub fn with_config(client_id: ClientId, config: Config) -> Self {
+        Account {
+            client_id,
+            config,
+            ..Default::default()
+        }
     }
 
-    /// Withdraws the specified amount from the account. Returns an error if there are insufficient funds.
-    fn withdraw(&mut self, amount: Money) -> Result<(), TransactionError> {
-        if self.available >= amount {
-            self.available -= amount;
-            self.total -= amount;
-            Ok(())
-        } else {
-            Err(TransactionError::InsufficientFunds)
+    /// Creates a new account for the given client ID that starts with `available` in the implicit currency, e.g. one
+    /// migrated from another system, instead of replaying its deposits. Nothing is held, so the total is the same.
+    /// The opening balance isn't a recorded transaction and can't be disputed. Fails with `NegativeOpeningBalance` if
+    /// `available` is negative.
+    ///
+    /// ```
+    /// use bank::{Account, Money, Transaction, TransactionType};
+    ///
+    /// let mut account = Account::with_opening_balance(1, "100".parse().unwrap()).unwrap();
+    /// let withdrawal = Transaction::new(TransactionType::Withdrawal, 1, 1, "40".parse().ok());
+    /// account.process_transaction(withdrawal).unwrap();
+    /// assert_eq!(account.total().to_string(), "60.0000");
+    /// ```
+    pub fn with_opening_balance(
+        client_id: ClientId,
+        available: Money,
+    ) -> Result<Self, TransactionError> {
+        if available.is_negative() {
+            return Err(TransactionError::NegativeOpeningBalance);
+        }
+        let mut account = Account::new(client_id);
+        if available != Money::ZERO {
+            account.deposit(None, available)?;
+        }
+        Ok(account)
+    }
+
+    /// Restores an account from its snapshot, using the given engine configuration.
+    pub(crate) fn from_snapshot(snapshot: AccountSnapshot, config: Config) -> Self {
+        let client_id = snapshot.client;
+        let implicit = Balances {
+            available: snapshot.available,
+            held: snapshot.held,
+            total: snapshot.total,
+            manual_held: snapshot.manual_held,
+        };
+        let balances = snapshot
+            .currencies
+            .into_iter()
+            .map(|balances| {
+                let currency_balances = Balances {
+                    available: balances.available,
+                    held: balances.held,
+                    total: balances.total,
+                    manual_held: balances.manual_held,
+                };
+                (Some(balances.currency), currency_balances)
+            })
+            .chain((!implicit.is_zero()).then_some((None, implicit)))
+            .collect();
+        let mut account = Account {
+            client_id,
+            balances,
+            locked: snapshot.locked,
+            transactions: snapshot
+                .transactions
+                .into_iter()
+                .zip(0..)
+                .map(|(tx, seq)| {
+                    let stored = StoredTx {
+                        amount: tx.amount,
+                        kind: tx.kind,
+                        currency: tx.currency,
+                        seq,
+                        resolved: tx.resolved,
+                    };
+                    (tx.tx, stored)
+                })
+                .collect(),
+            in_dispute: snapshot.in_dispute.into_iter().collect(),
+            pending_disputes: snapshot.pending_disputes.into_iter().collect(),
+            charged_back: snapshot.charged_back.into_iter().collect(),
+            recent: VecDeque::new(),
+            rejected_withdrawals: snapshot.rejected_withdrawals,
+            next_seq: 0,
+            config: Config::default(),
+        };
+        account.next_seq = account.transactions.len() as u64;
+        account.set_config(config);
+        account
+    }
+
+    /// Captures the full state of the account, in a deterministic order.
+    pub(crate) fn snapshot(&self) -> AccountSnapshot {
+        let transactions = self
+            .recorded_in_order()
+            .into_iter()
+            .map(|(tx, stored)| TransactionSnapshot {
+                tx,
+                kind: stored.kind,
+                amount: stored.amount,
+                currency: stored.currency,
+                resolved: stored.resolved,
+            })
+            .collect::<Vec<_>>();
+        let mut currencies = self
+            .balances
+            .iter()
+            .filter_map(|(currency, balances)| {
+                Some(BalancesSnapshot {
+                    currency: (*currency)?,
+                    available: balances.available,
+                    held: balances.held,
+                    total: balances.total,
+                    manual_held: balances.manual_held,
+                })
+            })
+            .collect::<Vec<_>>();
+        currencies.sort_unstable_by_key(|balances| balances.currency);
+        let sorted = |ids: &HashSet<TransactionId>| {
+            let mut ids = ids.iter().copied().collect::<Vec<_>>();
+            ids.sort_unstable();
+            ids
+        };
+        let implicit = self.balances(None);
+        AccountSnapshot {
+            client: self.client_id,
+            available: implicit.available,
+            held: implicit.held,
+            total: implicit.total,
+            locked: self.locked,
+            manual_held: implicit.manual_held,
+            currencies,
+            transactions,
+            in_dispute: sorted(&self.in_dispute),
+            pending_disputes: sorted(&self.pending_disputes),
+            charged_back: sorted(&self.charged_back),
+            rejected_withdrawals: self.rejected_withdrawals,
+        }
+    }
+
+    /// Changes the engine configuration of the account.
+    /// With a finality window the recorded transactions fall out of it in the order they were recorded in, which for
+    /// restored ones is the order of the snapshot.
+    pub(crate) fn set_config(&mut self, config: Config) {
+        self.config = config;
+        self.recent.clear();
+        if config.finality_window.is_some() {
+            // Numbered again without gaps, so that the window covers the `window` highest sequence numbers.
+            let recorded = self.recorded_in_order();
+            for ((tx, _), seq) in recorded.iter().zip(0..) {
+                if let Some(stored) = self.transactions.get_mut(tx) {
+                    stored.seq = seq;
+                }
+            }
+            self.next_seq = recorded.len() as u64;
+            self.recent.extend(recorded.into_iter().map(|(tx, _)| tx));
+            self.finalize();
+        }
+    }
+
+    /// Whether the transaction recorded with the sequence number is still within the finality window, which holds
+    /// the `window` most recently recorded ones. Always `false` without a window.
+    fn in_window(&self, seq: u64) -> bool {
+        self.config
+            .finality_window
+            .is_some_and(|window| self.next_seq - seq <= window as u64)
+    }
+
+    /// Returns the recorded transactions in the order they were recorded in.
+    fn recorded_in_order(&self) -> Vec<(TransactionId, StoredTx)> {
+        let mut recorded = self
+            .transactions
+            .iter()
+            .map(|(tx, stored)| (*tx, *stored))
+            .collect::<Vec<_>>();
+        recorded.sort_unstable_by_key(|(_, stored)| stored.seq);
+        recorded
+    }
+
+    /// Drops the transactions that fell out of the finality window, except the ones in dispute, which are dropped
+    /// once the dispute is closed. Charged back ones are forgotten too, disputing them fails as for any final one.
+    fn finalize(&mut self) {
+        let Some(window) = self.config.finality_window else {
+            return;
+        };
+        while self.recent.len() > window {
+            if let Some(transaction_id) = self.recent.pop_front()
+                && !self.in_dispute.contains(&transaction_id)
+            {
+                self.transactions.remove(&transaction_id);
+                self.charged_back.remove(&transaction_id);
+            }
+        }
+    }
+
+    /// Returns the IDs of the recorded deposits and withdrawals, in no particular order.
+    pub(crate) fn recorded_transactions(&self) -> impl Iterator<Item = TransactionId> + '_ {
+        self.transactions.keys().copied()
+    }
+
+    /// Returns the currency whose balances the transaction changes: the one of the referenced transaction
+    /// for disputes, resolves and chargebacks, otherwise the transaction's own.
+    pub(crate) fn currency_of(&self, transaction: &Transaction) -> Option<Currency> {
+        match transaction.get_type() {
+            TransactionType::Dispute | TransactionType::Resolve | TransactionType::Chargeback => {
+                self.transactions
+                    .get(&transaction.get_transaction_id())
+                    .map_or(transaction.get_currency(), |stored| stored.currency)
+            }
+            _ => transaction.get_currency(),
+        }
+    }
+
+    /// Returns the ID of the client owning the account.
+    pub fn client_id(&self) -> ClientId {
+        self.client_id
+    }
+
+    /// Returns the balances in the currency, all zero if the account never used it.
+    pub fn balances(&self, currency: Option<Currency>) -> Balances {
+        self.balances.get(&currency).copied().unwrap_or_default()
+    }
+
+    /// Returns the currencies the account reports balances in, sorted with the implicit currency first.
+    /// The implicit currency is left out when it's all zero and other currencies are used, and an account
+    /// that has nothing in any currency reports the implicit one.
+    pub fn currencies(&self) -> Vec<Option<Currency>> {
+        let mut currencies = self
+            .balances
+            .iter()
+            .filter(|(currency, balances)| currency.is_some() || !balances.is_zero())
+            .map(|(currency, _)| *currency)
+            .collect::<Vec<_>>();
+        if currencies.is_empty() {
+            currencies.push(None);
+        }
+        currencies.sort_unstable();
+        currencies
+    }
+
+    /// Returns the funds available for withdrawal in the implicit currency.
+    pub fn available(&self) -> Money {
+        self.balances(None).available
+    }
+
+    /// Returns the funds held by open disputes in the implicit currency.
+    pub fn held(&self) -> Money {
+        self.balances(None).held
+    }
+
+    /// Returns the total funds of the account in the implicit currency.
+    pub fn total(&self) -> Money {
+        self.balances(None).total
+    }
+
+    /// Returns whether the account was locked by a chargeback.
+    pub fn is_locked(&self) -> bool {
+        self.locked
+    }
+
+    /// Returns the part of the held funds placed by manual holds in the implicit currency.
+    pub fn manual_held(&self) -> Money {
+        self.balances(None).manual_held
+    }
+
+    /// Returns the number of withdrawals rejected for insufficient funds.
+    pub fn rejected_withdrawals(&self) -> u64 {
+        self.rejected_withdrawals
+    }
+
+    /// Returns the IDs of the unknown transactions whose disputes are parked, in no particular order.
+    pub fn pending_disputes(&self) -> impl Iterator<Item = TransactionId> + '_ {
+        self.pending_disputes.iter().copied()
+    }
+
+    /// Returns the IDs of the transactions that are currently in dispute, in no particular order.
+    pub fn disputed_transactions(&self) -> impl Iterator<Item = TransactionId> + '_ {
+        self.in_dispute.iter().copied()
+    }
+
+    /// Returns the recorded deposits and withdrawals in the order they were recorded in, with their current status.
+    /// With `Config::finality_window` the transactions that were forgotten are left out, charged back ones included.
+    ///
+    /// ```
+    /// use bank::{Account, Transaction, TransactionType, TxStatus};
+    ///
+    /// let mut account = Account::new(1);
+    /// for (tx_type, tx, amount) in [
+    ///     (TransactionType::Deposit, 2, "5".parse().ok()),
+    ///     (TransactionType::Deposit, 1, "3".parse().ok()),
+    ///     (TransactionType::Dispute, 1, None),
+    /// ] {
+    ///     account.process_transaction(Transaction::new(tx_type, 1, tx, amount)).unwrap();
+    /// }
+    /// let history = account.history();
+    /// assert_eq!((history[0].tx, history[0].status), (2, TxStatus::Recorded));
+    /// assert_eq!((history[1].tx, history[1].status), (1, TxStatus::Disputed));
+    /// ```
+    pub fn history(&self) -> Vec<TxRecord> {
+        self.recorded_in_order()
+            .into_iter()
+            .map(|(tx, stored)| TxRecord {
+                tx,
+                kind: stored.kind.into(),
+                amount: stored.amount,
+                currency: stored.currency,
+                status: if self.charged_back.contains(&tx) {
+                    TxStatus::ChargedBack
+                } else if self.in_dispute.contains(&tx) {
+                    TxStatus::Disputed
+                } else if stored.resolved {
+                    TxStatus::Resolved
+                } else {
+                    TxStatus::Recorded
+                },
+            })
+            .collect()
+    }
+
+    /// Checks the consistency of the balances in every currency: `total == available + held` and `held >= 0`.
+    /// A failure means there is a bug in the engine, the processed transactions can't cause it.
+    pub fn check_invariants(&self) -> Result<(), String> {
+        let mut currencies = self.balances.keys().copied().collect::<Vec<_>>();
+        currencies.sort_unstable();
+        for currency in currencies {
+            self.balances[&currency]
+                .check_invariants()
+                .map_err(|err| match currency {
+                    Some(currency) => format!("{currency}: {err}"),
+                    None => err,
+                })?;
+        }
+        Ok(())
+    }
+
+    /// Returns a summary of the implicit currency with the balances formatted to `precision` decimal places.
+    pub fn summary(&self, precision: usize) -> AccountSummary {
+        self.currency_summary(None, precision)
+    }
+
+    /// Returns a summary for each of the `currencies`, in the same order.
+    pub fn summaries(&self, precision: usize) -> Vec<AccountSummary> {
+        self.currencies()
+            .into_iter()
+            .map(|currency| self.currency_summary(currency, precision))
+            .collect()
+    }
+
+    /// Returns a summary of the balances in the currency.
+    fn currency_summary(&self, currency: Option<Currency>, precision: usize) -> AccountSummary {
+        let balances = self.balances(currency);
+        AccountSummary {
+            client: self.client_id,
+            currency,
+            available: FormattedMoney::new(balances.available, precision),
+            held: FormattedMoney::new(balances.held, precision),
+            total: FormattedMoney::new(balances.total, precision),
+            locked: self.locked,
         }
     }
 
-    /// Marks a transaction as disputed. If the transaction is a deposit, it moves the amount from available to held. If it's a withdrawal, it adds the amount to held.
-    /// Returns an error if the transaction is already in dispute or if the transaction doesn't exists.
+    /// Applies a change to the balances in the currency. They are only updated if the change succeeds,
+    /// so a failed transaction neither changes them nor adds the currency to the account.
+    fn update(
+        &mut self,
+        currency: Option<Currency>,
+        change: impl FnOnce(&mut Balances) -> Result<(), TransactionError>,
+    ) -> Result<(), TransactionError> {
+        let mut balances = self.balances(currency);
+        change(&mut balances)?;
+        self.balances.insert(currency, balances);
+        Ok(())
+    }
+
+    /// Deposits the specified amount into the account. Returns an error if the balance would overflow.
+    fn deposit(
+        &mut self,
+        currency: Option<Currency>,
+        amount: Money,
+    ) -> Result<(), TransactionError> {
+        self.update(currency, |balances| {
+            balances.available = checked_add(balances.available, amount)?;
+            balances.total = checked_add(balances.total, amount)?;
+            Ok(())
+        })
+    }
+
+    /// Withdraws the specified amount from the account. Returns an error if there are insufficient funds or if the balance would overflow.
+    /// Available may go below zero by at most `Config::overdraft_limit`.
+    fn withdraw(
+        &mut self,
+        currency: Option<Currency>,
+        amount: Money,
+    ) -> Result<(), TransactionError> {
+        let overdraft_limit = self.config.overdraft_limit;
+        self.update(currency, |balances| {
+            let available = balances.available;
+            balances.available = checked_sub(available, amount)?;
+            if balances.available < -overdraft_limit {
+                return Err(TransactionError::InsufficientFunds {
+                    requested: amount,
+                    available,
+                });
+            }
+            balances.total = checked_sub(balances.total, amount)?;
+            Ok(())
+        })
+    }
+
+    /// Marks a transaction as disputed. If the transaction is a deposit, it moves the amount from available to held. If it's a withdrawal, it adds the amount to held and total,
+    /// so that `total == available + held` still holds, or follows the configured `WithdrawalDisputePolicy`.
+    /// Returns an error if the transaction is already in dispute, was charged back or if the transaction doesn't exists.
+    /// With `DisputePolicy::RejectIfInsufficientAvailable` a deposit dispute is also rejected when it would push available below zero.
     fn dispute(&mut self, transaction_id: TransactionId) -> Result<(), TransactionError> {
+        if self.charged_back.contains(&transaction_id) {
+            return Err(TransactionError::AlreadyChargedBack);
+        }
         if self.in_dispute.contains(&transaction_id) {
             return Err(TransactionError::AlreadyInDispute);
         }
-        if let Some(tx) = self.transactions.get(&transaction_id) {
-            match tx.get_type() {
-                TransactionType::Deposit => {
-                    self.available -= tx.get_amount().unwrap_or(0);
-                    self.held += tx.get_amount().unwrap_or(0);
-                }
-                TransactionType::Withdrawal => {
-                    self.held += tx.get_amount().unwrap_or(0);
+        if let Some(&StoredTx {
+            amount,
+            kind,
+            currency,
+            ..
+        }) = self.transactions.get(&transaction_id)
+        {
+            let dispute_policy = self.config.dispute_policy;
+            let withdrawal_policy = self.config.withdrawal_dispute_policy;
+            self.update(currency, |balances| {
+                match kind {
+                    StoredKind::Deposit => {
+                        if dispute_policy == DisputePolicy::RejectIfInsufficientAvailable
+                            && balances.available < amount
+                        {
+                            return Err(TransactionError::InsufficientAvailableForDispute);
+                        }
+                        balances.available = checked_sub(balances.available, amount)?;
+                        balances.held = checked_add(balances.held, amount)?;
+                    }
+                    StoredKind::Withdrawal => match withdrawal_policy {
+                        WithdrawalDisputePolicy::HoldFromTotal => {
+                            balances.held = checked_add(balances.held, amount)?;
+                            balances.total = checked_add(balances.total, amount)?;
+                        }
+                        WithdrawalDisputePolicy::CreditOnChargeback => {}
+                        WithdrawalDisputePolicy::Reject => {
+                            return Err(TransactionError::WithdrawalDisputeNotAllowed);
+                        }
+                    },
                 }
-                _ => return Err(TransactionError::InvalidTransaction),
-            }
+                Ok(())
+            })?;
             self.in_dispute.insert(transaction_id);
             Ok(())
         } else {
@@ -97,58 +617,249 @@ impl Account {
         }
     }
 
-    /// Resolves a disputed transaction, moving the amount back to available if it was a deposit, or reducing held if it was a withdrawal.
-    /// Returns an error if the transaction is not in dispute or if the transaction doesn't exist.
+    /// Resolves a disputed transaction, moving the amount back to available if it was a deposit, or reducing held and total if it was a withdrawal.
+    /// With `WithdrawalDisputePolicy::CreditOnChargeback` resolving a withdrawal doesn't change the balances.
+    /// Returns an error if the transaction doesn't exist, was charged back or is not in dispute, checked in that order.
     fn resolve(&mut self, transaction_id: TransactionId) -> Result<(), TransactionError> {
+        let Some(&StoredTx {
+            amount,
+            kind,
+            currency,
+            seq,
+            ..
+        }) = self.transactions.get(&transaction_id)
+        else {
+            return Err(self.unknown_transaction(transaction_id));
+        };
+        if self.charged_back.contains(&transaction_id) {
+            return Err(TransactionError::AlreadyChargedBack);
+        }
         if !self.in_dispute.contains(&transaction_id) {
             return Err(TransactionError::NotInDispute);
         }
-        if let Some(tx) = self.transactions.get(&transaction_id) {
-            match tx.get_type() {
-                TransactionType::Deposit => {
-                    self.available += tx.get_amount().unwrap_or(0);
-                    self.held -= tx.get_amount().unwrap_or(0);
+        let credit_on_chargeback = self.credit_on_chargeback();
+        self.update(currency, |balances| {
+            match kind {
+                StoredKind::Deposit => {
+                    balances.available = checked_add(balances.available, amount)?;
+                    balances.held = checked_sub(balances.held, amount)?;
                 }
-                TransactionType::Withdrawal => {
-                    self.held -= tx.get_amount().unwrap_or(0);
+                StoredKind::Withdrawal if credit_on_chargeback => {}
+                StoredKind::Withdrawal => {
+                    balances.held = checked_sub(balances.held, amount)?;
+                    balances.total = checked_sub(balances.total, amount)?;
                 }
-                _ => return Err(TransactionError::InvalidTransaction),
             }
-            self.in_dispute.remove(&transaction_id);
             Ok(())
-        } else {
-            Err(TransactionError::TransactionDoesNotExist)
+        })?;
+        self.in_dispute.remove(&transaction_id);
+        if let Some(stored) = self.transactions.get_mut(&transaction_id) {
+            stored.resolved = true;
         }
+        if self.config.finality_window.is_some() && !self.in_window(seq) {
+            // It was kept past the finality window only for the dispute.
+            self.transactions.remove(&transaction_id);
+        }
+        Ok(())
     }
 
-    /// Charges back a disputed transaction, locking the account and moving the held amount to total if it was a deposit, or returning the held amount to available if it was a withdrawal.
-    /// Returns an error if the transaction is not in dispute or if the transaction doesn't exist.
+    /// Charges back a disputed transaction, locking the account and removing the held amount from total if it was a deposit, or returning the held amount to available if it was a withdrawal.
+    /// Returns an error if the transaction doesn't exist, was already charged back or is not in dispute, checked in that order.
+    ///
+    /// A withdrawal chargeback reverses the withdrawal: the disputed amount was held as a pending credit, which never counted
+    /// as available and so couldn't be withdrawn again, and only now becomes available. Every transaction can be charged back
+    /// at most once, so the account ends with exactly the balance it would have without the withdrawal.
+    /// With `WithdrawalDisputePolicy::CreditOnChargeback` nothing was held, so the amount is credited to available and total.
     fn chargeback(&mut self, transaction_id: TransactionId) -> Result<(), TransactionError> {
+        let Some(&StoredTx {
+            amount,
+            kind,
+            currency,
+            seq,
+            ..
+        }) = self.transactions.get(&transaction_id)
+        else {
+            return Err(self.unknown_transaction(transaction_id));
+        };
+        if self.charged_back.contains(&transaction_id) {
+            return Err(TransactionError::AlreadyChargedBack);
+        }
         if !self.in_dispute.contains(&transaction_id) {
             return Err(TransactionError::NotInDispute);
         }
-        if let Some(tx) = self.transactions.get(&transaction_id) {
-            match tx.get_type() {
-                TransactionType::Deposit => {
-                    self.held -= tx.get_amount().unwrap_or_default();
-                    self.total -= tx.get_amount().unwrap_or_default();
+        let credit_on_chargeback = self.credit_on_chargeback();
+        self.update(currency, |balances| {
+            match kind {
+                StoredKind::Deposit => {
+                    balances.held = checked_sub(balances.held, amount)?;
+                    balances.total = checked_sub(balances.total, amount)?;
+                }
+                StoredKind::Withdrawal if credit_on_chargeback => {
+                    balances.available = checked_add(balances.available, amount)?;
+                    balances.total = checked_add(balances.total, amount)?;
                 }
-                TransactionType::Withdrawal => {
-                    self.available += tx.get_amount().unwrap_or_default();
-                    self.held -= tx.get_amount().unwrap_or_default();
+                StoredKind::Withdrawal => {
+                    balances.available = checked_add(balances.available, amount)?;
+                    balances.held = checked_sub(balances.held, amount)?;
                 }
-                _ => return Err(TransactionError::InvalidTransaction),
             }
-            self.locked = true;
-            self.in_dispute.remove(&transaction_id);
             Ok(())
+        })?;
+        self.locked = true;
+        self.in_dispute.remove(&transaction_id);
+        if self.config.finality_window.is_none() {
+            self.charged_back.insert(transaction_id);
         } else {
-            Err(TransactionError::TransactionDoesNotExist)
+            // A charged back transaction can't be disputed again, only its ID needs to be remembered, and only until
+            // it falls out of the window. One kept past the window for the dispute is forgotten right away.
+            self.transactions.remove(&transaction_id);
+            if self.in_window(seq) {
+                self.charged_back.insert(transaction_id);
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether disputed withdrawals are credited only on a chargeback instead of being held while in dispute.
+    fn credit_on_chargeback(&self) -> bool {
+        self.config.withdrawal_dispute_policy == WithdrawalDisputePolicy::CreditOnChargeback
+    }
+
+    /// Gets the error for a resolve or chargeback of a transaction that isn't recorded. With a finality window
+    /// a charged back transaction is no longer recorded, but it's still reported as charged back.
+    fn unknown_transaction(&self, transaction_id: TransactionId) -> TransactionError {
+        if self.charged_back.contains(&transaction_id) {
+            TransactionError::AlreadyChargedBack
+        } else {
+            TransactionError::TransactionDoesNotExist
+        }
+    }
+
+    /// Records a deposit or withdrawal, applying a dispute that was parked for it.
+    /// With a finality window, the oldest transaction that falls out of it is dropped.
+    fn record(
+        &mut self,
+        transaction_id: TransactionId,
+        kind: StoredKind,
+        amount: Money,
+        currency: Option<Currency>,
+    ) {
+        // The amount was validated before it was applied, disputes rely on it to reverse the transaction.
+        debug_assert!(!amount.is_negative(), "Recorded a negative amount");
+        let stored = StoredTx {
+            amount,
+            kind,
+            currency,
+            seq: self.next_seq,
+            resolved: false,
+        };
+        self.next_seq += 1;
+        self.transactions.insert(transaction_id, stored);
+        if self.pending_disputes.remove(&transaction_id) {
+            // The transaction itself was applied, a dispute that can't be applied anymore is dropped.
+            let _ = self.dispute(transaction_id);
+        }
+        if self.config.finality_window.is_some() {
+            self.recent.push_back(transaction_id);
+            self.finalize();
+        }
+    }
+
+    /// Places a manual hold, moving the amount from available to held. Returns an error if there are not enough available funds.
+    fn hold(&mut self, currency: Option<Currency>, amount: Money) -> Result<(), TransactionError> {
+        self.update(currency, |balances| {
+            if balances.available < amount {
+                return Err(TransactionError::InsufficientFunds {
+                    requested: amount,
+                    available: balances.available,
+                });
+            }
+            balances.held = checked_add(balances.held, amount)?;
+            balances.manual_held = checked_add(balances.manual_held, amount)?;
+            balances.available -= amount;
+            Ok(())
+        })
+    }
+
+    /// Releases a manual hold, moving the amount from held back to available.
+    /// Returns an error if the amount is more than what is held by manual holds.
+    fn release(
+        &mut self,
+        currency: Option<Currency>,
+        amount: Money,
+    ) -> Result<(), TransactionError> {
+        self.update(currency, |balances| {
+            if amount > balances.manual_held {
+                return Err(TransactionError::ReleaseExceedsHold);
+            }
+            balances.available = checked_add(balances.available, amount)?;
+            balances.held -= amount;
+            balances.manual_held -= amount;
+            Ok(())
+        })
+    }
+
+    /// Locks the account like a chargeback does, without touching the balances. Returns an error if it's already locked.
+    fn freeze(&mut self) -> Result<(), TransactionError> {
+        if self.locked {
+            return Err(TransactionError::AlreadyLocked);
+        }
+        self.locked = true;
+        Ok(())
+    }
+
+    /// Clears the lock set by a chargeback or a freeze, without touching the balances. Returns an error if the account
+    /// isn't locked.
+    fn unlock(&mut self) -> Result<(), TransactionError> {
+        if !self.locked {
+            return Err(TransactionError::NotLocked);
+        }
+        self.locked = false;
+        Ok(())
+    }
+
+    /// Checks that the amount carried by a dispute, resolve or chargeback matches the referenced transaction.
+    /// Only done when `Config::verify_dispute_amounts` is set and the row carries an amount.
+    fn verify_dispute_amount(&self, transaction: &Transaction) -> Result<(), TransactionError> {
+        if self.config.verify_dispute_amounts
+            && let Some(amount) = transaction.get_amount()
+            && let Some(tx) = self.transactions.get(&transaction.get_transaction_id())
+            && tx.amount != amount
+        {
+            return Err(TransactionError::AmountMismatch);
+        }
+        Ok(())
+    }
+
+    /// Gets the amount moved by a deposit, withdrawal, hold or release, which must have one.
+    /// Zero is rejected unless `Config::allow_zero_amounts` is set, negative amounts are rejected before.
+    fn required_amount(&self, transaction: &Transaction) -> Result<Money, TransactionError> {
+        let transaction_id = transaction.get_transaction_id();
+        match transaction.get_amount() {
+            None => Err(TransactionError::MissingAmount(transaction_id)),
+            Some(amount) if amount.is_zero() && !self.config.allow_zero_amounts => {
+                Err(TransactionError::ZeroAmount(transaction_id))
+            }
+            Some(amount) => Ok(amount),
+        }
+    }
+
+    /// Gets the amount of a deposit or withdrawal like `required_amount`, also checking it against `Config::max_amount`.
+    fn movement_amount(&self, transaction: &Transaction) -> Result<Money, TransactionError> {
+        let amount = self.required_amount(transaction)?;
+        match self.config.max_amount {
+            Some(max_amount) if amount > max_amount => Err(TransactionError::AmountTooLarge(
+                transaction.get_transaction_id(),
+            )),
+            _ => Ok(amount),
         }
     }
 
     /// Processes a transaction based on its type.
-    /// Returns an error if the account is locked or if the transaction is invalid.
+    /// Returns an error if the account is locked or if the transaction is invalid (e.g. a deposit or withdrawal without an amount).
+    /// A locked account still accepts disputes, resolves, chargebacks, holds and releases, unless `LockPolicy::BlockAll`
+    /// is configured.
+    /// An unlock, freeze or unfreeze is always accepted on a locked account.
     pub fn process_transaction(
         &mut self,
         transaction: Transaction,
@@ -157,50 +868,114 @@ impl Account {
             return Err(TransactionError::NotForThisAccount);
         }
 
-        if self.locked {
+        if self.locked
+            && !matches!(
+                transaction.get_type(),
+                TransactionType::Unlock | TransactionType::Freeze | TransactionType::Unfreeze
+            )
+            && (self.config.lock_policy == LockPolicy::BlockAll
+                || matches!(
+                    transaction.get_type(),
+                    TransactionType::Deposit | TransactionType::Withdrawal
+                ))
+        {
             return Err(TransactionError::AccountLocked);
         }
 
+        if transaction.get_amount().is_some_and(Money::is_negative) {
+            return Err(TransactionError::NegativeAmount(
+                transaction.get_transaction_id(),
+            ));
+        }
+
+        if matches!(
+            transaction.get_type(),
+            TransactionType::Deposit | TransactionType::Withdrawal
+        ) && self
+            .transactions
+            .contains_key(&transaction.get_transaction_id())
+        {
+            return Err(TransactionError::DuplicateTransactionId);
+        }
+
         match transaction.get_type() {
             TransactionType::Deposit => {
-                let amount = transaction
-                    .get_amount()
-                    .ok_or(TransactionError::InvalidTransaction)?;
-                self.deposit(amount);
-                self.transactions
-                    .insert(transaction.get_transaction_id(), transaction);
+                let amount = self.movement_amount(&transaction)?;
+                let currency = transaction.get_currency();
+                self.deposit(currency, amount)?;
+                self.record(
+                    transaction.get_transaction_id(),
+                    StoredKind::Deposit,
+                    amount,
+                    currency,
+                );
             }
             TransactionType::Withdrawal => {
-                let amount = transaction
-                    .get_amount()
-                    .ok_or(TransactionError::InvalidTransaction)?;
-                self.withdraw(amount)?;
-                self.transactions
-                    .insert(transaction.get_transaction_id(), transaction);
+                let amount = self.movement_amount(&transaction)?;
+                let currency = transaction.get_currency();
+                self.withdraw(currency, amount).inspect_err(|err| {
+                    if matches!(err, TransactionError::InsufficientFunds { .. }) {
+                        self.rejected_withdrawals += 1;
+                    }
+                })?;
+                self.record(
+                    transaction.get_transaction_id(),
+                    StoredKind::Withdrawal,
+                    amount,
+                    currency,
+                );
             }
             TransactionType::Dispute => {
-                self.dispute(transaction.get_transaction_id())?;
+                self.verify_dispute_amount(&transaction)?;
+                let transaction_id = transaction.get_transaction_id();
+                match self.dispute(transaction_id) {
+                    Err(TransactionError::TransactionDoesNotExist)
+                        if self.config.park_unknown_disputes =>
+                    {
+                        if !self.pending_disputes.insert(transaction_id) {
+                            return Err(TransactionError::AlreadyInDispute);
+                        }
+                    }
+                    result => result?,
+                }
             }
             TransactionType::Resolve => {
+                self.verify_dispute_amount(&transaction)?;
                 self.resolve(transaction.get_transaction_id())?;
             }
             TransactionType::Chargeback => {
+                self.verify_dispute_amount(&transaction)?;
                 self.chargeback(transaction.get_transaction_id())?;
             }
+            TransactionType::Unlock | TransactionType::Unfreeze => self.unlock()?,
+            TransactionType::Freeze => self.freeze()?,
+            TransactionType::Hold | TransactionType::Release => {
+                let amount = self.required_amount(&transaction)?;
+                if *transaction.get_type() == TransactionType::Hold {
+                    self.hold(transaction.get_currency(), amount)?;
+                } else {
+                    self.release(transaction.get_currency(), amount)?;
+                }
+            }
         }
         Ok(())
     }
 }
 
 /// Errors that can occur during transaction processing.
-#[derive(Error, Debug)]
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
 pub enum TransactionError {
-    #[error("Insufficient funds for transaction")]
-    InsufficientFunds,
+    #[error(
+        "Insufficient funds for transaction: requested {requested} but only {available} available"
+    )]
+    InsufficientFunds {
+        /// The amount the transaction needed.
+        requested: Money,
+        /// The available balance of the account in the currency of the transaction, before the transaction.
+        available: Money,
+    },
     #[error("Account is locked")]
     AccountLocked,
-    #[error("Invalid transaction")]
-    InvalidTransaction,
     #[error("Transaction is already in dispute")]
     AlreadyInDispute,
     #[error("Transaction not in dispute")]
@@ -209,11 +984,91 @@ pub enum TransactionError {
     NotForThisAccount,
     #[error("Transaction does not exist")]
     TransactionDoesNotExist,
+    #[error("Transaction ID is already used by another transaction")]
+    DuplicateTransactionId,
+    #[error("Transaction would overflow the account balance")]
+    Overflow,
+    #[error("Transaction {0} has a negative amount")]
+    NegativeAmount(TransactionId),
+    #[error("Transaction {0} has a zero amount")]
+    ZeroAmount(TransactionId),
+    #[error("Transaction {0} has no amount")]
+    MissingAmount(TransactionId),
+    #[error("Transaction {0} exceeds the maximum amount")]
+    AmountTooLarge(TransactionId),
+    #[error("Insufficient available funds to dispute the transaction")]
+    InsufficientAvailableForDispute,
+    #[error("Disputes of withdrawals are not accepted")]
+    WithdrawalDisputeNotAllowed,
+    #[error("Disputed transaction belongs to a different client")]
+    DisputeClientMismatch,
+    #[error("Amount does not match the disputed transaction")]
+    AmountMismatch,
+    #[error("Transaction was already charged back")]
+    AlreadyChargedBack,
+    #[error("Account is not locked")]
+    NotLocked,
+    #[error("Account is already locked")]
+    AlreadyLocked,
+    #[error("Opening balance can't be negative")]
+    NegativeOpeningBalance,
+    #[error("Release is more than the manually held amount")]
+    ReleaseExceedsHold,
+}
+
+impl TransactionError {
+    /// Gets the name of the variant, e.g. `InsufficientFunds`, to group errors by.
+    pub fn name(&self) -> &'static str {
+        match self {
+            TransactionError::InsufficientFunds { .. } => "InsufficientFunds",
+            TransactionError::AccountLocked => "AccountLocked",
+            TransactionError::AlreadyInDispute => "AlreadyInDispute",
+            TransactionError::NotInDispute => "NotInDispute",
+            TransactionError::NotForThisAccount => "NotForThisAccount",
+            TransactionError::TransactionDoesNotExist => "TransactionDoesNotExist",
+            TransactionError::DuplicateTransactionId => "DuplicateTransactionId",
+            TransactionError::Overflow => "Overflow",
+            TransactionError::NegativeAmount(_) => "NegativeAmount",
+            TransactionError::ZeroAmount(_) => "ZeroAmount",
+            TransactionError::MissingAmount(_) => "MissingAmount",
+            TransactionError::AmountTooLarge(_) => "AmountTooLarge",
+            TransactionError::InsufficientAvailableForDispute => "InsufficientAvailableForDispute",
+            TransactionError::WithdrawalDisputeNotAllowed => "WithdrawalDisputeNotAllowed",
+            TransactionError::DisputeClientMismatch => "DisputeClientMismatch",
+            TransactionError::AmountMismatch => "AmountMismatch",
+            TransactionError::AlreadyChargedBack => "AlreadyChargedBack",
+            TransactionError::NotLocked => "NotLocked",
+            TransactionError::AlreadyLocked => "AlreadyLocked",
+            TransactionError::NegativeOpeningBalance => "NegativeOpeningBalance",
+            TransactionError::ReleaseExceedsHold => "ReleaseExceedsHold",
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::bank::{Account, TransactionError, TransactionType, transaction::Transaction};
+    use csv::{ReaderBuilder, Trim};
+
+    use crate::bank::{
+        Account, Balances, Config, Currency, DisputePolicy, LockPolicy, Money, StoredKind,
+        TransactionError, TransactionReader, TransactionType, TxStatus, WithdrawalDisputePolicy,
+        transaction::Transaction,
+    };
+
+    /// Gets the balances of the implicit currency to tamper with them.
+    fn implicit(account: &mut Account) -> &mut Balances {
+        account.balances.entry(None).or_default()
+    }
+
+    /// Replays the given CSV input against the account, returning the result of each transaction.
+    fn replay_csv(account: &mut Account, input: &str) -> Vec<Result<(), TransactionError>> {
+        ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(input.as_bytes())
+            .deserialize()
+            .map(|transaction| account.process_transaction(transaction.unwrap()))
+            .collect()
+    }
 
     #[test]
     fn test_wrong_account() {
@@ -222,7 +1077,7 @@ mod tests {
             TransactionType::Deposit,
             2, // Different client ID
             1,
-            Some(1000),
+            Some(Money::from_raw(1000)),
         );
         assert!(matches!(
             account.process_transaction(transaction),
@@ -233,30 +1088,67 @@ mod tests {
     #[test]
     fn test_deposit() {
         let mut account = Account::new(1);
-        let transaction = Transaction::new(TransactionType::Deposit, 1, 2, Some(1000));
+        let transaction =
+            Transaction::new(TransactionType::Deposit, 1, 2, Some(Money::from_raw(1000)));
         assert!(account.process_transaction(transaction).is_ok());
-        assert_eq!(account.available, 1000);
-        assert_eq!(account.total, 1000);
+        assert_eq!(account.available(), Money::from_raw(1000));
+        assert_eq!(account.total(), Money::from_raw(1000));
     }
 
     #[test]
     fn test_withdrawal() {
         let mut account = Account::new(1);
-        account.deposit(2000);
-        let transaction = Transaction::new(TransactionType::Withdrawal, 1, 2, Some(1000));
+        account.deposit(None, Money::from_raw(2000)).unwrap();
+        let transaction = Transaction::new(
+            TransactionType::Withdrawal,
+            1,
+            2,
+            Some(Money::from_raw(1000)),
+        );
         assert!(account.process_transaction(transaction).is_ok());
-        assert_eq!(account.available, 1000);
-        assert_eq!(account.total, 1000);
+        assert_eq!(account.available(), Money::from_raw(1000));
+        assert_eq!(account.total(), Money::from_raw(1000));
     }
 
     #[test]
     fn test_withdrawal_insufficient_funds() {
         let mut account = Account::new(1);
-        let transaction = Transaction::new(TransactionType::Withdrawal, 1, 2, Some(1000));
+        let transaction = Transaction::new(
+            TransactionType::Withdrawal,
+            1,
+            2,
+            Some(Money::from_raw(1000)),
+        );
         assert!(matches!(
             account.process_transaction(transaction),
-            Err(TransactionError::InsufficientFunds)
+            Err(TransactionError::InsufficientFunds { .. })
+        ));
+    }
+
+    #[test]
+    fn test_rejected_withdrawals_count() {
+        let mut account = Account::new(1);
+        let results = replay_csv(
+            &mut account,
+            "type, client, tx, amount\n\
+             deposit, 1, 1, 1.0\n\
+             withdrawal, 1, 2, 1.5\n\
+             withdrawal, 1, 3, 0.5\n\
+             withdrawal, 1, 4, 0.6\n\
+             withdrawal, 1, 5, 0.0\n",
+        );
+        assert!(matches!(
+            results[..],
+            [
+                Ok(()),
+                Err(TransactionError::InsufficientFunds { .. }),
+                Ok(()),
+                Err(TransactionError::InsufficientFunds { .. }),
+                Err(TransactionError::ZeroAmount(5)),
+            ]
         ));
+        assert_eq!(account.rejected_withdrawals(), 2);
+        assert_eq!(account.available(), Money::from_raw(5000));
     }
 
     #[test]
@@ -272,18 +1164,20 @@ mod tests {
     #[test]
     fn test_dispute() {
         let mut account = Account::new(1);
-        let transaction = Transaction::new(TransactionType::Deposit, 1, 2, Some(1000));
+        let transaction =
+            Transaction::new(TransactionType::Deposit, 1, 2, Some(Money::from_raw(1000)));
         assert!(account.process_transaction(transaction).is_ok());
         let dispute_tx = Transaction::new(TransactionType::Dispute, 1, 2, None);
         assert!(account.process_transaction(dispute_tx).is_ok());
-        assert_eq!(account.available, 0);
-        assert_eq!(account.held, 1000);
+        assert_eq!(account.available(), Money::ZERO);
+        assert_eq!(account.held(), Money::from_raw(1000));
     }
 
     #[test]
     fn test_double_dispute() {
         let mut account = Account::new(1);
-        let transaction = Transaction::new(TransactionType::Deposit, 1, 2, Some(1000));
+        let transaction =
+            Transaction::new(TransactionType::Deposit, 1, 2, Some(Money::from_raw(1000)));
         assert!(account.process_transaction(transaction).is_ok());
         let dispute_tx = Transaction::new(TransactionType::Dispute, 1, 2, None);
         assert!(account.process_transaction(dispute_tx.clone()).is_ok());
@@ -296,42 +1190,1489 @@ mod tests {
     #[test]
     fn test_resolve() {
         let mut account = Account::new(1);
-        let transaction = Transaction::new(TransactionType::Deposit, 1, 2, Some(1000));
+        let transaction =
+            Transaction::new(TransactionType::Deposit, 1, 2, Some(Money::from_raw(1000)));
         assert!(account.process_transaction(transaction).is_ok());
         let dispute_tx = Transaction::new(TransactionType::Dispute, 1, 2, None);
         assert!(account.process_transaction(dispute_tx).is_ok());
         let resolve_tx = Transaction::new(TransactionType::Resolve, 1, 2, None);
         assert!(account.process_transaction(resolve_tx).is_ok());
-        assert_eq!(account.available, 1000);
-        assert_eq!(account.held, 0);
+        assert_eq!(account.available(), Money::from_raw(1000));
+        assert_eq!(account.held(), Money::ZERO);
+    }
+
+    #[test]
+    fn test_resolve_undisputed_and_unknown() {
+        let mut account = Account::new(1);
+        let transaction =
+            Transaction::new(TransactionType::Deposit, 1, 2, Some(Money::from_raw(1000)));
+        assert!(account.process_transaction(transaction).is_ok());
+        for tx_type in [TransactionType::Resolve, TransactionType::Chargeback] {
+            assert!(matches!(
+                account.process_transaction(Transaction::new(tx_type, 1, 2, None)),
+                Err(TransactionError::NotInDispute)
+            ));
+            assert!(matches!(
+                account.process_transaction(Transaction::new(tx_type, 1, 3, None)),
+                Err(TransactionError::TransactionDoesNotExist)
+            ));
+        }
+        assert_eq!(account.available(), Money::from_raw(1000));
+        assert_eq!(account.held(), Money::ZERO);
+        assert!(!account.is_locked());
     }
 
     #[test]
     fn test_deposit_chargeback() {
         let mut account = Account::new(1);
-        let transaction = Transaction::new(TransactionType::Deposit, 1, 2, Some(1000));
+        let transaction =
+            Transaction::new(TransactionType::Deposit, 1, 2, Some(Money::from_raw(1000)));
         assert!(account.process_transaction(transaction).is_ok());
         let dispute_tx = Transaction::new(TransactionType::Dispute, 1, 2, None);
         assert!(account.process_transaction(dispute_tx).is_ok());
         let chargeback_tx = Transaction::new(TransactionType::Chargeback, 1, 2, None);
         assert!(account.process_transaction(chargeback_tx).is_ok());
-        assert_eq!(account.available, 0);
-        assert_eq!(account.held, 0);
-        assert!(account.locked);
+        assert_eq!(account.available(), Money::ZERO);
+        assert_eq!(account.held(), Money::ZERO);
+        assert!(account.is_locked());
+    }
+
+    #[test]
+    fn test_withdrawal_dispute_invariant() {
+        let mut account = Account::new(1);
+        account.deposit(None, Money::from_raw(2000)).unwrap();
+        let transaction = Transaction::new(
+            TransactionType::Withdrawal,
+            1,
+            2,
+            Some(Money::from_raw(1000)),
+        );
+        assert!(account.process_transaction(transaction).is_ok());
+        let dispute_tx = Transaction::new(TransactionType::Dispute, 1, 2, None);
+        assert!(account.process_transaction(dispute_tx).is_ok());
+        assert_eq!(account.available(), Money::from_raw(1000));
+        assert_eq!(account.held(), Money::from_raw(1000));
+        assert_eq!(account.total(), account.available() + account.held());
+        let resolve_tx = Transaction::new(TransactionType::Resolve, 1, 2, None);
+        assert!(account.process_transaction(resolve_tx).is_ok());
+        assert_eq!(account.held(), Money::ZERO);
+        assert_eq!(account.total(), Money::from_raw(1000));
+        assert_eq!(account.total(), account.available() + account.held());
     }
 
     #[test]
     fn test_withdraw_chargeback() {
         let mut account = Account::new(1);
-        account.deposit(2000);
-        let transaction = Transaction::new(TransactionType::Withdrawal, 1, 2, Some(1000));
+        account.deposit(None, Money::from_raw(2000)).unwrap();
+        let transaction = Transaction::new(
+            TransactionType::Withdrawal,
+            1,
+            2,
+            Some(Money::from_raw(1000)),
+        );
         assert!(account.process_transaction(transaction).is_ok());
         let dispute_tx = Transaction::new(TransactionType::Dispute, 1, 2, None);
         assert!(account.process_transaction(dispute_tx).is_ok());
         let chargeback_tx = Transaction::new(TransactionType::Chargeback, 1, 2, None);
         assert!(account.process_transaction(chargeback_tx).is_ok());
-        assert_eq!(account.available, 2000);
-        assert_eq!(account.held, 0);
-        assert!(account.locked);
+        assert_eq!(account.available(), Money::from_raw(2000));
+        assert_eq!(account.held(), Money::ZERO);
+        assert_eq!(account.total(), Money::from_raw(2000));
+        assert!(account.is_locked());
+    }
+
+    #[test]
+    fn test_withdrawal_dispute_policies() {
+        // Balances after the withdrawal, the dispute, and then a resolve or a chargeback of it.
+        let cases = [
+            (
+                WithdrawalDisputePolicy::HoldFromTotal,
+                Ok(()),
+                (10000, 0, 10000),
+                (10000, 30000, 40000),
+                (10000, 0, 10000),
+                (40000, 0, 40000),
+            ),
+            (
+                WithdrawalDisputePolicy::CreditOnChargeback,
+                Ok(()),
+                (10000, 0, 10000),
+                (10000, 0, 10000),
+                (10000, 0, 10000),
+                (40000, 0, 40000),
+            ),
+            (
+                WithdrawalDisputePolicy::Reject,
+                Err(TransactionError::WithdrawalDisputeNotAllowed),
+                (10000, 0, 10000),
+                (10000, 0, 10000),
+                (10000, 0, 10000),
+                (10000, 0, 10000),
+            ),
+        ];
+        for (policy, dispute_result, withdrawn, disputed, resolved, charged_back) in cases {
+            let config = Config {
+                withdrawal_dispute_policy: policy,
+                ..Default::default()
+            };
+            let balances = |account: &Account| {
+                assert_eq!(account.check_invariants(), Ok(()));
+                (account.available(), account.held(), account.total())
+            };
+            let expected = |(available, held, total)| {
+                (
+                    Money::from_raw(available),
+                    Money::from_raw(held),
+                    Money::from_raw(total),
+                )
+            };
+            for (end, end_balances) in [
+                (TransactionType::Resolve, resolved),
+                (TransactionType::Chargeback, charged_back),
+            ] {
+                let mut account = Account::with_config(1, config);
+                account.deposit(None, Money::from_raw(40000)).unwrap();
+                let withdrawal = Transaction::new(
+                    TransactionType::Withdrawal,
+                    1,
+                    2,
+                    Some(Money::from_raw(30000)),
+                );
+                assert!(account.process_transaction(withdrawal).is_ok());
+                assert_eq!(balances(&account), expected(withdrawn), "{policy:?}");
+                let dispute = Transaction::new(TransactionType::Dispute, 1, 2, None);
+                assert_eq!(
+                    account.process_transaction(dispute),
+                    dispute_result,
+                    "{policy:?}"
+                );
+                assert_eq!(balances(&account), expected(disputed), "{policy:?}");
+                let result = account.process_transaction(Transaction::new(end, 1, 2, None));
+                assert_eq!(result.is_ok(), dispute_result.is_ok(), "{policy:?} {end:?}");
+                assert_eq!(
+                    balances(&account),
+                    expected(end_balances),
+                    "{policy:?} {end:?}"
+                );
+                assert_eq!(
+                    account.is_locked(),
+                    dispute_result.is_ok() && end == TransactionType::Chargeback,
+                    "{policy:?} {end:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_history() {
+        let mut account = Account::new(1);
+        let results = replay_csv(
+            &mut account,
+            "type, client, tx, amount\n\
+             deposit, 1, 5, 10.0\n\
+             deposit, 1, 2, 3.0\n\
+             withdrawal, 1, 9, 1.0\n\
+             withdrawal, 1, 3, 100.0\n\
+             deposit, 1, 7, 2.0\n\
+             dispute, 1, 2,\n\
+             resolve, 1, 2,\n\
+             dispute, 1, 9,\n\
+             dispute, 1, 7,\n\
+             chargeback, 1, 7,\n",
+        );
+        assert!(matches!(
+            results[3],
+            Err(TransactionError::InsufficientFunds { .. })
+        ));
+        let history = account
+            .history()
+            .into_iter()
+            .map(|record| (record.tx, record.kind, record.amount, record.status))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            history,
+            [
+                (
+                    5,
+                    TransactionType::Deposit,
+                    Money::from_raw(100000),
+                    TxStatus::Recorded
+                ),
+                (
+                    2,
+                    TransactionType::Deposit,
+                    Money::from_raw(30000),
+                    TxStatus::Resolved
+                ),
+                (
+                    9,
+                    TransactionType::Withdrawal,
+                    Money::from_raw(10000),
+                    TxStatus::Disputed
+                ),
+                (
+                    7,
+                    TransactionType::Deposit,
+                    Money::from_raw(20000),
+                    TxStatus::ChargedBack
+                ),
+            ]
+        );
+
+        // A resolved transaction disputed again is in dispute, and the order survives a snapshot.
+        let dispute = Transaction::new(TransactionType::Dispute, 1, 2, None);
+        account.process_transaction(dispute).unwrap();
+        assert_eq!(account.history()[1].status, TxStatus::Disputed);
+        let restored = Account::from_snapshot(account.snapshot(), Config::default());
+        assert_eq!(restored.history(), account.history());
+        let resolve = Transaction::new(TransactionType::Resolve, 1, 2, None);
+        account.process_transaction(resolve).unwrap();
+        let restored = Account::from_snapshot(account.snapshot(), Config::default());
+        assert_eq!(restored.history()[1].status, TxStatus::Resolved);
+    }
+
+    #[test]
+    fn test_duplicate_deposit() {
+        let mut account = Account::new(1);
+        let results = replay_csv(
+            &mut account,
+            "type, client, tx, amount\n\
+             deposit, 1, 1, 1.0\n\
+             deposit, 1, 1, 5.0\n",
+        );
+        assert!(results[0].is_ok());
+        assert!(matches!(
+            results[1],
+            Err(TransactionError::DuplicateTransactionId)
+        ));
+        assert_eq!(account.available(), Money::from_raw(10000));
+        assert_eq!(account.total(), Money::from_raw(10000));
+        assert_eq!(account.transactions[&1].amount, Money::from_raw(10000));
+    }
+
+    #[test]
+    fn test_duplicate_withdrawal_after_deposit() {
+        let mut account = Account::new(1);
+        let results = replay_csv(
+            &mut account,
+            "type, client, tx, amount\n\
+             deposit, 1, 1, 3.0\n\
+             withdrawal, 1, 1, 1.0\n\
+             dispute, 1, 1,\n",
+        );
+        assert!(results[0].is_ok());
+        assert!(matches!(
+            results[1],
+            Err(TransactionError::DuplicateTransactionId)
+        ));
+        assert!(results[2].is_ok());
+        assert_eq!(account.available(), Money::ZERO);
+        assert_eq!(account.held(), Money::from_raw(30000));
+        assert_eq!(account.total(), Money::from_raw(30000));
+    }
+
+    #[test]
+    fn test_deposit_overflow() {
+        let mut account = Account::new(1);
+        let transaction = Transaction::new(
+            TransactionType::Deposit,
+            1,
+            1,
+            Some(Money::MAX - Money::from_raw(1)),
+        );
+        assert!(account.process_transaction(transaction).is_ok());
+        let transaction =
+            Transaction::new(TransactionType::Deposit, 1, 2, Some(Money::from_raw(2)));
+        assert!(matches!(
+            account.process_transaction(transaction),
+            Err(TransactionError::Overflow)
+        ));
+        assert_eq!(account.available(), Money::MAX - Money::from_raw(1));
+        assert_eq!(account.total(), Money::MAX - Money::from_raw(1));
+        assert!(!account.transactions.contains_key(&2));
+    }
+
+    #[test]
+    fn test_deposit_up_to_max() {
+        let mut account = Account::new(1);
+        let transaction = Transaction::new(
+            TransactionType::Deposit,
+            1,
+            1,
+            Some(Money::MAX - Money::from_raw(1)),
+        );
+        assert!(account.process_transaction(transaction).is_ok());
+        let transaction =
+            Transaction::new(TransactionType::Deposit, 1, 2, Some(Money::from_raw(1)));
+        assert!(account.process_transaction(transaction).is_ok());
+        assert_eq!(account.available(), Money::MAX);
+        assert_eq!(account.total(), Money::MAX);
+    }
+
+    #[test]
+    fn test_withdrawal_overflow() {
+        let mut account = Account::new(1);
+        account
+            .deposit(None, Money::MAX - Money::from_raw(1))
+            .unwrap();
+        // A negative withdrawal would push the balance past `Money::MAX`.
+        assert!(matches!(
+            account.withdraw(None, Money::from_raw(-2)),
+            Err(TransactionError::Overflow)
+        ));
+        assert_eq!(account.available(), Money::MAX - Money::from_raw(1));
+        assert_eq!(account.total(), Money::MAX - Money::from_raw(1));
+    }
+
+    #[test]
+    fn test_negative_deposit() {
+        let mut account = Account::new(1);
+        let transaction =
+            Transaction::new(TransactionType::Deposit, 1, 1, Some(Money::from_raw(-1000)));
+        assert!(matches!(
+            account.process_transaction(transaction),
+            Err(TransactionError::NegativeAmount(1))
+        ));
+        assert_eq!(account.available(), Money::ZERO);
+        assert_eq!(account.total(), Money::ZERO);
+    }
+
+    #[test]
+    fn test_negative_withdrawal() {
+        let mut account = Account::new(1);
+        account.deposit(None, Money::from_raw(1000)).unwrap();
+        let transaction = Transaction::new(
+            TransactionType::Withdrawal,
+            1,
+            2,
+            Some(Money::from_raw(-1000)),
+        );
+        assert!(matches!(
+            account.process_transaction(transaction),
+            Err(TransactionError::NegativeAmount(2))
+        ));
+        assert_eq!(account.available(), Money::from_raw(1000));
+        assert_eq!(account.total(), Money::from_raw(1000));
+    }
+
+    #[test]
+    fn test_dispute_rejected_negative_deposit() {
+        let mut account = Account::new(1);
+        let results = replay_csv(
+            &mut account,
+            "type, client, tx, amount\n\
+             deposit, 1, 1, -50.0\n\
+             dispute, 1, 1,\n",
+        );
+        assert!(matches!(
+            results[0],
+            Err(TransactionError::NegativeAmount(1))
+        ));
+        assert!(matches!(
+            results[1],
+            Err(TransactionError::TransactionDoesNotExist)
+        ));
+        assert_eq!(account.available(), Money::ZERO);
+        assert_eq!(account.held(), Money::ZERO);
+        assert_eq!(account.total(), Money::ZERO);
+    }
+
+    #[test]
+    fn test_deposit_dispute_overflow() {
+        let mut account = Account::new(1);
+        let transaction =
+            Transaction::new(TransactionType::Deposit, 1, 1, Some(Money::from_raw(1000)));
+        assert!(account.process_transaction(transaction).is_ok());
+        implicit(&mut account).held = Money::MAX - Money::from_raw(500);
+        let dispute_tx = Transaction::new(TransactionType::Dispute, 1, 1, None);
+        assert!(matches!(
+            account.process_transaction(dispute_tx),
+            Err(TransactionError::Overflow)
+        ));
+        assert_eq!(account.available(), Money::from_raw(1000));
+        assert_eq!(account.held(), Money::MAX - Money::from_raw(500));
+        assert!(!account.disputed_transactions().any(|id| id == 1));
+    }
+
+    #[test]
+    fn test_withdrawal_dispute_overflow() {
+        let mut account = Account::new(1);
+        account.deposit(None, Money::MAX).unwrap();
+        let transaction = Transaction::new(
+            TransactionType::Withdrawal,
+            1,
+            1,
+            Some(Money::from_raw(1000)),
+        );
+        assert!(account.process_transaction(transaction).is_ok());
+        account.deposit(None, Money::from_raw(1000)).unwrap();
+        let dispute_tx = Transaction::new(TransactionType::Dispute, 1, 1, None);
+        assert!(matches!(
+            account.process_transaction(dispute_tx),
+            Err(TransactionError::Overflow)
+        ));
+        assert_eq!(account.available(), Money::MAX);
+        assert_eq!(account.held(), Money::ZERO);
+        assert_eq!(account.total(), Money::MAX);
+        assert!(!account.disputed_transactions().any(|id| id == 1));
+    }
+
+    #[test]
+    fn test_resolve_overflow() {
+        let mut account = Account::new(1);
+        let transaction =
+            Transaction::new(TransactionType::Deposit, 1, 1, Some(Money::from_raw(1000)));
+        assert!(account.process_transaction(transaction).is_ok());
+        let dispute_tx = Transaction::new(TransactionType::Dispute, 1, 1, None);
+        assert!(account.process_transaction(dispute_tx).is_ok());
+        implicit(&mut account).available = Money::MAX - Money::from_raw(500);
+        let resolve_tx = Transaction::new(TransactionType::Resolve, 1, 1, None);
+        assert!(matches!(
+            account.process_transaction(resolve_tx),
+            Err(TransactionError::Overflow)
+        ));
+        assert_eq!(account.available(), Money::MAX - Money::from_raw(500));
+        assert_eq!(account.held(), Money::from_raw(1000));
+        assert!(account.disputed_transactions().any(|id| id == 1));
+    }
+
+    #[test]
+    fn test_withdrawal_chargeback_overflow() {
+        let mut account = Account::new(1);
+        account.deposit(None, Money::from_raw(2000)).unwrap();
+        let transaction = Transaction::new(
+            TransactionType::Withdrawal,
+            1,
+            1,
+            Some(Money::from_raw(1000)),
+        );
+        assert!(account.process_transaction(transaction).is_ok());
+        let dispute_tx = Transaction::new(TransactionType::Dispute, 1, 1, None);
+        assert!(account.process_transaction(dispute_tx).is_ok());
+        implicit(&mut account).available = Money::MAX - Money::from_raw(500);
+        let chargeback_tx = Transaction::new(TransactionType::Chargeback, 1, 1, None);
+        assert!(matches!(
+            account.process_transaction(chargeback_tx),
+            Err(TransactionError::Overflow)
+        ));
+        assert_eq!(account.available(), Money::MAX - Money::from_raw(500));
+        assert_eq!(account.held(), Money::from_raw(1000));
+        assert!(account.disputed_transactions().any(|id| id == 1));
+        assert!(!account.is_locked());
+    }
+
+    #[test]
+    fn test_zero_deposit() {
+        let mut account = Account::new(1);
+        let transaction = Transaction::new(TransactionType::Deposit, 1, 1, Some(Money::ZERO));
+        assert!(matches!(
+            account.process_transaction(transaction),
+            Err(TransactionError::ZeroAmount(1))
+        ));
+        assert!(account.transactions.is_empty());
+    }
+
+    #[test]
+    fn test_zero_withdrawal() {
+        let mut account = Account::new(1);
+        account.deposit(None, Money::from_raw(1000)).unwrap();
+        let transaction = Transaction::new(TransactionType::Withdrawal, 1, 1, Some(Money::ZERO));
+        assert!(matches!(
+            account.process_transaction(transaction),
+            Err(TransactionError::ZeroAmount(1))
+        ));
+        assert!(account.transactions.is_empty());
+        assert_eq!(account.available(), Money::from_raw(1000));
+    }
+
+    #[test]
+    fn test_zero_and_missing_amounts() {
+        let input = "type, client, tx, amount\n\
+                     deposit, 1, 1, 0\n\
+                     deposit, 1, 2, 0.0000\n\
+                     deposit, 1, 3,\n\
+                     deposit, 1, 4\n\
+                     withdrawal, 1, 5,\"\"\n\
+                     dispute, 1, 1,\n";
+        let replay = |account: &mut Account| {
+            TransactionReader::new(input.as_bytes())
+                .unwrap()
+                .map(|transaction| account.process_transaction(transaction.unwrap()))
+                .collect::<Vec<_>>()
+        };
+
+        let mut account = Account::new(1);
+        assert!(matches!(
+            replay(&mut account)[..],
+            [
+                Err(TransactionError::ZeroAmount(1)),
+                Err(TransactionError::ZeroAmount(2)),
+                Err(TransactionError::MissingAmount(3)),
+                Err(TransactionError::MissingAmount(4)),
+                Err(TransactionError::MissingAmount(5)),
+                Err(TransactionError::TransactionDoesNotExist),
+            ]
+        ));
+        assert!(account.transactions.is_empty());
+
+        let config = Config {
+            allow_zero_amounts: true,
+            ..Default::default()
+        };
+        let mut account = Account::with_config(1, config);
+        let results = replay(&mut account);
+        assert!(matches!(results[..2], [Ok(()), Ok(())]));
+        assert!(results[5].is_ok());
+        assert_eq!(account.disputed_transactions().collect::<Vec<_>>(), [1]);
+        assert_eq!(
+            (account.available(), account.held()),
+            (Money::ZERO, Money::ZERO)
+        );
+    }
+
+    #[test]
+    fn test_max_amount() {
+        let config = Config {
+            max_amount: Some(Money::from_raw(1_000_000)),
+            ..Default::default()
+        };
+        let mut account = Account::with_config(1, config);
+        let results = replay_csv(
+            &mut account,
+            "type, client, tx, amount\n\
+             deposit, 1, 1, 99.9999\n\
+             deposit, 1, 2, 100.0001\n\
+             deposit, 1, 3, 100\n\
+             withdrawal, 1, 4, 100.0001\n\
+             hold, 1, 5, 100.0001\n",
+        );
+        assert_eq!(
+            results,
+            [
+                Ok(()),
+                Err(TransactionError::AmountTooLarge(2)),
+                Ok(()),
+                Err(TransactionError::AmountTooLarge(4)),
+                Ok(()),
+            ]
+        );
+        // Manual holds are not limited.
+        assert_eq!(account.available(), Money::from_raw(999_998));
+        assert_eq!(account.held(), Money::from_raw(1_000_001));
+        assert_eq!(account.total(), Money::from_raw(1_999_999));
+        assert!(!account.transactions.contains_key(&2));
+        assert_eq!(account.rejected_withdrawals(), 0);
+    }
+
+    #[test]
+    fn test_duplicate_deposit_while_disputed() {
+        let mut account = Account::new(1);
+        let transaction =
+            Transaction::new(TransactionType::Deposit, 1, 1, Some(Money::from_raw(1000)));
+        assert!(account.process_transaction(transaction).is_ok());
+        let dispute_tx = Transaction::new(TransactionType::Dispute, 1, 1, None);
+        assert!(account.process_transaction(dispute_tx).is_ok());
+        let duplicate =
+            Transaction::new(TransactionType::Deposit, 1, 1, Some(Money::from_raw(5000)));
+        assert!(matches!(
+            account.process_transaction(duplicate),
+            Err(TransactionError::DuplicateTransactionId)
+        ));
+        assert_eq!(account.available(), Money::ZERO);
+        assert_eq!(account.held(), Money::from_raw(1000));
+        assert_eq!(account.total(), Money::from_raw(1000));
+        let chargeback_tx = Transaction::new(TransactionType::Chargeback, 1, 1, None);
+        assert!(account.process_transaction(chargeback_tx).is_ok());
+        assert_eq!(account.held(), Money::ZERO);
+        assert_eq!(account.total(), Money::ZERO);
+    }
+
+    #[test]
+    fn test_serialize_exact_decimals() {
+        let mut account = Account::new(1);
+        let results = replay_csv(
+            &mut account,
+            "type, client, tx, amount\n\
+             deposit, 1, 1, 1.1\n\
+             deposit, 1, 2, 0.2\n\
+             deposit, 1, 3, 0.0003\n\
+             withdrawal, 1, 4, 1.005\n\
+             dispute, 1, 3,\n",
+        );
+        assert!(results.iter().all(Result::is_ok));
+        let mut writer = csv::Writer::from_writer(vec![]);
+        writer.serialize(&account).unwrap();
+        let output = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+        assert_eq!(
+            output,
+            "client,available,held,total,locked\n1,0.2950,0.0003,0.2953,false\n"
+        );
+    }
+
+    /// Deposits, withdraws everything and then disputes the deposit.
+    fn dispute_withdrawn_deposit(account: &mut Account) -> Result<(), TransactionError> {
+        let results = replay_csv(
+            account,
+            "type, client, tx, amount\n\
+             deposit, 1, 1, 100.0\n\
+             withdrawal, 1, 2, 100.0\n",
+        );
+        assert!(results.iter().all(Result::is_ok));
+        account.process_transaction(Transaction::new(TransactionType::Dispute, 1, 1, None))
+    }
+
+    #[test]
+    fn test_dispute_policy_allow_negative() {
+        let mut account = Account::new(1);
+        assert!(dispute_withdrawn_deposit(&mut account).is_ok());
+        assert_eq!(account.available(), Money::from_raw(-1000000));
+        assert_eq!(account.held(), Money::from_raw(1000000));
+        assert_eq!(account.total(), Money::ZERO);
+    }
+
+    #[test]
+    fn test_dispute_policy_reject_insufficient() {
+        let config = Config {
+            dispute_policy: DisputePolicy::RejectIfInsufficientAvailable,
+            ..Default::default()
+        };
+        let mut account = Account::with_config(1, config);
+        assert!(matches!(
+            dispute_withdrawn_deposit(&mut account),
+            Err(TransactionError::InsufficientAvailableForDispute)
+        ));
+        assert_eq!(account.available(), Money::ZERO);
+        assert_eq!(account.held(), Money::ZERO);
+        assert_eq!(account.total(), Money::ZERO);
+        assert!(account.disputed_transactions().next().is_none());
+    }
+
+    #[test]
+    fn test_dispute_amount_ignored_by_default() {
+        let mut account = Account::new(1);
+        let transaction =
+            Transaction::new(TransactionType::Deposit, 1, 1, Some(Money::from_raw(1000)));
+        assert!(account.process_transaction(transaction).is_ok());
+        let dispute_tx = Transaction::new(TransactionType::Dispute, 1, 1, Some(Money::from_raw(5)));
+        assert!(account.process_transaction(dispute_tx).is_ok());
+        assert_eq!(account.held(), Money::from_raw(1000));
+    }
+
+    #[test]
+    fn test_dispute_amount_verified() {
+        let config = Config {
+            verify_dispute_amounts: true,
+            ..Default::default()
+        };
+        let mut account = Account::with_config(1, config);
+        let transaction =
+            Transaction::new(TransactionType::Deposit, 1, 1, Some(Money::from_raw(1000)));
+        assert!(account.process_transaction(transaction).is_ok());
+
+        let dispute_tx =
+            Transaction::new(TransactionType::Dispute, 1, 1, Some(Money::from_raw(999)));
+        assert!(matches!(
+            account.process_transaction(dispute_tx),
+            Err(TransactionError::AmountMismatch)
+        ));
+        assert_eq!(account.held(), Money::ZERO);
+
+        let dispute_tx =
+            Transaction::new(TransactionType::Dispute, 1, 1, Some(Money::from_raw(1000)));
+        assert!(account.process_transaction(dispute_tx).is_ok());
+        let resolve_tx = Transaction::new(TransactionType::Resolve, 1, 1, Some(Money::from_raw(1)));
+        assert!(matches!(
+            account.process_transaction(resolve_tx),
+            Err(TransactionError::AmountMismatch)
+        ));
+        // Rows without an amount are still accepted.
+        let chargeback_tx = Transaction::new(TransactionType::Chargeback, 1, 1, None);
+        assert!(account.process_transaction(chargeback_tx).is_ok());
+        assert_eq!(account.held(), Money::ZERO);
+        assert_eq!(account.total(), Money::ZERO);
+    }
+
+    #[test]
+    fn test_redispute_after_resolve() {
+        let mut account = Account::new(1);
+        let results = replay_csv(
+            &mut account,
+            "type, client, tx, amount\n\
+             deposit, 1, 1, 1.0\n\
+             dispute, 1, 1,\n\
+             resolve, 1, 1,\n\
+             dispute, 1, 1,\n\
+             chargeback, 1, 1,\n\
+             dispute, 1, 1,\n",
+        );
+        assert_eq!(
+            results,
+            [
+                Ok(()),
+                Ok(()),
+                Ok(()),
+                Ok(()),
+                Ok(()),
+                Err(TransactionError::AlreadyChargedBack),
+            ]
+        );
+        assert_eq!(account.total(), Money::ZERO);
+        assert!(account.is_locked());
+    }
+
+    #[test]
+    fn test_dispute_after_chargeback() {
+        let mut account = Account::new(1);
+        let results = replay_csv(
+            &mut account,
+            "type, client, tx, amount\n\
+             deposit, 1, 1, 1.0\n\
+             dispute, 1, 1,\n\
+             chargeback, 1, 1,\n",
+        );
+        assert!(results.iter().all(Result::is_ok));
+        assert!(matches!(
+            account.dispute(1),
+            Err(TransactionError::AlreadyChargedBack)
+        ));
+        assert_eq!(account.available(), Money::ZERO);
+        assert_eq!(account.held(), Money::ZERO);
+        assert_eq!(account.total(), Money::ZERO);
+    }
+
+    #[test]
+    fn test_resolve_after_withdrawal_chargeback() {
+        let mut account = Account::new(1);
+        let results = replay_csv(
+            &mut account,
+            "type, client, tx, amount\n\
+             deposit, 1, 1, 2.0\n\
+             withdrawal, 1, 2, 1.0\n\
+             dispute, 1, 2,\n\
+             chargeback, 1, 2,\n",
+        );
+        assert!(results.iter().all(Result::is_ok));
+        assert!(matches!(
+            account.resolve(2),
+            Err(TransactionError::AlreadyChargedBack)
+        ));
+        assert!(matches!(
+            account.chargeback(2),
+            Err(TransactionError::AlreadyChargedBack)
+        ));
+        assert_eq!(account.available(), Money::from_raw(20000));
+        assert_eq!(account.held(), Money::ZERO);
+        assert_eq!(account.total(), Money::from_raw(20000));
+    }
+
+    #[test]
+    fn test_finality_window_forgets_charged_back() {
+        let config = Config {
+            finality_window: Some(10),
+            ..Default::default()
+        };
+        let mut account = Account::with_config(1, config);
+        let results = replay_csv(
+            &mut account,
+            "type, client, tx, amount\n\
+             deposit, 1, 1, 2.0\n\
+             deposit, 1, 2, 1.0\n\
+             dispute, 1, 1,\n",
+        );
+        assert!(results.iter().all(Result::is_ok));
+        assert_eq!(account.transactions.len(), 2);
+
+        account.chargeback(1).unwrap();
+        assert_eq!(account.transactions.len(), 1);
+        assert_eq!(
+            account.dispute(1),
+            Err(TransactionError::AlreadyChargedBack)
+        );
+        assert_eq!(
+            account.resolve(1),
+            Err(TransactionError::AlreadyChargedBack)
+        );
+        assert_eq!(
+            account.chargeback(1),
+            Err(TransactionError::AlreadyChargedBack)
+        );
+        assert_eq!(account.snapshot().charged_back, [1]);
+
+        // The other deposit can still be disputed.
+        account.dispute(2).unwrap();
+        assert_eq!(account.available(), Money::ZERO);
+        assert_eq!(account.held(), Money::from_raw(10000));
+        assert_eq!(account.total(), Money::from_raw(10000));
+    }
+
+    #[test]
+    fn test_finality_window_prunes_charged_back() {
+        let config = Config {
+            finality_window: Some(2),
+            ..Default::default()
+        };
+        let mut account = Account::with_config(1, config);
+        let results = replay_csv(
+            &mut account,
+            "type, client, tx, amount\n\
+             deposit, 1, 1, 1.0\n\
+             deposit, 1, 2, 2.0\n\
+             dispute, 1, 1,\n\
+             dispute, 1, 2,\n\
+             chargeback, 1, 2,\n",
+        );
+        assert!(results.iter().all(Result::is_ok));
+        assert_eq!(account.snapshot().charged_back, [2]);
+
+        // Recorded directly, since the account is locked. They push both disputed deposits out of the window.
+        account.record(3, StoredKind::Deposit, Money::from_raw(10000), None);
+        account.record(4, StoredKind::Deposit, Money::from_raw(10000), None);
+        account.chargeback(1).unwrap();
+        assert!(account.charged_back.is_empty());
+        assert_eq!(
+            account.dispute(2),
+            Err(TransactionError::TransactionDoesNotExist)
+        );
+        assert_eq!(
+            account.resolve(1),
+            Err(TransactionError::TransactionDoesNotExist)
+        );
+    }
+
+    #[test]
+    fn test_finality_window() {
+        let config = Config {
+            finality_window: Some(2),
+            ..Default::default()
+        };
+        let mut account = Account::with_config(1, config);
+        let results = replay_csv(
+            &mut account,
+            "type, client, tx, amount\n\
+             deposit, 1, 1, 1.0\n\
+             deposit, 1, 2, 2.0\n\
+             dispute, 1, 2,\n\
+             deposit, 1, 3, 3.0\n\
+             deposit, 1, 4, 4.0\n\
+             dispute, 1, 1,\n",
+        );
+        assert_eq!(results[5], Err(TransactionError::TransactionDoesNotExist));
+        assert!(results[..5].iter().all(Result::is_ok));
+        // The disputed deposit is kept past the window until its dispute is closed.
+        let mut recorded = account.recorded_transactions().collect::<Vec<_>>();
+        recorded.sort_unstable();
+        assert_eq!(recorded, [2, 3, 4]);
+        assert_eq!(account.held(), Money::from_raw(20000));
+
+        account.resolve(2).unwrap();
+        let mut recorded = account.recorded_transactions().collect::<Vec<_>>();
+        recorded.sort_unstable();
+        assert_eq!(recorded, [3, 4]);
+        assert_eq!(account.available(), Money::from_raw(100000));
+        assert_eq!(account.held(), Money::ZERO);
+        assert_eq!(account.check_invariants(), Ok(()));
+
+        // A transaction resolved within the window stays recorded.
+        account.dispute(4).unwrap();
+        account.resolve(4).unwrap();
+        let mut recorded = account.recorded_transactions().collect::<Vec<_>>();
+        recorded.sort_unstable();
+        assert_eq!(recorded, [3, 4]);
+
+        // One charged back past the window is forgotten right away, one within it is still known as charged back.
+        account.dispute(3).unwrap();
+        let results = replay_csv(
+            &mut account,
+            "type, client, tx, amount\n\
+             deposit, 1, 5, 5.0\n",
+        );
+        assert_eq!(results, [Ok(())]);
+        account.chargeback(3).unwrap();
+        assert_eq!(
+            account.dispute(3),
+            Err(TransactionError::TransactionDoesNotExist)
+        );
+        account.dispute(5).unwrap();
+        account.chargeback(5).unwrap();
+        assert_eq!(
+            account.dispute(5),
+            Err(TransactionError::AlreadyChargedBack)
+        );
+        assert_eq!(account.recorded_transactions().collect::<Vec<_>>(), [4]);
+        assert_eq!(account.available(), Money::from_raw(70000));
+        assert_eq!(account.check_invariants(), Ok(()));
+    }
+
+    /// Deposits twice, disputes both deposits and charges back the first one, which locks the account.
+    fn lock_with_open_dispute(account: &mut Account) {
+        let results = replay_csv(
+            account,
+            "type, client, tx, amount\n\
+             deposit, 1, 1, 1.0\n\
+             deposit, 1, 2, 2.0\n\
+             dispute, 1, 1,\n\
+             dispute, 1, 2,\n\
+             chargeback, 1, 1,\n",
+        );
+        assert!(results.iter().all(Result::is_ok));
+        assert!(account.is_locked());
+    }
+
+    #[test]
+    fn test_resolve_on_locked_account() {
+        let mut account = Account::new(1);
+        lock_with_open_dispute(&mut account);
+        let resolve_tx = Transaction::new(TransactionType::Resolve, 1, 2, None);
+        assert!(account.process_transaction(resolve_tx).is_ok());
+        assert_eq!(account.available(), Money::from_raw(20000));
+        assert_eq!(account.held(), Money::ZERO);
+        assert_eq!(account.total(), Money::from_raw(20000));
+        assert!(account.is_locked());
+
+        let deposit = Transaction::new(TransactionType::Deposit, 1, 3, Some(Money::from_raw(1000)));
+        assert!(matches!(
+            account.process_transaction(deposit),
+            Err(TransactionError::AccountLocked)
+        ));
+    }
+
+    #[test]
+    fn test_dispute_pre_lock_transaction_on_locked_account() {
+        let mut account = Account::new(1);
+        let results = replay_csv(
+            &mut account,
+            "type, client, tx, amount\n\
+             deposit, 1, 1, 1.0\n\
+             deposit, 1, 2, 2.0\n\
+             dispute, 1, 1,\n\
+             chargeback, 1, 1,\n\
+             deposit, 1, 3, 1.0\n\
+             withdrawal, 1, 4, 1.0\n\
+             dispute, 1, 2,\n",
+        );
+        assert!(matches!(
+            results[4..],
+            [
+                Err(TransactionError::AccountLocked),
+                Err(TransactionError::AccountLocked),
+                Ok(())
+            ]
+        ));
+        assert_eq!(account.available(), Money::ZERO);
+        assert_eq!(account.held(), Money::from_raw(20000));
+        assert_eq!(account.total(), Money::from_raw(20000));
+        assert!(account.is_locked());
+    }
+
+    #[test]
+    fn test_unlock_then_deposit() {
+        let mut account = Account::new(1);
+        lock_with_open_dispute(&mut account);
+        let unlock = Transaction::new(TransactionType::Unlock, 1, 10, None);
+        assert!(account.process_transaction(unlock).is_ok());
+        assert!(!account.is_locked());
+        assert_eq!(account.available(), Money::ZERO);
+        assert_eq!(account.held(), Money::from_raw(20000));
+        assert_eq!(account.total(), Money::from_raw(20000));
+
+        let deposit = Transaction::new(TransactionType::Deposit, 1, 3, Some(Money::from_raw(1000)));
+        assert!(account.process_transaction(deposit).is_ok());
+        assert_eq!(account.available(), Money::from_raw(1000));
+        // The charged back deposit stays charged back.
+        let dispute = Transaction::new(TransactionType::Dispute, 1, 1, None);
+        assert!(matches!(
+            account.process_transaction(dispute),
+            Err(TransactionError::AlreadyChargedBack)
+        ));
+    }
+
+    #[test]
+    fn test_unlock_unlocked_account() {
+        let mut account = Account::new(1);
+        account.deposit(None, Money::from_raw(1000)).unwrap();
+        let results = replay_csv(&mut account, "type, client, tx, amount\nunlock, 1, 1,\n");
+        assert!(matches!(results[..], [Err(TransactionError::NotLocked)]));
+        assert_eq!(account.available(), Money::from_raw(1000));
+    }
+
+    #[test]
+    fn test_opening_balance() {
+        let mut account = Account::with_opening_balance(1, Money::from_raw(1000000)).unwrap();
+        assert_eq!(account.check_invariants(), Ok(()));
+        assert_eq!(account.total(), Money::from_raw(1000000));
+        let results = replay_csv(
+            &mut account,
+            "type, client, tx, amount\n\
+             withdrawal, 1, 1, 60.0\n\
+             withdrawal, 1, 2, 50.0\n\
+             dispute, 1, 1,\n",
+        );
+        assert!(matches!(
+            results[..],
+            [
+                Ok(()),
+                Err(TransactionError::InsufficientFunds { .. }),
+                Ok(())
+            ]
+        ));
+        assert_eq!(account.available(), Money::from_raw(400000));
+        assert_eq!(account.held(), Money::from_raw(600000));
+        assert_eq!(account.total(), Money::from_raw(1000000));
+        assert_eq!(account.check_invariants(), Ok(()));
+        // Only the withdrawal was recorded, the opening balance isn't a transaction.
+        assert_eq!(account.history().len(), 1);
+
+        let empty = Account::with_opening_balance(2, Money::ZERO).unwrap();
+        assert_eq!(empty.summaries(4).len(), 1);
+        assert_eq!(empty.total(), Money::ZERO);
+        assert!(matches!(
+            Account::with_opening_balance(3, Money::from_raw(-1)),
+            Err(TransactionError::NegativeOpeningBalance)
+        ));
+    }
+
+    #[test]
+    fn test_freeze_and_unfreeze() {
+        let mut account = Account::new(1);
+        let results = replay_csv(
+            &mut account,
+            "type, client, tx, amount\n\
+             deposit, 1, 1, 1.0\n\
+             freeze, 1, 2,\n\
+             deposit, 1, 3, 2.0\n\
+             freeze, 1, 4,\n\
+             unfreeze, 1, 5,\n\
+             deposit, 1, 6, 2.0\n\
+             unfreeze, 1, 7,\n",
+        );
+        assert!(matches!(
+            results[..],
+            [
+                Ok(()),
+                Ok(()),
+                Err(TransactionError::AccountLocked),
+                Err(TransactionError::AlreadyLocked),
+                Ok(()),
+                Ok(()),
+                Err(TransactionError::NotLocked),
+            ]
+        ));
+        assert!(!account.is_locked());
+        assert_eq!(account.available(), Money::from_raw(30000));
+
+        // An account locked by a chargeback can be unfrozen, and frozen again under any lock policy.
+        let config = Config {
+            lock_policy: LockPolicy::BlockAll,
+            ..Default::default()
+        };
+        let mut account = Account::with_config(1, config);
+        lock_with_open_dispute(&mut account);
+        let freeze = Transaction::new(TransactionType::Freeze, 1, 10, None);
+        assert_eq!(
+            account.process_transaction(freeze.clone()),
+            Err(TransactionError::AlreadyLocked)
+        );
+        let unfreeze = Transaction::new(TransactionType::Unfreeze, 1, 11, None);
+        assert_eq!(account.process_transaction(unfreeze), Ok(()));
+        assert!(!account.is_locked());
+        assert_eq!(account.process_transaction(freeze), Ok(()));
+        assert!(account.is_locked());
+        assert_eq!(account.held(), Money::from_raw(20000));
+    }
+
+    #[test]
+    fn test_unlock_with_block_all_policy() {
+        let config = Config {
+            lock_policy: LockPolicy::BlockAll,
+            ..Default::default()
+        };
+        let mut account = Account::with_config(1, config);
+        lock_with_open_dispute(&mut account);
+        let unlock = Transaction::new(TransactionType::Unlock, 1, 10, None);
+        assert!(account.process_transaction(unlock).is_ok());
+        assert!(!account.is_locked());
+    }
+
+    #[test]
+    fn test_withdrawal_chargeback_lifecycle() {
+        let mut account = Account::new(1);
+        let balances = |account: &Account| {
+            assert_eq!(account.check_invariants(), Ok(()));
+            (account.available(), account.held(), account.total())
+        };
+        let mut process = |tx_type, tx, amount| {
+            let result = account.process_transaction(Transaction::new(tx_type, 1, tx, amount));
+            (result, balances(&account))
+        };
+
+        let steps = [
+            process(TransactionType::Deposit, 1, Some(Money::from_raw(1000000))),
+            process(
+                TransactionType::Withdrawal,
+                2,
+                Some(Money::from_raw(1000000)),
+            ),
+            process(TransactionType::Dispute, 2, None),
+            // The pending credit of the disputed withdrawal can't be withdrawn.
+            process(TransactionType::Withdrawal, 3, Some(Money::from_raw(1))),
+            process(TransactionType::Chargeback, 2, None),
+            process(TransactionType::Unlock, 4, None),
+            // The withdrawal can't be disputed and charged back a second time.
+            process(TransactionType::Dispute, 2, None),
+            process(TransactionType::Chargeback, 2, None),
+            process(
+                TransactionType::Withdrawal,
+                5,
+                Some(Money::from_raw(1000000)),
+            ),
+            process(TransactionType::Withdrawal, 6, Some(Money::from_raw(1))),
+        ];
+        let expected = [
+            (Ok(()), (1000000, 0, 1000000)),
+            (Ok(()), (0, 0, 0)),
+            (Ok(()), (0, 1000000, 1000000)),
+            (
+                Err(TransactionError::InsufficientFunds {
+                    requested: Money::from_raw(1),
+                    available: Money::ZERO,
+                }),
+                (0, 1000000, 1000000),
+            ),
+            (Ok(()), (1000000, 0, 1000000)),
+            (Ok(()), (1000000, 0, 1000000)),
+            (
+                Err(TransactionError::AlreadyChargedBack),
+                (1000000, 0, 1000000),
+            ),
+            (
+                Err(TransactionError::AlreadyChargedBack),
+                (1000000, 0, 1000000),
+            ),
+            (Ok(()), (0, 0, 0)),
+            (
+                Err(TransactionError::InsufficientFunds {
+                    requested: Money::from_raw(1),
+                    available: Money::ZERO,
+                }),
+                (0, 0, 0),
+            ),
+        ];
+        for (step, ((result, balances), (expected_result, expected_balances))) in
+            steps.into_iter().zip(expected).enumerate()
+        {
+            assert_eq!(
+                format!("{result:?}"),
+                format!("{expected_result:?}"),
+                "step {step}"
+            );
+            let (available, held, total) = expected_balances;
+            let expected_balances = (
+                Money::from_raw(available),
+                Money::from_raw(held),
+                Money::from_raw(total),
+            );
+            assert_eq!(balances, expected_balances, "step {step}");
+        }
+    }
+
+    #[test]
+    fn test_check_invariants() {
+        let mut account = Account::new(1);
+        replay_csv(
+            &mut account,
+            "type, client, tx, amount\n\
+             deposit, 1, 1, 10.0\n\
+             withdrawal, 1, 2, 4.0\n\
+             dispute, 1, 1,\n\
+             dispute, 1, 2,\n",
+        );
+        assert_eq!(account.check_invariants(), Ok(()));
+
+        implicit(&mut account).total += Money::from_raw(1);
+        assert_eq!(
+            account.check_invariants(),
+            Err("total 10.0001 is not available -4.0000 + held 14.0000".to_string())
+        );
+        implicit(&mut account).total = Money::ZERO;
+        implicit(&mut account).available = Money::from_raw(1);
+        implicit(&mut account).held = Money::from_raw(-1);
+        assert_eq!(
+            account.check_invariants(),
+            Err("held -0.0001 is negative".to_string())
+        );
+    }
+
+    #[test]
+    fn test_overdraft() {
+        let config = Config {
+            overdraft_limit: Money::from_raw(50000),
+            ..Default::default()
+        };
+        let mut account = Account::with_config(1, config);
+        let results = replay_csv(
+            &mut account,
+            "type, client, tx, amount\n\
+             deposit, 1, 1, 10.0\n\
+             withdrawal, 1, 2, 14.0\n\
+             withdrawal, 1, 3, 1.0001\n\
+             withdrawal, 1, 4, 1.0\n",
+        );
+        assert!(matches!(
+            results[..],
+            [
+                Ok(()),
+                Ok(()),
+                Err(TransactionError::InsufficientFunds { .. }),
+                Ok(())
+            ]
+        ));
+        assert_eq!(account.available(), Money::from_raw(-50000));
+        assert_eq!(account.total(), Money::from_raw(-50000));
+        assert_eq!(account.rejected_withdrawals(), 1);
+    }
+
+    #[test]
+    fn test_dispute_before_its_deposit() {
+        let config = Config {
+            park_unknown_disputes: true,
+            ..Default::default()
+        };
+        let mut account = Account::with_config(1, config);
+        let results = replay_csv(
+            &mut account,
+            "type, client, tx, amount\n\
+             dispute, 1, 2,\n\
+             dispute, 1, 2,\n\
+             resolve, 1, 2,\n\
+             deposit, 1, 1, 1.0\n\
+             deposit, 1, 2, 2.0\n",
+        );
+        assert!(matches!(
+            results[..],
+            [
+                Ok(()),
+                Err(TransactionError::AlreadyInDispute),
+                Err(TransactionError::TransactionDoesNotExist),
+                Ok(()),
+                Ok(())
+            ]
+        ));
+        assert_eq!(account.available(), Money::from_raw(10000));
+        assert_eq!(account.held(), Money::from_raw(20000));
+        assert_eq!(account.total(), Money::from_raw(30000));
+        assert!(account.pending_disputes().next().is_none());
+        assert_eq!(account.disputed_transactions().collect::<Vec<_>>(), [2]);
+
+        let resolve_tx = Transaction::new(TransactionType::Resolve, 1, 2, None);
+        assert!(account.process_transaction(resolve_tx).is_ok());
+        assert_eq!(account.available(), Money::from_raw(30000));
+    }
+
+    #[test]
+    fn test_unknown_dispute_rejected_by_default() {
+        let mut account = Account::new(1);
+        let results = replay_csv(
+            &mut account,
+            "type, client, tx, amount\n\
+             dispute, 1, 1,\n\
+             deposit, 1, 1, 1.0\n",
+        );
+        assert!(matches!(
+            results[..],
+            [Err(TransactionError::TransactionDoesNotExist), Ok(())]
+        ));
+        assert_eq!(account.held(), Money::ZERO);
+        assert!(account.pending_disputes().next().is_none());
+    }
+
+    #[test]
+    fn test_hold_and_release() {
+        let mut account = Account::new(1);
+        let results = replay_csv(
+            &mut account,
+            "type, client, tx, amount\n\
+             deposit, 1, 1, 10.0\n\
+             hold, 1, 2, 4.0\n\
+             withdrawal, 1, 3, 7.0\n\
+             release, 1, 4, 1.5\n",
+        );
+        assert!(matches!(
+            results[..],
+            [
+                Ok(()),
+                Ok(()),
+                Err(TransactionError::InsufficientFunds { .. }),
+                Ok(())
+            ]
+        ));
+        assert_eq!(account.available(), Money::from_raw(75000));
+        assert_eq!(account.held(), Money::from_raw(25000));
+        assert_eq!(account.manual_held(), Money::from_raw(25000));
+        assert_eq!(account.total(), Money::from_raw(100000));
+    }
+
+    #[test]
+    fn test_over_release() {
+        let mut account = Account::new(1);
+        let results = replay_csv(
+            &mut account,
+            "type, client, tx, amount\n\
+             deposit, 1, 1, 10.0\n\
+             deposit, 1, 2, 5.0\n\
+             hold, 1, 3, 1.0\n\
+             dispute, 1, 2,\n\
+             release, 1, 4, 2.0\n\
+             hold, 1, 5, 20.0\n",
+        );
+        // The disputed amount is held too, but it can't be released manually.
+        assert!(matches!(
+            results[4..],
+            [
+                Err(TransactionError::ReleaseExceedsHold),
+                Err(TransactionError::InsufficientFunds { .. })
+            ]
+        ));
+        assert_eq!(account.available(), Money::from_raw(90000));
+        assert_eq!(account.held(), Money::from_raw(60000));
+        assert_eq!(account.manual_held(), Money::from_raw(10000));
+
+        let release = Transaction::new(TransactionType::Release, 1, 6, None);
+        assert!(matches!(
+            account.process_transaction(release),
+            Err(TransactionError::MissingAmount(6))
+        ));
+    }
+
+    #[test]
+    fn test_hold_and_release_on_frozen_account() {
+        let input = "type, client, tx, amount\n\
+                     deposit, 1, 1, 10.0\n\
+                     freeze, 1, 2,\n\
+                     hold, 1, 3, 4.0\n\
+                     release, 1, 4, 1.5\n";
+        let mut account = Account::new(1);
+        let results = replay_csv(&mut account, input);
+        assert!(results.iter().all(Result::is_ok));
+        assert!(account.is_locked());
+        assert_eq!(account.available(), Money::from_raw(75000));
+        assert_eq!(account.manual_held(), Money::from_raw(25000));
+
+        // Only `LockPolicy::BlockAll` blocks them.
+        let config = Config {
+            lock_policy: LockPolicy::BlockAll,
+            ..Default::default()
+        };
+        let mut account = Account::with_config(1, config);
+        let results = replay_csv(&mut account, input);
+        assert!(matches!(
+            results[2..],
+            [
+                Err(TransactionError::AccountLocked),
+                Err(TransactionError::AccountLocked)
+            ]
+        ));
+        assert_eq!(account.available(), Money::from_raw(100000));
+        assert_eq!(account.manual_held(), Money::ZERO);
+    }
+
+    #[test]
+    fn test_lock_policy_block_all() {
+        let config = Config {
+            lock_policy: LockPolicy::BlockAll,
+            ..Default::default()
+        };
+        let mut account = Account::with_config(1, config);
+        lock_with_open_dispute(&mut account);
+        let resolve_tx = Transaction::new(TransactionType::Resolve, 1, 2, None);
+        assert!(matches!(
+            account.process_transaction(resolve_tx),
+            Err(TransactionError::AccountLocked)
+        ));
+        assert_eq!(account.held(), Money::from_raw(20000));
+    }
+
+    #[test]
+    fn test_summary_precision() {
+        let mut account = Account::new(1);
+        account.deposit(None, Money::from_raw(15000)).unwrap();
+        let mut writer = csv::Writer::from_writer(vec![]);
+        writer.serialize(account.summary(4)).unwrap();
+        writer.serialize(account.summary(2)).unwrap();
+        let output = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+        assert_eq!(
+            output,
+            "client,available,held,total,locked\n\
+             1,1.5000,0.0000,1.5000,false\n\
+             1,1.50,0.00,1.50,false\n"
+        );
+    }
+
+    #[test]
+    fn test_currencies() {
+        let mut account = Account::new(1);
+        let results = replay_csv(
+            &mut account,
+            "type, client, tx, amount, currency\n\
+             deposit, 1, 1, 10.0, USD\n\
+             deposit, 1, 2, 5.0, eur\n\
+             withdrawal, 1, 3, 6.0, EUR\n\
+             withdrawal, 1, 4, 3.0, USD\n\
+             dispute, 1, 2,,\n\
+             hold, 1, 5, 1.0, USD\n\
+             withdrawal, 1, 6, 1.0, GBP\n",
+        );
+        assert!(matches!(
+            results[..],
+            [
+                Ok(()),
+                Ok(()),
+                Err(TransactionError::InsufficientFunds { .. }),
+                Ok(()),
+                Ok(()),
+                Ok(()),
+                Err(TransactionError::InsufficientFunds { .. }),
+            ]
+        ));
+        let usd = "USD".parse::<Currency>().unwrap();
+        let eur = "EUR".parse::<Currency>().unwrap();
+        let balances = |currency| {
+            let balances = account.balances(currency);
+            (balances.available(), balances.held(), balances.total())
+        };
+        assert_eq!(
+            balances(Some(usd)),
+            (
+                Money::from_raw(60000),
+                Money::from_raw(10000),
+                Money::from_raw(70000)
+            )
+        );
+        assert_eq!(
+            balances(Some(eur)),
+            (Money::ZERO, Money::from_raw(50000), Money::from_raw(50000))
+        );
+        assert_eq!(balances(None), (Money::ZERO, Money::ZERO, Money::ZERO));
+        // The rejected GBP withdrawal doesn't add the currency.
+        assert_eq!(account.currencies(), [Some(eur), Some(usd)]);
+        assert_eq!(account.check_invariants(), Ok(()));
+
+        let mut writer = csv::Writer::from_writer(vec![]);
+        for summary in account.summaries(2) {
+            writer.serialize(summary).unwrap();
+        }
+        let output = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+        assert_eq!(
+            output,
+            "client,currency,available,held,total,locked\n\
+             1,EUR,0.00,5.00,5.00,false\n\
+             1,USD,6.00,1.00,7.00,false\n"
+        );
+
+        // Read back the way snapshot files are, without borrowing from the input.
+        let json = serde_json::to_string(&account.snapshot()).unwrap();
+        let snapshot = serde_json::from_reader(json.as_bytes()).unwrap();
+        let restored = Account::from_snapshot(snapshot, Config::default());
+        assert_eq!(restored.snapshot(), account.snapshot());
+        assert_eq!(restored.currencies(), account.currencies());
     }
 }
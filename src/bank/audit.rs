@@ -0,0 +1,23 @@
+//! Audit trail of the transactions that were applied to the accounts.
+use crate::bank::{ClientId, Currency, Money, TransactionId, TransactionType};
+
+/// A transaction that was successfully applied, with the balances of its account in the affected currency right after it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditEntry {
+    /// The ID of the applied transaction.
+    pub transaction_id: TransactionId,
+    /// The client of the applied transaction.
+    pub client_id: ClientId,
+    /// The type of the applied transaction.
+    pub tx_type: TransactionType,
+    /// The amount of the applied transaction, if it had one.
+    pub amount: Option<Money>,
+    /// The currency whose balances the transaction changed, `None` for the implicit one.
+    pub currency: Option<Currency>,
+    /// The available balance after the transaction.
+    pub available: Money,
+    /// The held balance after the transaction.
+    pub held: Money,
+    /// The total balance after the transaction.
+    pub total: Money,
+}
@@ -0,0 +1,56 @@
+//! The balances an account keeps for each currency.
+use crate::bank::Money;
+
+/// The balances of an account in a single currency.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Balances {
+    /// The funds available for withdrawal.
+    pub(crate) available: Money,
+    /// The funds held by open disputes and manual holds.
+    pub(crate) held: Money,
+    /// The total funds, including available and held amounts.
+    pub(crate) total: Money,
+    /// The part of the held funds placed by manual holds, which is all that can be released.
+    pub(crate) manual_held: Money,
+}
+
+impl Balances {
+    /// Returns the funds available for withdrawal.
+    pub fn available(&self) -> Money {
+        self.available
+    }
+
+    /// Returns the funds held by open disputes and manual holds.
+    pub fn held(&self) -> Money {
+        self.held
+    }
+
+    /// Returns the total funds.
+    pub fn total(&self) -> Money {
+        self.total
+    }
+
+    /// Returns the part of the held funds placed by manual holds.
+    pub fn manual_held(&self) -> Money {
+        self.manual_held
+    }
+
+    /// Returns whether all of the balances are zero.
+    pub(crate) fn is_zero(&self) -> bool {
+        *self == Balances::default()
+    }
+
+    /// Checks that `total == available + held` and `held >= 0`.
+    pub(crate) fn check_invariants(&self) -> Result<(), String> {
+        if self.available.checked_add(self.held) != Some(self.total) {
+            return Err(format!(
+                "total {} is not available {} + held {}",
+                self.total, self.available, self.held
+            ));
+        }
+        if self.held.is_negative() {
+            return Err(format!("held {} is negative", self.held));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,62 @@
+//! Handing transactions over to the channel of a `State` in batches rather than one at a time.
+use tokio::sync::mpsc::{
+    self,
+    error::{SendError, TrySendError},
+};
+
+use crate::bank::Transaction;
+
+/// The most transactions `State::run` takes from its channel at once, and a good size for the batches of
+/// `send_batch`. Every batch costs a single wake-up of the other side instead of one per transaction.
+/// The channel itself carries single transactions, so producers that send one at a time don't need to batch, and a
+/// channel of `Vec<Transaction>` wasn't any faster.
+pub const BATCH_SIZE: usize = 128;
+
+/// Sends all transactions of the batch through the channel, leaving the batch empty. Room for the whole batch is
+/// reserved at once, or for as much of it as the channel can hold at a time.
+/// Returns how many times it had to wait for the channel to have room, or an error if the channel was closed, in
+/// which case the transactions that weren't sent are dropped.
+///
+/// ```
+/// use bank::{State, Transaction, TransactionType};
+/// use tokio::sync::mpsc;
+///
+/// # tokio::runtime::Runtime::new().unwrap().block_on(async {
+/// let (sender, receiver) = mpsc::channel(bank::DEFAULT_CHANNEL_SIZE);
+/// let mut state = State::new(receiver);
+/// let handle = tokio::spawn(async move {
+///     state.run().await;
+///     state
+/// });
+/// let mut batch = (1..=3)
+///     .map(|tx| Transaction::new(TransactionType::Deposit, 1, tx, "1".parse().ok()))
+///     .collect();
+/// bank::send_batch(&sender, &mut batch).await.unwrap();
+/// drop(sender);
+/// assert_eq!(handle.await.unwrap().get_account(1).unwrap().total().to_string(), "3.0000");
+/// # });
+/// ```
+pub async fn send_batch(
+    sender: &mpsc::Sender<Transaction>,
+    batch: &mut Vec<Transaction>,
+) -> Result<usize, SendError<()>> {
+    let mut waits = 0;
+    let mut transactions = batch.drain(..);
+    loop {
+        let count = transactions.len().min(sender.max_capacity());
+        if count == 0 {
+            return Ok(waits);
+        }
+        let permits = match sender.try_reserve_many(count) {
+            Ok(permits) => permits,
+            Err(TrySendError::Full(())) => {
+                waits += 1;
+                sender.reserve_many(count).await?
+            }
+            Err(TrySendError::Closed(())) => return Err(SendError(())),
+        };
+        for (permit, transaction) in permits.zip(transactions.by_ref()) {
+            permit.send(transaction);
+        }
+    }
+}
@@ -0,0 +1,161 @@
+//! Building a `State` together with the channel it processes transactions from.
+use tokio::sync::mpsc;
+
+use crate::bank::{Config, Snapshot, State, Transaction, TransactionError};
+
+/// The capacity of the transaction channel unless `StateBuilder::channel_size` sets another one.
+pub const DEFAULT_CHANNEL_SIZE: usize = 100;
+
+/// Builds a `State` and the channel feeding it. The capacity of the channel decides how far the producers may
+/// get ahead of the processing: once it's full, `Sender::send` waits until `State::run` takes a transaction.
+///
+/// ```
+/// use bank::{StateBuilder, Transaction, TransactionType};
+///
+/// # tokio::runtime::Runtime::new().unwrap().block_on(async {
+/// let (mut state, sender) = StateBuilder::new().channel_size(10).build();
+/// let handle = tokio::spawn(async move {
+///     state.run().await;
+///     state
+/// });
+/// sender
+///     .send(Transaction::new(TransactionType::Deposit, 1, 1, "2".parse().ok()))
+///     .await
+///     .unwrap();
+/// drop(sender);
+/// let state = handle.await.unwrap();
+/// assert_eq!(state.get_account(1).unwrap().available().to_string(), "2.0000");
+/// # });
+/// ```
+pub struct StateBuilder {
+    /// Capacity of the transaction channel.
+    channel_size: usize,
+    /// The configuration of the accounts.
+    config: Config,
+    /// Where failed transactions are sent, see `State::with_error_sink`.
+    error_sink: Option<mpsc::UnboundedSender<(Transaction, TransactionError)>>,
+    /// Whether applied transactions are recorded, see `State::with_audit`.
+    audit: bool,
+    /// The state to start from, see `State::restore`.
+    snapshot: Option<Snapshot>,
+}
+
+impl Default for StateBuilder {
+    fn default() -> Self {
+        StateBuilder {
+            channel_size: DEFAULT_CHANNEL_SIZE,
+            config: Config::default(),
+            error_sink: None,
+            audit: false,
+            snapshot: None,
+        }
+    }
+}
+
+impl StateBuilder {
+    /// Creates a builder of an empty state with the default configuration and channel capacity.
+    pub fn new() -> Self {
+        StateBuilder::default()
+    }
+
+    /// Sets the capacity of the transaction channel. Panics if it's zero.
+    pub fn channel_size(mut self, channel_size: usize) -> Self {
+        assert!(channel_size > 0, "The channel capacity must be at least 1");
+        self.channel_size = channel_size;
+        self
+    }
+
+    /// Sets the configuration of the accounts.
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Sends every failed transaction with its error to the sink, see `State::with_error_sink`.
+    pub fn error_sink(
+        mut self,
+        error_sink: mpsc::UnboundedSender<(Transaction, TransactionError)>,
+    ) -> Self {
+        self.error_sink = Some(error_sink);
+        self
+    }
+
+    /// Records every applied transaction, see `State::with_audit`.
+    pub fn audit(mut self) -> Self {
+        self.audit = true;
+        self
+    }
+
+    /// Starts from the saved state instead of an empty one.
+    pub fn snapshot(mut self, snapshot: Snapshot) -> Self {
+        self.snapshot = Some(snapshot);
+        self
+    }
+
+    /// Creates the channel and the state processing it, returning the state together with the sending side.
+    /// More senders can be made by cloning it, the state's `run` returns once all of them are dropped.
+    pub fn build(self) -> (State, mpsc::Sender<Transaction>) {
+        let (sender, receiver) = mpsc::channel(self.channel_size);
+        let state = match self.snapshot {
+            Some(snapshot) => State::restore(snapshot, receiver),
+            None => State::new(receiver),
+        };
+        let mut state = state.with_config(self.config);
+        if let Some(error_sink) = self.error_sink {
+            state = state.with_error_sink(error_sink);
+        }
+        if self.audit {
+            state = state.with_audit();
+        }
+        (state, sender)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::{sync::mpsc::error::TrySendError, time::timeout};
+
+    use crate::bank::{Money, StateBuilder, Transaction, TransactionType};
+
+    #[tokio::test]
+    async fn test_backpressure() {
+        let deposit = |tx| {
+            Transaction::new(
+                TransactionType::Deposit,
+                1,
+                tx,
+                Some(Money::from_raw(10000)),
+            )
+        };
+        let (mut state, sender) = StateBuilder::new().channel_size(1).build();
+        sender.send(deposit(1)).await.unwrap();
+        assert!(matches!(
+            sender.try_send(deposit(2)),
+            Err(TrySendError::Full(_))
+        ));
+        // Nothing takes from the channel yet, so the send waits.
+        assert!(
+            timeout(Duration::from_millis(50), sender.send(deposit(2)))
+                .await
+                .is_err()
+        );
+
+        let handle = tokio::spawn(async move {
+            state.run().await;
+            state
+        });
+        // Once the state drains the channel the send goes through.
+        timeout(Duration::from_secs(5), sender.send(deposit(2)))
+            .await
+            .expect("The send should complete once the state takes from the channel")
+            .unwrap();
+        drop(sender);
+        let state = handle.await.unwrap();
+        assert_eq!(
+            state.get_account(1).unwrap().available(),
+            Money::from_raw(20000)
+        );
+    }
+}
@@ -0,0 +1,214 @@
+//! Processing transactions from several tasks at once, with every account behind its own lock.
+use std::{
+    collections::HashMap,
+    sync::{Mutex, MutexGuard, PoisonError},
+};
+
+use dashmap::{DashMap, mapref::entry::Entry};
+
+use crate::bank::{
+    Account, ClientId, Config, ProcessingError, Snapshot, State, Stats, Transaction,
+    TransactionError, TransactionId, TransactionType,
+};
+
+/// Accounts shared between tasks, which process transactions through `&self` without a channel in between.
+/// A transaction locks the account of its client for as long as it's being applied, so transactions of different
+/// clients run in parallel while the ones of a client are applied one at a time, in the order they are submitted.
+/// A task submitting all transactions of a client, e.g. with the clients partitioned between the tasks, therefore gets
+/// the same accounts as a single `State` processing them in order.
+///
+/// Transaction IDs of deposits and withdrawals are unique across all clients like in a `State`, unlike in a
+/// `ShardedState`. There are no observers, audit log or ingestion errors: once the tasks are done, `into_state` turns
+/// it into a `State` for the report and the snapshot.
+///
+/// ```
+/// use std::thread;
+///
+/// use bank::{ConcurrentState, Transaction, TransactionType};
+///
+/// let state = ConcurrentState::new();
+/// thread::scope(|scope| {
+///     for client in 1..=4 {
+///         let state = &state;
+///         scope.spawn(move || {
+///             let deposit = Transaction::new(TransactionType::Deposit, client, client.into(), "2".parse().ok());
+///             state.process_transaction(deposit).unwrap();
+///         });
+///     }
+/// });
+/// assert_eq!(state.into_state().get_all_accounts().len(), 4);
+/// ```
+#[derive(Default)]
+pub struct ConcurrentState {
+    /// The accounts, each locked while a transaction is applied to it.
+    accounts: DashMap<ClientId, Mutex<Account>>,
+    /// The client that owns each recorded deposit and withdrawal.
+    transaction_owners: DashMap<TransactionId, ClientId>,
+    /// The configuration applied to every account.
+    config: Config,
+    /// Counters of the processed transactions.
+    stats: Mutex<Stats>,
+}
+
+impl ConcurrentState {
+    /// Creates a state without any accounts.
+    pub fn new() -> Self {
+        ConcurrentState::default()
+    }
+
+    /// Takes over the accounts of the state, e.g. one restored from a snapshot, together with its configuration and
+    /// counters. Its observers and audit log are dropped.
+    pub fn from_state(state: State) -> Self {
+        let (accounts, transaction_owners, config, stats) = state.into_parts();
+        ConcurrentState {
+            accounts: accounts
+                .into_iter()
+                .map(|(client_id, account)| (client_id, Mutex::new(account)))
+                .collect(),
+            transaction_owners: transaction_owners.into_iter().collect(),
+            config,
+            stats: Mutex::new(stats),
+        }
+    }
+
+    /// Sets the configuration used for the accounts created from now on.
+    pub fn with_config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Processes a transaction like `State::process_transaction` does, with the same errors.
+    pub fn process_transaction(&self, transaction: Transaction) -> Result<(), ProcessingError> {
+        let tx_type = *transaction.get_type();
+        self.lock_stats().count_transaction(tx_type);
+        self.apply_transaction(transaction)
+            .inspect_err(|err| self.lock_stats().count_rejection(err.tx_type, &err.error))
+    }
+
+    /// Applies the transaction to its account, creating the account if needed, and records it.
+    fn apply_transaction(&self, transaction: Transaction) -> Result<(), ProcessingError> {
+        let client_id = transaction.get_client_id();
+        let transaction_id = transaction.get_transaction_id();
+        let tx_type = *transaction.get_type();
+        let line = transaction.get_line();
+        let file = transaction.get_file().cloned();
+        let context = |error| ProcessingError {
+            client_id,
+            transaction_id,
+            tx_type,
+            line,
+            file: file.clone(),
+            error,
+        };
+        // A deposit or withdrawal claims its ID before it's applied, so that two clients can't both record the same
+        // one. The claim is given up if the transaction fails.
+        let claimed = match tx_type {
+            TransactionType::Deposit | TransactionType::Withdrawal => {
+                match self.transaction_owners.entry(transaction_id) {
+                    Entry::Occupied(_) => {
+                        return Err(context(TransactionError::DuplicateTransactionId));
+                    }
+                    Entry::Vacant(entry) => {
+                        entry.insert(client_id);
+                    }
+                }
+                true
+            }
+            TransactionType::Dispute | TransactionType::Resolve | TransactionType::Chargeback => {
+                if let Some(owner) = self.transaction_owners.get(&transaction_id)
+                    && *owner != client_id
+                {
+                    return Err(context(TransactionError::DisputeClientMismatch));
+                }
+                false
+            }
+            TransactionType::Unlock
+            | TransactionType::Hold
+            | TransactionType::Release
+            | TransactionType::Freeze
+            | TransactionType::Unfreeze => false,
+        };
+
+        let error = {
+            let account = match self.accounts.get(&client_id) {
+                Some(account) => account,
+                None => self
+                    .accounts
+                    .entry(client_id)
+                    .or_insert_with(|| {
+                        self.lock_stats().count_account();
+                        Mutex::new(Account::with_config(client_id, self.config))
+                    })
+                    .downgrade(),
+            };
+            let mut account = account.lock().unwrap_or_else(PoisonError::into_inner);
+            account.process_transaction(transaction).err()
+        };
+        match error {
+            Some(error) => {
+                if claimed {
+                    self.transaction_owners.remove(&transaction_id);
+                }
+                Err(context(error))
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Runs `read` on the account of the client, if it exists. The account is locked in the meantime.
+    pub fn with_account<T>(
+        &self,
+        client_id: ClientId,
+        read: impl FnOnce(&Account) -> T,
+    ) -> Option<T> {
+        let account = self.accounts.get(&client_id)?;
+        let account = account.lock().unwrap_or_else(PoisonError::into_inner);
+        Some(read(&account))
+    }
+
+    /// Captures the state of every account, see `State::snapshot`.
+    pub fn snapshot(&self) -> Snapshot {
+        let mut accounts = self
+            .accounts
+            .iter()
+            .map(|entry| {
+                entry
+                    .value()
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .snapshot()
+            })
+            .collect::<Vec<_>>();
+        accounts.sort_by_key(|account| account.client);
+        Snapshot { accounts }
+    }
+
+    /// Turns the accounts into a regular `State`, with the configuration and the counters.
+    pub fn into_state(self) -> State {
+        let accounts = self
+            .accounts
+            .into_iter()
+            .map(|(client_id, account)| {
+                (
+                    client_id,
+                    account.into_inner().unwrap_or_else(PoisonError::into_inner),
+                )
+            })
+            .collect::<HashMap<_, _>>();
+        let stats = self
+            .stats
+            .into_inner()
+            .unwrap_or_else(PoisonError::into_inner);
+        State::from_parts(
+            accounts,
+            self.transaction_owners.into_iter().collect(),
+            self.config,
+            stats,
+        )
+    }
+
+    /// Locks the counters. A panic of another task doesn't make them unusable.
+    fn lock_stats(&self) -> MutexGuard<'_, Stats> {
+        self.stats.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
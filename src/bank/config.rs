@@ -0,0 +1,75 @@
+//! Configuration of the banking engine behavior.
+use crate::bank::Money;
+
+/// How a dispute is handled when the disputed amount is no longer available in the account.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DisputePolicy {
+    /// The dispute is applied anyway, which can push the available balance below zero.
+    #[default]
+    AllowNegativeAvailable,
+    /// The dispute is rejected with `TransactionError::InsufficientAvailableForDispute`.
+    RejectIfInsufficientAvailable,
+}
+
+/// How a dispute of a withdrawal affects the balances.
+/// It shouldn't be changed while withdrawals are in dispute, since their resolve or chargeback assumes the same policy.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WithdrawalDisputePolicy {
+    /// The disputed amount is held as a pending credit, increasing held and total, and becomes available on a chargeback.
+    /// A resolve removes it again.
+    #[default]
+    HoldFromTotal,
+    /// The balances don't change while the withdrawal is in dispute, a chargeback credits the amount to available
+    /// and total, and a resolve changes nothing.
+    CreditOnChargeback,
+    /// Disputes of withdrawals are rejected with `TransactionError::WithdrawalDisputeNotAllowed`.
+    Reject,
+}
+
+/// Which transactions are blocked once an account is locked.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LockPolicy {
+    /// Deposits and withdrawals are blocked, open disputes can still be resolved or charged back. Manual holds and
+    /// releases are accepted too, since they only move funds between available and held.
+    #[default]
+    BlockFundsMovement,
+    /// Every transaction is blocked.
+    BlockAll,
+}
+
+/// Options controlling how the engine processes transactions.
+/// By default a dispute may push the available balance below zero (`DisputePolicy::AllowNegativeAvailable`), a
+/// disputed withdrawal is held as a pending credit (`WithdrawalDisputePolicy::HoldFromTotal`) and a locked account
+/// blocks deposits and withdrawals but not disputes (`LockPolicy::BlockFundsMovement`). Dispute amounts aren't checked,
+/// unknown disputes and zero amounts are rejected, and there is no overdraft, maximum amount or finality window.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Config {
+    /// How to handle disputes of deposits whose funds were already withdrawn.
+    pub dispute_policy: DisputePolicy,
+    /// How disputes of withdrawals affect the balances.
+    pub withdrawal_dispute_policy: WithdrawalDisputePolicy,
+    /// When set, a dispute, resolve or chargeback carrying an amount must match the amount of the referenced transaction.
+    pub verify_dispute_amounts: bool,
+    /// Which transactions are blocked on a locked account.
+    pub lock_policy: LockPolicy,
+    /// When set, a dispute of a transaction the account doesn't know (yet) is parked instead of rejected.
+    /// If a deposit or withdrawal with that ID arrives later, the dispute is applied right after it, as if the
+    /// transactions came in the opposite order. A parked dispute that would fail at that point is dropped.
+    /// Resolves and chargebacks are not parked, they only apply to disputes that are already open.
+    pub park_unknown_disputes: bool,
+    /// How far below zero a withdrawal may take the available balance. Zero, the default, allows no overdraft.
+    pub overdraft_limit: Money,
+    /// When set, deposits, withdrawals, holds and releases of zero are applied instead of rejected with
+    /// `TransactionError::ZeroAmount`. A zero deposit or withdrawal is then recorded and can be disputed.
+    pub allow_zero_amounts: bool,
+    /// When set, deposits and withdrawals of more than this amount are rejected with `TransactionError::AmountTooLarge`
+    /// before they touch the balances, to catch obviously bogus rows. An amount equal to it is accepted.
+    pub max_amount: Option<Money>,
+    /// When set, only this many of the most recent deposits and withdrawals of each account can be disputed, which bounds
+    /// the memory used per account. Older ones are final and forgotten, so a dispute of one fails with
+    /// `TransactionError::TransactionDoesNotExist`. A transaction in dispute is kept until the dispute is resolved or
+    /// charged back, and a charged back one is forgotten right away, only its ID is kept to reject another dispute until
+    /// it falls out of the window. `State` still remembers every ID, so they can't be reused, which is the one part
+    /// that keeps growing with the input. A snapshot only has the transactions that weren't forgotten.
+    pub finality_window: Option<usize>,
+}
@@ -0,0 +1,99 @@
+//! Currency codes of multi-currency feeds.
+use std::{fmt, str::FromStr};
+
+use serde::{Deserialize, Serialize, de};
+use thiserror::Error;
+
+/// Maximum length of a currency code.
+const MAX_LEN: usize = 8;
+
+/// A currency code such as `USD`: one to eight ASCII letters or digits, stored uppercase.
+/// It's kept inline, so it's as cheap to copy and store with every transaction as an integer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Currency([u8; MAX_LEN]);
+
+impl Currency {
+    /// Gets the code of the currency.
+    pub fn as_str(&self) -> &str {
+        let len = self.0.iter().position(|byte| *byte == 0).unwrap_or(MAX_LEN);
+        // Safe to unwrap, the code is always ASCII.
+        std::str::from_utf8(&self.0[..len]).unwrap()
+    }
+}
+
+impl FromStr for Currency {
+    type Err = CurrencyError;
+
+    /// Parses a currency code, case-insensitively.
+    fn from_str(code: &str) -> Result<Self, Self::Err> {
+        if code.is_empty()
+            || code.len() > MAX_LEN
+            || !code.bytes().all(|byte| byte.is_ascii_alphanumeric())
+        {
+            return Err(CurrencyError(code.to_string()));
+        }
+        let mut bytes = [0; MAX_LEN];
+        for (byte, code) in bytes.iter_mut().zip(code.bytes()) {
+            *byte = code.to_ascii_uppercase();
+        }
+        Ok(Currency(bytes))
+    }
+}
+
+impl fmt::Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl Serialize for Currency {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Currency {
+    fn deserialize<D: de::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_str(CurrencyVisitor)
+    }
+}
+
+/// Parses a currency code from any string, borrowed or not.
+struct CurrencyVisitor;
+
+impl de::Visitor<'_> for CurrencyVisitor {
+    type Value = Currency;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a currency code")
+    }
+
+    fn visit_str<E: de::Error>(self, code: &str) -> Result<Currency, E> {
+        code.parse().map_err(E::custom)
+    }
+}
+
+/// A string that is not a valid currency code.
+#[derive(Error, Debug, PartialEq)]
+#[error("Invalid currency `{0}`, expected one to eight letters or digits")]
+pub struct CurrencyError(String);
+
+#[cfg(test)]
+mod tests {
+    use crate::bank::Currency;
+
+    #[test]
+    fn test_parse_currency() {
+        let usd = "usd".parse::<Currency>().unwrap();
+        assert_eq!(usd.as_str(), "USD");
+        assert_eq!(usd, "USD".parse().unwrap());
+        assert_eq!(
+            "BTC2TEST".parse::<Currency>().unwrap().to_string(),
+            "BTC2TEST"
+        );
+        assert!("EU" < "EUR" && "EU".parse::<Currency>().unwrap() < "EUR".parse().unwrap());
+        for invalid in ["", "US D", "EURO-1", "TOOLONGXX", "€"] {
+            assert!(invalid.parse::<Currency>().is_err(), "{invalid}");
+        }
+    }
+}
@@ -1,10 +1,38 @@
 //! Banking module for handling accounts, transactions, and state management.
 mod account;
+mod audit;
+mod balances;
+mod batch;
+mod builder;
+mod concurrent;
+mod config;
+mod currency;
+mod money;
+mod observer;
+mod reader;
+mod request;
+mod sharded;
+mod snapshot;
 mod state;
+mod stats;
 mod transaction;
 mod types;
 
 pub use account::*;
+pub use audit::*;
+pub use balances::*;
+pub use batch::*;
+pub use builder::*;
+pub use concurrent::*;
+pub use config::*;
+pub use currency::*;
+pub use money::*;
+pub use observer::*;
+pub use reader::*;
+pub use request::*;
+pub use sharded::*;
+pub use snapshot::*;
 pub use state::*;
+pub use stats::*;
 pub use transaction::*;
 pub use types::*;
@@ -0,0 +1,723 @@
+//! Fixed-point monetary values and their exact conversion from and to decimal strings.
+use std::{
+    fmt,
+    iter::Sum,
+    ops::{Add, AddAssign, Neg, Sub, SubAssign},
+    str::FromStr,
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+use serde::{Deserialize, Serialize, de};
+use thiserror::Error;
+
+/// The representation of a `Money` value: the number of ten-thousandths by default, or with the `decimal` feature
+/// a `Decimal` with four decimal places, which also holds amounts beyond the range of `i64`.
+#[cfg(not(feature = "decimal"))]
+type Repr = i64;
+#[cfg(feature = "decimal")]
+type Repr = rust_decimal::Decimal;
+
+/// A monetary value with four fractional digits, stored exactly as a whole number of ten-thousandths.
+/// It's parsed from and displayed as a decimal string, e.g. `1.5000`, and serialized the same way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Money(Repr);
+
+impl Money {
+    /// Number of fractional digits stored in a `Money` value.
+    pub const DECIMAL_PLACES: usize = 4;
+
+    /// Zero.
+    pub const ZERO: Money = Money::from_raw(0);
+
+    /// The largest representable value.
+    #[cfg(not(feature = "decimal"))]
+    pub const MAX: Money = Money(i64::MAX);
+    #[cfg(feature = "decimal")]
+    pub const MAX: Money = Money::from_mantissa(false);
+
+    /// The smallest representable value.
+    #[cfg(not(feature = "decimal"))]
+    pub const MIN: Money = Money(i64::MIN);
+    #[cfg(feature = "decimal")]
+    pub const MIN: Money = Money::from_mantissa(true);
+
+    /// Creates a value from its raw fixed-point representation, in ten-thousandths (`15000` is `1.5`).
+    #[cfg(not(feature = "decimal"))]
+    pub const fn from_raw(raw: i64) -> Self {
+        Money(raw)
+    }
+
+    /// Creates a value from its raw fixed-point representation, in ten-thousandths (`15000` is `1.5`).
+    #[cfg(feature = "decimal")]
+    pub const fn from_raw(raw: i64) -> Self {
+        let magnitude = raw.unsigned_abs();
+        Money(rust_decimal::Decimal::from_parts(
+            magnitude as u32,
+            (magnitude >> 32) as u32,
+            0,
+            raw < 0,
+            Money::DECIMAL_PLACES as u32,
+        ))
+    }
+
+    /// Creates the value with the largest mantissa `Decimal` holds, positive or `negative`.
+    #[cfg(feature = "decimal")]
+    const fn from_mantissa(negative: bool) -> Self {
+        Money(rust_decimal::Decimal::from_parts(
+            u32::MAX,
+            u32::MAX,
+            u32::MAX,
+            negative,
+            Money::DECIMAL_PLACES as u32,
+        ))
+    }
+
+    /// Creates a value from the decimal digits of its raw representation, returning `None` if it's out of range.
+    #[cfg(not(feature = "decimal"))]
+    fn from_raw_digits(digits: &str) -> Option<Self> {
+        digits.parse().ok().map(Money)
+    }
+
+    /// Creates a value from the decimal digits of its raw representation, returning `None` if it's out of range.
+    #[cfg(feature = "decimal")]
+    fn from_raw_digits(digits: &str) -> Option<Self> {
+        let raw = digits.parse().ok()?;
+        rust_decimal::Decimal::try_from_i128_with_scale(raw, Money::DECIMAL_PLACES as u32)
+            .ok()
+            .map(Money)
+    }
+
+    /// Adds two values, returning `None` if the result would overflow.
+    #[cfg(not(feature = "decimal"))]
+    pub fn checked_add(self, rhs: Money) -> Option<Money> {
+        self.0.checked_add(rhs.0).map(Money)
+    }
+
+    /// Adds two values, returning `None` if the result would overflow or lose a decimal place.
+    #[cfg(feature = "decimal")]
+    pub fn checked_add(self, rhs: Money) -> Option<Money> {
+        Money::exact(self, rhs, self.0.checked_add(rhs.0)?)
+    }
+
+    /// Subtracts two values, returning `None` if the result would overflow.
+    #[cfg(not(feature = "decimal"))]
+    pub fn checked_sub(self, rhs: Money) -> Option<Money> {
+        self.0.checked_sub(rhs.0).map(Money)
+    }
+
+    /// Subtracts two values, returning `None` if the result would overflow or lose a decimal place.
+    #[cfg(feature = "decimal")]
+    pub fn checked_sub(self, rhs: Money) -> Option<Money> {
+        Money::exact(self, rhs, self.0.checked_sub(rhs.0)?)
+    }
+
+    /// Checks that the result of an operation kept every decimal place of its operands.
+    /// `Decimal` rounds a result that doesn't fit its mantissa instead of failing, which money can't afford.
+    #[cfg(feature = "decimal")]
+    fn exact(lhs: Money, rhs: Money, result: rust_decimal::Decimal) -> Option<Money> {
+        (result.scale() >= lhs.0.scale().max(rhs.0.scale())).then_some(Money(result))
+    }
+
+    /// Returns whether the value is below zero.
+    pub fn is_negative(self) -> bool {
+        self < Money::ZERO
+    }
+
+    /// Returns whether the value is zero.
+    pub fn is_zero(self) -> bool {
+        self == Money::ZERO
+    }
+}
+
+impl Default for Money {
+    fn default() -> Self {
+        Money::ZERO
+    }
+}
+
+impl Add for Money {
+    type Output = Money;
+
+    fn add(self, rhs: Money) -> Money {
+        Money(self.0 + rhs.0)
+    }
+}
+
+impl AddAssign for Money {
+    fn add_assign(&mut self, rhs: Money) {
+        self.0 += rhs.0;
+    }
+}
+
+impl Sub for Money {
+    type Output = Money;
+
+    fn sub(self, rhs: Money) -> Money {
+        Money(self.0 - rhs.0)
+    }
+}
+
+impl SubAssign for Money {
+    fn sub_assign(&mut self, rhs: Money) {
+        self.0 -= rhs.0;
+    }
+}
+
+impl Neg for Money {
+    type Output = Money;
+
+    fn neg(self) -> Money {
+        Money(-self.0)
+    }
+}
+
+impl Sum for Money {
+    fn sum<I: Iterator<Item = Money>>(iter: I) -> Money {
+        iter.fold(Money::ZERO, Add::add)
+    }
+}
+
+impl FromStr for Money {
+    type Err = MoneyError;
+
+    /// Parses the value exactly, see `parse_money`.
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        parse_money(input)
+    }
+}
+
+impl TryFrom<f64> for Money {
+    type Error = MoneyError;
+
+    /// Converts the shortest decimal text that reads back as the float, so e.g. `0.1` is exactly `0.1000`.
+    /// Extra fractional digits are handled according to the process-wide `rounding_mode`,
+    /// while infinite and NaN values are rejected as invalid.
+    fn try_from(value: f64) -> Result<Self, Self::Error> {
+        if !value.is_finite() {
+            return Err(MoneyError::InvalidFormat(value.to_string()));
+        }
+        parse_money(&value.to_string())
+    }
+}
+
+impl fmt::Display for Money {
+    /// Formats the value with exactly four fractional digits, e.g. `1.5000`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&FormattedMoney::new(*self, Money::DECIMAL_PLACES), f)
+    }
+}
+
+impl Serialize for Money {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Money {
+    /// Parses the value from its decimal string, so no precision is lost on the way.
+    /// JSON numbers are rejected since they'd have to go through `f64`, `JsonLinesReader` parses them from their raw text instead.
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        deserializer.deserialize_str(MoneyVisitor)
+    }
+}
+
+/// Parses a `Money` value from a borrowed or owned string.
+struct MoneyVisitor;
+
+impl de::Visitor<'_> for MoneyVisitor {
+    type Value = Money;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a decimal amount as a string")
+    }
+
+    fn visit_str<E>(self, value: &str) -> Result<Money, E>
+    where
+        E: de::Error,
+    {
+        parse_money(value).map_err(E::custom)
+    }
+}
+
+/// (De)serializes a `Money` value as its raw number of ten-thousandths, for `#[serde(with = "raw")]`.
+/// Snapshots store amounts this way, so a restored value is exactly the one that was saved.
+pub(crate) mod raw {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    use super::Money;
+
+    #[cfg(not(feature = "decimal"))]
+    pub(crate) fn serialize<S: Serializer>(
+        money: &Money,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_i64(money.0)
+    }
+
+    #[cfg(feature = "decimal")]
+    pub(crate) fn serialize<S: Serializer>(
+        money: &Money,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let mut decimal = money.0;
+        decimal.rescale(Money::DECIMAL_PLACES as u32);
+        serializer.serialize_i128(decimal.mantissa())
+    }
+
+    #[cfg(not(feature = "decimal"))]
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Money, D::Error> {
+        i64::deserialize(deserializer).map(Money)
+    }
+
+    #[cfg(feature = "decimal")]
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Money, D::Error> {
+        let raw = i128::deserialize(deserializer)?;
+        rust_decimal::Decimal::try_from_i128_with_scale(raw, Money::DECIMAL_PLACES as u32)
+            .map(Money)
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// How amounts with more than four fractional digits are parsed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RoundingMode {
+    /// The amount is rejected with `MoneyError::TooManyDecimals`.
+    #[default]
+    Reject,
+    /// The extra digits are dropped, rounding toward zero, e.g. `1.00019` to `1.0001` and `-1.00019` to `-1.0001`.
+    Truncate,
+    /// The amount is rounded to the nearest `Money` unit, and a tie away from zero,
+    /// e.g. `1.00005` to `1.0001` and `-1.00005` to `-1.0001`.
+    HalfUp,
+    /// The amount is rounded to the nearest `Money` unit, and a tie to the even one (banker's rounding),
+    /// e.g. `1.00005` to `1.0000` and `1.00015` to `1.0002`. Negative amounts are rounded the same way as positive ones.
+    HalfEven,
+}
+
+/// The rounding mode used by `parse_money`, as the index of the `RoundingMode` variant.
+static ROUNDING_MODE: AtomicU8 = AtomicU8::new(RoundingMode::Reject as u8);
+
+/// Sets the rounding mode `parse_money` uses from now on, in the whole process.
+/// It applies to everything parsing amounts, e.g. the CSV and JSON Lines readers.
+pub fn set_rounding_mode(mode: RoundingMode) {
+    ROUNDING_MODE.store(mode as u8, Ordering::Relaxed);
+}
+
+/// Gets the rounding mode `parse_money` uses, `RoundingMode::Reject` unless changed with `set_rounding_mode`.
+pub fn rounding_mode() -> RoundingMode {
+    match ROUNDING_MODE.load(Ordering::Relaxed) {
+        mode if mode == RoundingMode::Truncate as u8 => RoundingMode::Truncate,
+        mode if mode == RoundingMode::HalfUp as u8 => RoundingMode::HalfUp,
+        mode if mode == RoundingMode::HalfEven as u8 => RoundingMode::HalfEven,
+        _ => RoundingMode::Reject,
+    }
+}
+
+/// Parses a decimal string (e.g. `1.2345`, `-0.5`, `10`) into a fixed-point `Money` value.
+/// The conversion is exact; inputs with more than four fractional digits are handled according to the process-wide
+/// `rounding_mode`, and rejected by default. Scientific notation (e.g. `1e5`) is not accepted.
+pub fn parse_money(input: &str) -> Result<Money, MoneyError> {
+    parse_money_with(input, rounding_mode())
+}
+
+/// Parses a decimal string like `parse_money`, with the given rounding mode instead of the process-wide one.
+pub fn parse_money_with(input: &str, mode: RoundingMode) -> Result<Money, MoneyError> {
+    let (negative, digits) = match input.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, input.strip_prefix('+').unwrap_or(input)),
+    };
+    let (integer, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+
+    let is_digits = |part: &str| part.bytes().all(|byte| byte.is_ascii_digit());
+    if (integer.is_empty() && fraction.is_empty()) || !is_digits(integer) || !is_digits(fraction) {
+        return Err(MoneyError::InvalidFormat(input.to_string()));
+    }
+    let (fraction, excess) = fraction.split_at(fraction.len().min(Money::DECIMAL_PLACES));
+    if !excess.is_empty() && mode == RoundingMode::Reject {
+        return Err(MoneyError::TooManyDecimals(input.to_string()));
+    }
+
+    let out_of_range = || MoneyError::OutOfRange(input.to_string());
+    let digits = format!(
+        "{integer}{fraction:0<width$}",
+        width = Money::DECIMAL_PLACES
+    );
+    let value = Money::from_raw_digits(&digits).ok_or_else(out_of_range)?;
+    let odd = digits.bytes().last().is_some_and(|digit| digit % 2 == 1);
+    let value = if round_up(mode, odd, excess) {
+        value
+            .checked_add(Money::from_raw(1))
+            .ok_or_else(out_of_range)?
+    } else {
+        value
+    };
+    Ok(if negative { -value } else { value })
+}
+
+/// Decides whether a magnitude, `odd` or not, is rounded up given the `excess` digits cut off after it.
+/// The sign is applied afterwards, so rounding the magnitude up rounds away from zero.
+fn round_up(mode: RoundingMode, odd: bool, excess: &str) -> bool {
+    let mut digits = excess.bytes();
+    match (mode, digits.next()) {
+        (RoundingMode::Reject | RoundingMode::Truncate, _) | (_, None | Some(b'0'..=b'4')) => false,
+        (RoundingMode::HalfEven, Some(b'5')) if digits.all(|digit| digit == b'0') => odd,
+        (_, Some(_)) => true,
+    }
+}
+
+/// A `Money` value that displays and serializes with a fixed number of fractional digits.
+/// With fewer than four digits the value is rounded half away from zero, with more it's padded with zeros.
+#[derive(Debug, Clone, Copy)]
+pub struct FormattedMoney {
+    money: Money,
+    precision: usize,
+}
+
+impl FormattedMoney {
+    /// Wraps the value to be formatted with `precision` fractional digits.
+    pub fn new(money: Money, precision: usize) -> Self {
+        FormattedMoney { money, precision }
+    }
+}
+
+impl fmt::Display for FormattedMoney {
+    #[cfg(not(feature = "decimal"))]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut value = self.money.0.unsigned_abs();
+        let mut digits = Money::DECIMAL_PLACES;
+        if self.precision < Money::DECIMAL_PLACES {
+            let divisor = 10_u64.pow((Money::DECIMAL_PLACES - self.precision) as u32);
+            let remainder = value % divisor;
+            value /= divisor;
+            if remainder * 2 >= divisor {
+                value += 1;
+            }
+            digits = self.precision;
+        }
+        let scale = 10_u64.pow(digits as u32);
+        let sign = if self.money.is_negative() && value != 0 {
+            "-"
+        } else {
+            ""
+        };
+        write!(f, "{sign}{}", value / scale)?;
+        if self.precision > 0 {
+            let fraction = format!("{:0digits$}", value % scale);
+            write!(f, ".{fraction:0<width$}", width = self.precision)?;
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "decimal")]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let rounded = self.money.0.round_dp_with_strategy(
+            self.precision as u32,
+            rust_decimal::RoundingStrategy::MidpointAwayFromZero,
+        );
+        // Rounding may leave a negative zero, which is shown without the sign.
+        let rounded = if rounded.is_zero() {
+            rust_decimal::Decimal::ZERO
+        } else {
+            rounded
+        };
+        write!(f, "{rounded:.precision$}", precision = self.precision)
+    }
+}
+
+impl Serialize for FormattedMoney {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
+/// Errors that can occur while parsing a monetary amount.
+#[derive(Error, Debug, PartialEq)]
+pub enum MoneyError {
+    #[error("Invalid amount `{0}`")]
+    InvalidFormat(String),
+    #[error("Amount `{0}` has more than four decimal places")]
+    TooManyDecimals(String),
+    #[error("Amount `{0}` is out of range")]
+    OutOfRange(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bank::{
+        FormattedMoney, Money, MoneyError, RoundingMode, parse_money, parse_money_with,
+    };
+
+    /// The previous float based conversion, kept to document the values it got wrong.
+    fn parse_money_f64(input: &str) -> Money {
+        Money::from_raw((input.parse::<f64>().unwrap() * 10000.0) as i64)
+    }
+
+    #[test]
+    fn test_parse_money() {
+        let cases = [
+            ("0.0001", 1),
+            ("1", 10000),
+            ("1.", 10000),
+            (".5", 5000),
+            ("+2.5", 25000),
+            ("-2.5", -25000),
+            ("1.1234", 11234),
+            ("1.2345", 12345),
+            ("0.1003", 1003),
+            ("9999999999.9999", 99999999999999),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(
+                parse_money(input),
+                Ok(Money::from_raw(expected)),
+                "input {input}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_money_rejects() {
+        assert!(matches!(
+            parse_money("0.10009"),
+            Err(MoneyError::TooManyDecimals(_))
+        ));
+        assert!(matches!(
+            parse_money("1.23456"),
+            Err(MoneyError::TooManyDecimals(_))
+        ));
+        assert!(matches!(
+            parse_money("1e5"),
+            Err(MoneyError::InvalidFormat(_))
+        ));
+        assert!(matches!(parse_money(""), Err(MoneyError::InvalidFormat(_))));
+        assert!(matches!(
+            parse_money("."),
+            Err(MoneyError::InvalidFormat(_))
+        ));
+        assert!(matches!(
+            parse_money("1.2.3"),
+            Err(MoneyError::InvalidFormat(_))
+        ));
+        assert!(matches!(
+            parse_money("99999999999999999999999999"),
+            Err(MoneyError::OutOfRange(_))
+        ));
+    }
+
+    #[test]
+    fn test_rounding_modes() {
+        for input in ["1.00004", "1.00005", "1.00006"] {
+            assert_eq!(
+                parse_money_with(input, RoundingMode::Reject),
+                Err(MoneyError::TooManyDecimals(input.to_string()))
+            );
+        }
+        let cases = [
+            ("1.00004", 10000),
+            ("1.00005", 10000),
+            ("1.00006", 10001),
+            ("1.00015", 10002),
+            ("1.000050001", 10001),
+            ("-1.00005", -10000),
+            ("-1.00015", -10002),
+            ("0.00005", 0),
+            ("1.0000", 10000),
+            ("922337203685477.58065", i64::MAX - 1),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(
+                parse_money_with(input, RoundingMode::HalfEven),
+                Ok(Money::from_raw(expected)),
+                "input {input}"
+            );
+        }
+        #[cfg(not(feature = "decimal"))]
+        assert!(matches!(
+            parse_money_with("922337203685477.58075", RoundingMode::HalfEven),
+            Err(MoneyError::OutOfRange(_))
+        ));
+    }
+
+    #[test]
+    fn test_rounding_mode_ties() {
+        let inputs = ["0.00005", "0.00015", "-0.00005"];
+        let cases = [
+            (RoundingMode::Truncate, [0, 1, 0]),
+            (RoundingMode::HalfUp, [1, 2, -1]),
+            (RoundingMode::HalfEven, [0, 2, 0]),
+        ];
+        for (mode, expected) in cases {
+            for (input, expected) in inputs.into_iter().zip(expected) {
+                assert_eq!(
+                    parse_money_with(input, mode),
+                    Ok(Money::from_raw(expected)),
+                    "input {input} with {mode:?}"
+                );
+            }
+        }
+        for input in inputs {
+            assert_eq!(
+                parse_money_with(input, RoundingMode::Reject),
+                Err(MoneyError::TooManyDecimals(input.to_string()))
+            );
+        }
+        // Only the digits after the fourth decide, whatever their number.
+        assert_eq!(
+            parse_money_with("-1.00019999", RoundingMode::Truncate),
+            Ok(Money::from_raw(-10001))
+        );
+        assert_eq!(
+            parse_money_with("1.000049999", RoundingMode::HalfUp),
+            Ok(Money::from_raw(10000))
+        );
+    }
+
+    #[test]
+    fn test_parse_money_fixes_float_rounding() {
+        for (input, expected) in [("1.005", 10050), ("0.0003", 3)] {
+            assert_eq!(parse_money(input), Ok(Money::from_raw(expected)));
+            assert_ne!(parse_money_f64(input), Money::from_raw(expected));
+        }
+        // The float path silently truncated extra digits instead of rejecting them.
+        assert_eq!(parse_money_f64("0.10009"), Money::from_raw(1000));
+    }
+
+    #[test]
+    fn test_display_money() {
+        assert_eq!(Money::ZERO.to_string(), "0.0000");
+        assert_eq!(Money::from_raw(1).to_string(), "0.0001");
+        assert_eq!(Money::from_raw(11000).to_string(), "1.1000");
+        assert_eq!(Money::from_raw(-5000).to_string(), "-0.5000");
+        assert_eq!(
+            Money::from_raw(99999999999999).to_string(),
+            "9999999999.9999"
+        );
+        #[cfg(not(feature = "decimal"))]
+        assert_eq!(Money::MIN.to_string(), "-922337203685477.5808");
+        #[cfg(feature = "decimal")]
+        assert_eq!(Money::MIN.to_string(), "-7922816251426433759354395.0335");
+    }
+
+    #[test]
+    fn test_format_parse_round_trip() {
+        for input in ["0.0000", "1.1000", "-0.5000", "0.1003", "9999999999.9999"] {
+            assert_eq!(parse_money(input).unwrap().to_string(), input);
+        }
+    }
+
+    #[test]
+    fn test_money_conversions() {
+        assert_eq!("1.5".parse(), Ok(Money::from_raw(15000)));
+        assert_eq!(Money::try_from(0.1), Ok(Money::from_raw(1000)));
+        assert_eq!(Money::try_from(-2.5), Ok(Money::from_raw(-25000)));
+        assert_eq!(Money::try_from(1e10), Ok(Money::from_raw(100000000000000)));
+        assert!(matches!(
+            Money::try_from(0.1 + 0.2),
+            Err(MoneyError::TooManyDecimals(_))
+        ));
+        assert!(matches!(
+            Money::try_from(1e30),
+            Err(MoneyError::OutOfRange(_))
+        ));
+        assert!(matches!(
+            Money::try_from(f64::NAN),
+            Err(MoneyError::InvalidFormat(_))
+        ));
+
+        let one = Money::from_raw(10000);
+        assert_eq!(one.checked_add(one), Some(Money::from_raw(20000)));
+        assert_eq!(Money::MAX.checked_add(one), None);
+        assert_eq!(Money::MIN.checked_sub(one), None);
+    }
+
+    /// Amounts beyond the range of `i64` are only representable with the `decimal` feature.
+    #[test]
+    fn test_large_amounts() {
+        let large = "1000000000000000.0001";
+        #[cfg(not(feature = "decimal"))]
+        assert!(matches!(parse_money(large), Err(MoneyError::OutOfRange(_))));
+        #[cfg(feature = "decimal")]
+        {
+            let money = parse_money(large).unwrap();
+            assert_eq!(money.to_string(), large);
+            assert_eq!(
+                money.checked_add(money).unwrap().to_string(),
+                "2000000000000000.0002"
+            );
+            let raw = r#"{"amount":10000000000000000001}"#;
+            let snapshot: Raw = serde_json::from_str(raw).unwrap();
+            assert_eq!(snapshot.amount, money);
+            assert_eq!(serde_json::to_string(&snapshot).unwrap(), raw);
+        }
+    }
+
+    /// An amount stored the way snapshots store it.
+    #[cfg(feature = "decimal")]
+    #[derive(serde::Serialize, serde::Deserialize)]
+    struct Raw {
+        #[serde(with = "crate::bank::money::raw")]
+        amount: Money,
+    }
+
+    #[test]
+    fn test_money_serde() {
+        let money = Money::from_raw(15000);
+        assert_eq!(serde_json::to_string(&money).unwrap(), r#""1.5000""#);
+        assert_eq!(serde_json::from_str::<Money>(r#""1.5""#).unwrap(), money);
+        assert!(serde_json::from_str::<Money>("1.5").is_err());
+        assert!(serde_json::from_str::<Money>(r#""1.23456""#).is_err());
+    }
+
+    #[test]
+    fn test_formatted_money_precision() {
+        assert_eq!(
+            FormattedMoney::new(Money::from_raw(15000), 4).to_string(),
+            "1.5000"
+        );
+        assert_eq!(
+            FormattedMoney::new(Money::from_raw(15000), 2).to_string(),
+            "1.50"
+        );
+        assert_eq!(
+            FormattedMoney::new(Money::from_raw(15000), 0).to_string(),
+            "2"
+        );
+        assert_eq!(
+            FormattedMoney::new(Money::from_raw(15000), 6).to_string(),
+            "1.500000"
+        );
+        assert_eq!(
+            FormattedMoney::new(Money::from_raw(12345), 2).to_string(),
+            "1.23"
+        );
+        assert_eq!(
+            FormattedMoney::new(Money::from_raw(12350), 2).to_string(),
+            "1.24"
+        );
+        assert_eq!(
+            FormattedMoney::new(Money::from_raw(-12350), 2).to_string(),
+            "-1.24"
+        );
+        assert_eq!(
+            FormattedMoney::new(Money::from_raw(-1), 2).to_string(),
+            "0.00"
+        );
+        assert_eq!(
+            FormattedMoney::new(Money::from_raw(99999), 2).to_string(),
+            "10.00"
+        );
+    }
+}
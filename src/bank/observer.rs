@@ -0,0 +1,47 @@
+//! Hooks for reacting to the outcome of each processed transaction.
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::mpsc;
+
+use crate::bank::{Account, AuditEntry, ClientId, Transaction, TransactionError};
+
+/// Gets notified about every transaction processed by a `State`, see `State::with_transaction_observer`.
+/// Both methods do nothing by default, so an observer only implements the ones it's interested in.
+pub trait TransactionObserver: Send {
+    /// Called after the transaction was applied to its account, with the balances of the account right after it.
+    fn on_accepted(&mut self, _transaction: &Transaction, _entry: &AuditEntry) {}
+
+    /// Called after the transaction was rejected, with the reason. The accounts are unchanged then.
+    fn on_rejected(&mut self, _transaction: &Transaction, _error: &TransactionError) {}
+}
+
+/// Sends every rejected transaction with its error to the channel. This is what `State::with_error_sink` registers.
+impl TransactionObserver for mpsc::UnboundedSender<(Transaction, TransactionError)> {
+    fn on_rejected(&mut self, transaction: &Transaction, error: &TransactionError) {
+        // The receiving side may be gone, there is nobody else to report to then.
+        let _ = self.send((transaction.clone(), error.clone()));
+    }
+}
+
+/// A callback getting the account changed by every applied transaction, see `State::with_observer`.
+pub(crate) type AccountCallback = Box<dyn FnMut(ClientId, &Account) + Send>;
+
+/// An observer shared by the parts of a split state, each notifying it in turn.
+pub(crate) struct SharedObserver(pub(crate) Arc<Mutex<Box<dyn TransactionObserver>>>);
+
+impl TransactionObserver for SharedObserver {
+    fn on_accepted(&mut self, transaction: &Transaction, entry: &AuditEntry) {
+        lock(&self.0).on_accepted(transaction, entry);
+    }
+
+    fn on_rejected(&mut self, transaction: &Transaction, error: &TransactionError) {
+        lock(&self.0).on_rejected(transaction, error);
+    }
+}
+
+/// Locks a shared observer. A panic in another part's callback doesn't stop the others from being notified.
+pub(crate) fn lock<T: ?Sized>(observer: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    observer
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
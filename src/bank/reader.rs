@@ -0,0 +1,571 @@
+//! Reading transactions from CSV or JSON Lines input, reporting malformed rows instead of dropping them.
+use std::{
+    io::{BufRead, Read},
+    sync::Arc,
+};
+
+use csv::{Reader, ReaderBuilder, StringRecord, Trim};
+use serde::Deserialize;
+use serde_json::value::RawValue;
+use thiserror::Error;
+
+use crate::bank::{ClientId, Currency, Transaction, TransactionId, TransactionType, parse_money};
+
+/// Reads transactions from CSV input row by row.
+/// Every row yields either a transaction or a `ParseError` describing why the row was rejected.
+pub struct TransactionReader<R> {
+    /// The underlying CSV reader.
+    reader: Reader<R>,
+    /// The header row, used to map the columns onto the `Transaction` fields.
+    headers: StringRecord,
+    /// Buffer for the current row.
+    record: StringRecord,
+    /// Set once the underlying reader failed with an I/O error, after which nothing more can be read.
+    failed: bool,
+}
+
+impl<R: Read> TransactionReader<R> {
+    /// Creates a new reader, reading the header row from the input.
+    pub fn new(input: R) -> Result<Self, csv::Error> {
+        // Rows may leave out trailing columns, e.g. `dispute,1,2` without the amount, the same as leaving them empty.
+        let mut reader = ReaderBuilder::new()
+            .trim(Trim::All)
+            .flexible(true)
+            .from_reader(input);
+        let headers = reader.headers()?.clone();
+        Ok(TransactionReader {
+            reader,
+            headers,
+            record: StringRecord::new(),
+            failed: false,
+        })
+    }
+}
+
+impl<R> TransactionReader<R> {
+    /// Gets the header row of the input.
+    pub fn headers(&self) -> &StringRecord {
+        &self.headers
+    }
+}
+
+/// The columns of the CSV input, in any order.
+pub const CSV_COLUMNS: [&str; 4] = ["type", "client", "tx", "amount"];
+
+/// The columns the CSV input may have besides `CSV_COLUMNS`.
+pub const OPTIONAL_CSV_COLUMNS: [&str; 1] = ["currency"];
+
+/// Checks that the header row has all of `CSV_COLUMNS`, in any order and with no other columns than `OPTIONAL_CSV_COLUMNS`.
+/// An empty header row is accepted, it's what an empty input has.
+pub fn check_headers(headers: &StringRecord) -> Result<(), HeaderError> {
+    if headers.is_empty() {
+        return Ok(());
+    }
+    let missing = CSV_COLUMNS
+        .iter()
+        .filter(|column| !headers.iter().any(|header| header == **column))
+        .map(|column| column.to_string())
+        .collect::<Vec<_>>();
+    let unexpected = headers
+        .iter()
+        .filter(|header| !CSV_COLUMNS.contains(header) && !OPTIONAL_CSV_COLUMNS.contains(header))
+        .map(str::to_string)
+        .collect::<Vec<_>>();
+    if missing.is_empty() && unexpected.is_empty() {
+        Ok(())
+    } else {
+        Err(HeaderError {
+            missing,
+            unexpected,
+        })
+    }
+}
+
+/// A CSV header row that doesn't match the expected columns.
+#[derive(Error, Debug, PartialEq)]
+#[error(
+    "Invalid CSV header, expected the columns `type,client,tx,amount` in any order (missing: [{}], unexpected: [{}])",
+    .missing.join(", "),
+    .unexpected.join(", ")
+)]
+pub struct HeaderError {
+    /// The required columns not found in the header.
+    pub missing: Vec<String>,
+    /// The columns in the header that aren't expected.
+    pub unexpected: Vec<String>,
+}
+
+impl<R: Read> Iterator for TransactionReader<R> {
+    type Item = Result<Transaction, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        match self.reader.read_record(&mut self.record) {
+            Ok(false) => None,
+            Ok(true) if self.record.len() > self.headers.len() => {
+                Some(Err(ParseError::with_message(
+                    &self.record,
+                    format!(
+                        "The row has {} fields but the header only {}",
+                        self.record.len(),
+                        self.headers.len()
+                    ),
+                )))
+            }
+            Ok(true) => Some(
+                self.record
+                    .deserialize::<Transaction>(Some(&self.headers))
+                    .map(|transaction| match self.record.position() {
+                        Some(position) => transaction.with_line(position.line()),
+                        None => transaction,
+                    })
+                    .map_err(|err| ParseError::new(&self.record, &err)),
+            ),
+            Err(err) => {
+                self.failed = err.is_io_error();
+                let line = err.position().map(|position| position.line());
+                Some(Err(ParseError {
+                    file: None,
+                    line: line.unwrap_or_default(),
+                    record: String::new(),
+                    message: err.to_string(),
+                }))
+            }
+        }
+    }
+}
+
+/// Reads transactions from JSON Lines input, one JSON object per line.
+/// Blank lines are ignored, every other line yields either a transaction or a `ParseError`.
+pub struct JsonLinesReader<R> {
+    /// The underlying line reader.
+    input: R,
+    /// Buffer for the current line.
+    line: String,
+    /// The 1-based number of the last read line.
+    line_number: u64,
+    /// Set once the input failed with an I/O error, after which nothing more can be read.
+    failed: bool,
+}
+
+/// A transaction as written in JSON Lines input.
+/// The amount is kept as raw JSON, so both numbers (`1.5`) and strings (`"1.5"`) are parsed exactly from their text.
+#[derive(Deserialize)]
+struct JsonTransaction<'a> {
+    #[serde(rename = "type")]
+    tx_type: TransactionType,
+    client: ClientId,
+    tx: TransactionId,
+    #[serde(default, borrow)]
+    amount: Option<&'a RawValue>,
+    #[serde(default)]
+    currency: Option<Currency>,
+}
+
+impl<R: BufRead> JsonLinesReader<R> {
+    /// Creates a new reader over the input.
+    pub fn new(input: R) -> Self {
+        JsonLinesReader {
+            input,
+            line: String::new(),
+            line_number: 0,
+            failed: false,
+        }
+    }
+}
+
+/// Parses a single JSON object into a transaction, the way `JsonLinesReader` parses each line.
+pub fn parse_json_transaction(line: &str) -> Result<Transaction, String> {
+    let row: JsonTransaction = serde_json::from_str(line).map_err(|err| err.to_string())?;
+    let amount = match row.amount.map(RawValue::get) {
+        None | Some("null") => None,
+        Some(raw) => {
+            let text = if raw.starts_with('"') {
+                serde_json::from_str::<String>(raw).map_err(|err| err.to_string())?
+            } else {
+                raw.to_string()
+            };
+            Some(parse_money(&text).map_err(|err| err.to_string())?)
+        }
+    };
+    let transaction = Transaction::new(row.tx_type, row.client, row.tx, amount);
+    Ok(match row.currency {
+        Some(currency) => transaction.with_currency(currency),
+        None => transaction,
+    })
+}
+
+impl<R: BufRead> Iterator for JsonLinesReader<R> {
+    type Item = Result<Transaction, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.failed {
+            self.line.clear();
+            self.line_number += 1;
+            match self.input.read_line(&mut self.line) {
+                Ok(0) => return None,
+                Ok(_) => {
+                    let line = self.line.trim();
+                    if line.is_empty() {
+                        continue;
+                    }
+                    let line_number = self.line_number;
+                    return Some(
+                        parse_json_transaction(line)
+                            .map(|transaction| transaction.with_line(line_number))
+                            .map_err(|message| ParseError {
+                                file: None,
+                                line: line_number,
+                                record: line.to_string(),
+                                message,
+                            }),
+                    );
+                }
+                Err(err) => {
+                    self.failed = true;
+                    return Some(Err(ParseError {
+                        file: None,
+                        line: self.line_number,
+                        record: String::new(),
+                        message: err.to_string(),
+                    }));
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Formats the line of an input row, prefixed with the name of its file if it's known, e.g. `b.csv:3`.
+pub fn line_in_file(file: Option<&str>, line: u64) -> String {
+    match file {
+        Some(file) => format!("{file}:{line}"),
+        None => line.to_string(),
+    }
+}
+
+/// A row that couldn't be parsed into a transaction.
+#[derive(Error, Debug)]
+#[error("Malformed row at line {} `{record}`: {message}", line_in_file(.file.as_deref(), *.line))]
+pub struct ParseError {
+    /// The name of the input file the row is in, when reading several of them.
+    pub file: Option<Arc<str>>,
+    /// The 1-based line number of the row in the input.
+    pub line: u64,
+    /// The raw (trimmed) content of the row.
+    pub record: String,
+    /// Description of what was wrong with the row.
+    pub message: String,
+}
+
+impl ParseError {
+    /// Sets the name of the input file the row is in.
+    pub fn with_file(mut self, file: Arc<str>) -> Self {
+        self.file = Some(file);
+        self
+    }
+
+    /// Creates a parse error for the given record.
+    fn new(record: &StringRecord, err: &csv::Error) -> Self {
+        let message = match err.kind() {
+            csv::ErrorKind::Deserialize { err, .. } => err.to_string(),
+            _ => err.to_string(),
+        };
+        ParseError::with_message(record, message)
+    }
+
+    /// Creates a parse error for the given record with a custom message.
+    fn with_message(record: &StringRecord, message: String) -> Self {
+        ParseError {
+            file: None,
+            line: record
+                .position()
+                .map(|position| position.line())
+                .unwrap_or_default(),
+            record: record.iter().collect::<Vec<_>>().join(","),
+            message,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bank::{
+        Currency, HeaderError, JsonLinesReader, Money, TransactionReader, TransactionType,
+    };
+
+    fn check_headers(input: &str) -> Result<(), HeaderError> {
+        let reader = TransactionReader::new(input.as_bytes()).unwrap();
+        crate::bank::check_headers(reader.headers())
+    }
+
+    #[test]
+    fn test_check_headers() {
+        assert_eq!(
+            check_headers(
+                "type,client,tx,amount
+"
+            ),
+            Ok(())
+        );
+        assert_eq!(
+            check_headers(
+                " amount , tx, client, type
+"
+            ),
+            Ok(())
+        );
+        assert_eq!(check_headers("type,client,tx,amount,currency\n"), Ok(()));
+        assert_eq!(check_headers(""), Ok(()));
+        assert_eq!(
+            check_headers(
+                "type,client,tx
+deposit,1,1
+"
+            ),
+            Err(HeaderError {
+                missing: vec!["amount".to_string()],
+                unexpected: vec![],
+            })
+        );
+        assert_eq!(
+            check_headers(
+                "type,clinet,tx,amount,note,currency
+"
+            ),
+            Err(HeaderError {
+                missing: vec!["client".to_string()],
+                unexpected: vec!["clinet".to_string(), "note".to_string()],
+            })
+        );
+    }
+
+    #[test]
+    fn test_malformed_rows() {
+        let input = "type, client, tx, amount\n\
+                     deposit, 1, 1, 1.0\n\
+                     deposti, 1, 2, 1.0\n\
+                     deposit, 1, 3, abc\n\
+                     deposit, 1, 4, 1.0, 2.0\n\
+                     withdrawal, 1, 5, 0.5\n";
+        let results = TransactionReader::new(input.as_bytes())
+            .unwrap()
+            .collect::<Vec<_>>();
+        assert_eq!(results.len(), 5);
+        assert!(results[0].is_ok());
+        assert!(results[4].is_ok());
+
+        let errors = results
+            .iter()
+            .filter_map(|result| result.as_ref().err())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            errors.iter().map(|err| err.line).collect::<Vec<_>>(),
+            [3, 4, 5]
+        );
+        assert_eq!(errors[0].record, "deposti,1,2,1.0");
+        assert!(errors[0].message.contains("unknown variant `deposti`"));
+        assert!(errors[1].message.contains("Invalid amount `abc`"));
+        assert_eq!(
+            errors[2].message,
+            "The row has 5 fields but the header only 4"
+        );
+
+        let err = results.into_iter().nth(1).unwrap().unwrap_err();
+        assert_eq!(
+            err.with_file("b.csv".into()).to_string(),
+            "Malformed row at line b.csv:3 `deposti,1,2,1.0`: unknown variant `deposti`, expected one of `deposit`, `withdrawal`, `dispute`, `resolve`, `chargeback`, `unlock`, `hold`, `release`, `freeze`, `unfreeze`"
+        );
+    }
+
+    #[test]
+    fn test_missing_amount() {
+        let input = "type, client, tx, amount\n\
+                     deposit, 1, 1,\n\
+                     deposit, 1, 2\n\
+                     deposit, 1, 3,\"\"\n\
+                     deposit, 1, 4, 0.0000\n\
+                     deposit, 1\n";
+        let results = TransactionReader::new(input.as_bytes())
+            .unwrap()
+            .collect::<Vec<_>>();
+        for result in &results[..3] {
+            assert_eq!(result.as_ref().unwrap().get_amount(), None);
+        }
+        assert_eq!(results[3].as_ref().unwrap().get_amount(), Some(Money::ZERO));
+        assert!(
+            results[4]
+                .as_ref()
+                .unwrap_err()
+                .message
+                .contains("expected field, but got end of row")
+        );
+    }
+
+    #[test]
+    fn test_client_id_bounds() {
+        let input = "type, client, tx, amount\n\
+                     deposit, 0, 1, 1.0\n\
+                     deposit, 65535, 2, 1.0\n\
+                     deposit, 65536, 3, 1.0\n\
+                     deposit, -1, 4, 1.0\n";
+        let results = TransactionReader::new(input.as_bytes())
+            .unwrap()
+            .collect::<Vec<_>>();
+        assert_eq!(results[0].as_ref().unwrap().get_client_id(), 0);
+        assert_eq!(results[1].as_ref().unwrap().get_client_id(), u16::MAX);
+        assert_eq!(results[2].as_ref().unwrap_err().line, 4);
+        assert_eq!(results[3].as_ref().unwrap_err().line, 5);
+
+        let input = r#"{"type": "deposit", "client": 0, "tx": 1, "amount": 1}
+{"type": "deposit", "client": 65535, "tx": 2, "amount": 1}
+{"type": "deposit", "client": 65536, "tx": 3, "amount": 1}"#;
+        let results = JsonLinesReader::new(input.as_bytes()).collect::<Vec<_>>();
+        assert_eq!(results[0].as_ref().unwrap().get_client_id(), 0);
+        assert_eq!(results[1].as_ref().unwrap().get_client_id(), u16::MAX);
+        assert!(results[2].is_err());
+    }
+
+    #[test]
+    fn test_type_casing() {
+        let input = "type, client, tx, amount\n\
+                     DEPOSIT, 1, 1, 1.0\n\
+                     Deposit, 1, 2, 1.0\n\
+                     WithDrawal, 1, 3, 1.0\n\
+                     foo, 1, 4, 1.0\n";
+        let results = TransactionReader::new(input.as_bytes())
+            .unwrap()
+            .collect::<Vec<_>>();
+        assert_eq!(
+            results[..3]
+                .iter()
+                .map(|result| *result.as_ref().unwrap().get_type())
+                .collect::<Vec<_>>(),
+            [
+                TransactionType::Deposit,
+                TransactionType::Deposit,
+                TransactionType::Withdrawal
+            ]
+        );
+        let err = results[3].as_ref().unwrap_err();
+        assert_eq!(err.line, 5);
+        assert!(
+            err.message
+                .contains("unknown variant `foo`, expected one of `deposit`")
+        );
+
+        let input = r#"{"type": " Dispute ", "client": 1, "tx": 1}
+{"type": "", "client": 1, "tx": 1}
+"#;
+        let results = JsonLinesReader::new(input.as_bytes()).collect::<Vec<_>>();
+        assert_eq!(
+            results[0].as_ref().unwrap().get_type(),
+            &TransactionType::Dispute
+        );
+        assert!(
+            results[1]
+                .as_ref()
+                .unwrap_err()
+                .message
+                .contains("unknown variant ``")
+        );
+    }
+
+    #[test]
+    fn test_currency_column() {
+        let input = "type, client, tx, amount, currency\n\
+                     deposit, 1, 1, 1.0, usd\n\
+                     deposit, 1, 2, 1.0,\n\
+                     deposit, 1, 3, 1.0, US-D\n";
+        let results = TransactionReader::new(input.as_bytes())
+            .unwrap()
+            .collect::<Vec<_>>();
+        let usd = "USD".parse::<Currency>().unwrap();
+        assert_eq!(results[0].as_ref().unwrap().get_currency(), Some(usd));
+        assert_eq!(results[1].as_ref().unwrap().get_currency(), None);
+        assert!(
+            results[2]
+                .as_ref()
+                .unwrap_err()
+                .message
+                .contains("Invalid currency `US-D`")
+        );
+
+        let input = r#"{"type": "deposit", "client": 1, "tx": 1, "amount": 1, "currency": "USD"}
+{"type": "deposit", "client": 1, "tx": 2, "amount": 1}
+"#;
+        let results = JsonLinesReader::new(input.as_bytes()).collect::<Vec<_>>();
+        assert_eq!(results[0].as_ref().unwrap().get_currency(), Some(usd));
+        assert_eq!(results[1].as_ref().unwrap().get_currency(), None);
+    }
+
+    #[test]
+    fn test_amount_precision() {
+        let input = "type, client, tx, amount\n\
+                     deposit, 1, 1, 1.2345\n\
+                     deposit, 1, 2, 0.0001\n\
+                     deposit, 1, 3, 1.23456\n";
+        let results = TransactionReader::new(input.as_bytes())
+            .unwrap()
+            .collect::<Vec<_>>();
+        assert_eq!(
+            results[0].as_ref().unwrap().get_amount(),
+            Some(Money::from_raw(12345))
+        );
+        assert_eq!(results[0].as_ref().unwrap().get_line(), Some(2));
+        assert_eq!(
+            results[1].as_ref().unwrap().get_amount(),
+            Some(Money::from_raw(1))
+        );
+        let err = results[2].as_ref().unwrap_err();
+        assert_eq!(err.line, 4);
+        assert_eq!(
+            err.message,
+            "Amount `1.23456` has more than four decimal places"
+        );
+    }
+
+    #[test]
+    fn test_json_lines() {
+        let input = r#"{"type": "deposit", "client": 1, "tx": 1, "amount": 1.2345}
+{"type": "deposit", "client": 1, "tx": 2, "amount": "0.0001"}
+
+{"type": "dispute", "client": 1, "tx": 2}
+{"type": "resolve", "client": 1, "tx": 2, "amount": null}
+{"type": "deposit", "client": 1, "tx": 3, "amount": 1.23456}
+{"type": "deposit", "client": 1, "tx": 4, "amount": 1e5}
+{"type": "deposti", "client": 1, "tx": 5, "amount": 1}
+"#;
+        let results = JsonLinesReader::new(input.as_bytes()).collect::<Vec<_>>();
+        assert_eq!(results.len(), 7);
+        let deposit = results[0].as_ref().unwrap();
+        assert_eq!(deposit.get_type(), &TransactionType::Deposit);
+        assert_eq!(deposit.get_amount(), Some(Money::from_raw(12345)));
+        assert_eq!(
+            results[1].as_ref().unwrap().get_amount(),
+            Some(Money::from_raw(1))
+        );
+        let dispute = results[2].as_ref().unwrap();
+        assert_eq!(dispute.get_type(), &TransactionType::Dispute);
+        assert_eq!(dispute.get_amount(), None);
+        assert_eq!(results[3].as_ref().unwrap().get_amount(), None);
+        assert_eq!(results[3].as_ref().unwrap().get_line(), Some(5));
+
+        let errors = results
+            .iter()
+            .filter_map(|result| result.as_ref().err())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            errors.iter().map(|err| err.line).collect::<Vec<_>>(),
+            [6, 7, 8]
+        );
+        assert_eq!(
+            errors[0].message,
+            "Amount `1.23456` has more than four decimal places"
+        );
+        assert_eq!(errors[1].message, "Invalid amount `1e5`");
+        assert!(errors[2].message.contains("unknown variant `deposti`"));
+    }
+}
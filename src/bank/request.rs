@@ -0,0 +1,39 @@
+//! Requests to a `State` running as a service, mixing transactions with queries of the current accounts.
+use tokio::sync::oneshot;
+
+use crate::bank::{ProcessingError, State, Transaction};
+
+/// A query run against the state between two transactions, e.g. to send a copy of the accounts back through a oneshot channel.
+pub type Query = Box<dyn FnOnce(&State) + Send>;
+
+/// A request handled by `State::serve`.
+pub enum Request {
+    /// A transaction, processed the same way as by `State::run`.
+    Transaction(Transaction),
+    /// A transaction whose result is sent back instead of being reported as a failure by the state.
+    Submit(Transaction, oneshot::Sender<Result<(), ProcessingError>>),
+    /// A query, which sees every transaction sent before it.
+    Query(Query),
+}
+
+impl Request {
+    /// Creates a query running `query` on the state, together with the receiver of its result.
+    pub fn query<T: Send + 'static>(
+        query: impl FnOnce(&State) -> T + Send + 'static,
+    ) -> (Self, oneshot::Receiver<T>) {
+        let (reply, result) = oneshot::channel();
+        let query = Box::new(move |state: &State| {
+            // The sender of the query may not wait for the result anymore, there is nobody to answer then.
+            let _ = reply.send(query(state));
+        });
+        (Request::Query(query), result)
+    }
+
+    /// Creates a submission of the transaction, together with the receiver of its result.
+    pub fn submit(
+        transaction: Transaction,
+    ) -> (Self, oneshot::Receiver<Result<(), ProcessingError>>) {
+        let (reply, result) = oneshot::channel();
+        (Request::Submit(transaction, reply), result)
+    }
+}
@@ -0,0 +1,299 @@
+//! Processing transactions in parallel, with the accounts partitioned across worker tasks by client ID.
+use std::{
+    pin::pin,
+    sync::{
+        Arc, Mutex, PoisonError,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+
+use dashmap::{DashMap, mapref::entry::Entry};
+use tokio::{
+    sync::{
+        Notify,
+        mpsc::{
+            self, Permit,
+            error::{SendError, TrySendError},
+        },
+    },
+    task::JoinHandle,
+};
+use tracing::{Instrument, info_span};
+
+use crate::bank::{ClientId, State, Transaction, TransactionId, TransactionType};
+
+/// Routes transactions to worker tasks, each owning the accounts of the clients with `client_id % workers` equal to its index.
+/// Transactions of a client always go to the same worker, so they are processed in the order they were sent.
+///
+/// Transaction IDs are checked across all workers, in the order the transactions were sent, so the accounts and the
+/// rejected transactions are the same as with a single `State` processing them in that order: a deposit or withdrawal
+/// reusing the ID of another client's transaction is rejected as a duplicate, and a dispute referencing it fails with
+/// `DisputeClientMismatch`. A worker waits for the others only when a transaction sent before its own, with the same
+/// ID, is still being processed by another worker.
+/// Failed transactions are reported by each worker as they happen, so their order across workers is not deterministic.
+/// For the same reason, the merged audit log lists the transactions of each worker one worker after another.
+pub struct ShardedState {
+    /// The channels to the workers, indexed by shard, carrying every transaction with its sequence number.
+    senders: Vec<mpsc::Sender<(u64, Transaction)>>,
+    /// The worker tasks, each returning its part of the state once its channel is closed.
+    workers: Vec<JoinHandle<State>>,
+    /// The owners of the transaction IDs, shared by the workers.
+    owners: Arc<SharedOwners>,
+    /// The sequence number of the last sent transaction.
+    sequence: Mutex<u64>,
+}
+
+impl ShardedState {
+    /// Splits the state into `workers` parts and spawns a task processing each of them.
+    /// The configuration and the observers of the state are shared by all workers.
+    pub fn spawn(state: State, workers: usize, channel_size: usize) -> Self {
+        let owners = Arc::new(SharedOwners::new(state.transaction_owners()));
+        let (senders, workers) = state
+            .split(workers)
+            .into_iter()
+            .enumerate()
+            .map(|(index, mut shard)| {
+                let (sender, receiver) = mpsc::channel(channel_size);
+                let owners = owners.clone();
+                let worker = tokio::spawn(
+                    async move {
+                        shard.run_shard(receiver, owners).await;
+                        shard
+                    }
+                    .instrument(info_span!("worker", index)),
+                );
+                (sender, worker)
+            })
+            .unzip();
+        ShardedState {
+            senders,
+            workers,
+            owners,
+            sequence: Mutex::new(0),
+        }
+    }
+
+    /// Sends the transaction to the worker owning its client.
+    pub async fn send(&self, transaction: Transaction) -> Result<(), SendError<Transaction>> {
+        match self.senders[self.shard(&transaction)].reserve().await {
+            Ok(permit) => {
+                self.dispatch(permit, transaction);
+                Ok(())
+            }
+            Err(SendError(())) => Err(SendError(transaction)),
+        }
+    }
+
+    /// Sends the transaction to the worker owning its client, failing instead of waiting if its channel is full.
+    pub fn try_send(&self, transaction: Transaction) -> Result<(), TrySendError<Transaction>> {
+        match self.senders[self.shard(&transaction)].try_reserve() {
+            Ok(permit) => {
+                self.dispatch(permit, transaction);
+                Ok(())
+            }
+            Err(TrySendError::Full(())) => Err(TrySendError::Full(transaction)),
+            Err(TrySendError::Closed(())) => Err(TrySendError::Closed(transaction)),
+        }
+    }
+
+    /// Numbers the transaction and sends it through the reserved slot, claiming the ID of a deposit or withdrawal first.
+    fn dispatch(&self, permit: Permit<'_, (u64, Transaction)>, transaction: Transaction) {
+        // Held until the transaction is sent, so that a worker never sees a transaction before the claims of the ones
+        // sent ahead of it.
+        let mut sequence = self.sequence.lock().unwrap_or_else(PoisonError::into_inner);
+        *sequence += 1;
+        if is_record(transaction.get_type()) {
+            self.owners.claim(
+                transaction.get_transaction_id(),
+                transaction.get_client_id(),
+                *sequence,
+            );
+        }
+        permit.send((*sequence, transaction));
+    }
+
+    /// Returns the index of the worker owning the client of the transaction.
+    fn shard(&self, transaction: &Transaction) -> usize {
+        usize::from(transaction.get_client_id()) % self.senders.len()
+    }
+
+    /// Waits for the workers to process all sent transactions and merges their accounts back into a single state.
+    pub async fn finish(self) -> State {
+        // Closing the channels lets the workers finish.
+        drop(self.senders);
+        let mut shards = Vec::with_capacity(self.workers.len());
+        for worker in self.workers {
+            shards.push(worker.await.expect("A state worker panicked"));
+        }
+        let mut shards = shards.into_iter();
+        // There is always at least one shard, `split` doesn't allow zero.
+        let mut state = shards.next().unwrap();
+        for shard in shards {
+            state.merge(shard);
+        }
+        state
+    }
+}
+
+/// Whether a transaction of the type records its ID, which no other deposit or withdrawal may reuse then.
+pub(crate) fn is_record(tx_type: &TransactionType) -> bool {
+    matches!(
+        tx_type,
+        TransactionType::Deposit | TransactionType::Withdrawal
+    )
+}
+
+/// A deposit or withdrawal sent to a worker, which claims its ID until the worker knows whether it was recorded.
+#[derive(Debug)]
+struct Claim {
+    /// The sequence number of the transaction.
+    sequence: u64,
+    /// The client of the transaction.
+    client_id: ClientId,
+    /// Whether the transaction was recorded, `false` while it's still being processed. A claim of a transaction that
+    /// failed is removed.
+    recorded: bool,
+}
+
+/// The claims on an ID in the order they were made. Almost every ID has a single one, which is kept inline.
+#[derive(Debug)]
+struct Claims {
+    /// The earliest claim.
+    first: Claim,
+    /// The claims made after it.
+    later: Vec<Claim>,
+}
+
+impl Claims {
+    /// Iterates over the claims in the order they were made.
+    fn iter(&self) -> impl Iterator<Item = &Claim> {
+        std::iter::once(&self.first).chain(&self.later)
+    }
+}
+
+/// A claim that is still being processed by its worker.
+struct Pending;
+
+/// The owners of the transaction IDs across the workers of a `ShardedState`.
+/// The router claims the ID of every deposit and withdrawal in the order they are sent, and the worker processing it
+/// resolves the claim. A transaction is checked only against the claims sent before it, once they are resolved, which
+/// gives the same answer as a single `State` processing the transactions in that order.
+#[derive(Debug, Default)]
+pub(crate) struct SharedOwners {
+    /// The claims on every ID, at most one of them recorded.
+    claims: DashMap<TransactionId, Claims>,
+    /// Notified whenever a claim is resolved while a worker is waiting.
+    resolved: Notify,
+    /// The number of workers waiting for a claim to be resolved.
+    waiting: AtomicUsize,
+}
+
+impl SharedOwners {
+    /// Starts with the IDs recorded so far, which come before any transaction sent to the workers.
+    fn new(owners: impl IntoIterator<Item = (TransactionId, ClientId)>) -> Self {
+        SharedOwners {
+            claims: owners
+                .into_iter()
+                .map(|(transaction_id, client_id)| {
+                    let first = Claim {
+                        sequence: 0,
+                        client_id,
+                        recorded: true,
+                    };
+                    let later = Vec::new();
+                    (transaction_id, Claims { first, later })
+                })
+                .collect(),
+            resolved: Notify::new(),
+            waiting: AtomicUsize::new(0),
+        }
+    }
+
+    /// Claims the ID for a deposit or withdrawal about to be sent.
+    fn claim(&self, transaction_id: TransactionId, client_id: ClientId, sequence: u64) {
+        let claim = Claim {
+            sequence,
+            client_id,
+            recorded: false,
+        };
+        match self.claims.entry(transaction_id) {
+            Entry::Occupied(mut entry) => entry.get_mut().later.push(claim),
+            Entry::Vacant(entry) => {
+                entry.insert(Claims {
+                    first: claim,
+                    later: Vec::new(),
+                });
+            }
+        }
+    }
+
+    /// Waits until every claim on the ID by a transaction sent before `sequence` is resolved, and returns the client
+    /// whose claim was recorded, if any. Claims of the same client were resolved by the same worker before, so only the
+    /// ones of other workers can keep it waiting.
+    pub(crate) async fn owner_before(
+        &self,
+        transaction_id: TransactionId,
+        sequence: u64,
+    ) -> Option<ClientId> {
+        if let Ok(owner) = self.recorded_before(transaction_id, sequence) {
+            return owner;
+        }
+        // Counted before checking again, so that a claim resolved after the check sees the waiting worker and notifies it.
+        self.waiting.fetch_add(1, Ordering::SeqCst);
+        let owner = loop {
+            let mut resolved = pin!(self.resolved.notified());
+            // Registered before checking, so that a claim resolved in between isn't missed.
+            resolved.as_mut().enable();
+            if let Ok(owner) = self.recorded_before(transaction_id, sequence) {
+                break owner;
+            }
+            resolved.await;
+        };
+        self.waiting.fetch_sub(1, Ordering::SeqCst);
+        owner
+    }
+
+    /// Gets the client whose claim on the ID by a transaction sent before `sequence` was recorded, or `Pending` if one
+    /// of those claims isn't resolved yet.
+    fn recorded_before(
+        &self,
+        transaction_id: TransactionId,
+        sequence: u64,
+    ) -> Result<Option<ClientId>, Pending> {
+        let Some(claims) = self.claims.get(&transaction_id) else {
+            return Ok(None);
+        };
+        let mut owner = None;
+        for claim in claims.iter().filter(|claim| claim.sequence < sequence) {
+            if !claim.recorded {
+                return Err(Pending);
+            }
+            owner = Some(claim.client_id);
+        }
+        Ok(owner)
+    }
+
+    /// Resolves the claim of the transaction with the sequence number, keeping it if the transaction was recorded.
+    pub(crate) fn resolve(&self, transaction_id: TransactionId, sequence: u64, recorded: bool) {
+        if recorded {
+            if let Some(mut claims) = self.claims.get_mut(&transaction_id) {
+                let Claims { first, later } = &mut *claims;
+                for claim in std::iter::once(first).chain(later) {
+                    claim.recorded |= claim.sequence == sequence;
+                }
+            }
+        } else if let Entry::Occupied(mut entry) = self.claims.entry(transaction_id) {
+            let claims = entry.get_mut();
+            if claims.first.sequence != sequence {
+                claims.later.retain(|claim| claim.sequence != sequence);
+            } else if claims.later.is_empty() {
+                entry.remove();
+            } else {
+                claims.first = claims.later.remove(0);
+            }
+        }
+        if self.waiting.load(Ordering::SeqCst) > 0 {
+            self.resolved.notify_waiters();
+        }
+    }
+}
@@ -0,0 +1,118 @@
+//! Serializable snapshot of the engine state, used to resume processing without replaying the history.
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Write},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::bank::{ClientId, Currency, Money, TransactionId, account::StoredKind, money::raw};
+
+/// The state of every account, including the recorded transactions and open disputes.
+/// Amounts are stored as raw fixed-point values, so a restored state is exactly the one that was saved.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Snapshot {
+    /// The accounts, sorted by client ID.
+    pub(crate) accounts: Vec<AccountSnapshot>,
+}
+
+impl Snapshot {
+    /// Reads a snapshot saved as JSON by `write`.
+    pub fn read(path: &Path) -> Result<Self, SnapshotError> {
+        let file = File::open(path)?;
+        Ok(serde_json::from_reader(BufReader::new(file))?)
+    }
+
+    /// Saves the snapshot as JSON. It's written to a temporary file next to `path` first, which then replaces `path`,
+    /// so an existing snapshot is never left half overwritten.
+    pub fn write(&self, path: &Path) -> Result<(), SnapshotError> {
+        let mut name = path.file_name().unwrap_or_default().to_os_string();
+        name.push(format!(".{}.tmp", std::process::id()));
+        let temp_path = path.with_file_name(name);
+        let result = (|| {
+            let mut writer = BufWriter::new(File::create(&temp_path)?);
+            serde_json::to_writer(&mut writer, self)?;
+            writer.flush()?;
+            writer.get_ref().sync_all()?;
+            fs::rename(&temp_path, path)?;
+            Ok(())
+        })();
+        if result.is_err() {
+            // There is nothing more to do if the temporary file can't be removed either.
+            let _ = fs::remove_file(&temp_path);
+        }
+        result
+    }
+}
+
+/// Errors that can occur while reading or writing a snapshot file.
+#[derive(Error, Debug)]
+pub enum SnapshotError {
+    #[error("Failed to access the snapshot file: {0}")]
+    Io(#[from] io::Error),
+    #[error("Invalid snapshot: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+/// The saved state of a single account.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub(crate) struct AccountSnapshot {
+    pub(crate) client: ClientId,
+    /// The balances of the implicit currency.
+    #[serde(with = "raw")]
+    pub(crate) available: Money,
+    #[serde(with = "raw")]
+    pub(crate) held: Money,
+    #[serde(with = "raw")]
+    pub(crate) total: Money,
+    pub(crate) locked: bool,
+    /// The part of `held` placed by manual holds.
+    #[serde(default, with = "raw")]
+    pub(crate) manual_held: Money,
+    /// The balances of the named currencies, sorted by currency.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) currencies: Vec<BalancesSnapshot>,
+    /// The recorded deposits and withdrawals, in the order they were recorded in.
+    pub(crate) transactions: Vec<TransactionSnapshot>,
+    /// The IDs of the transactions in dispute, sorted.
+    pub(crate) in_dispute: Vec<TransactionId>,
+    /// The IDs of the unknown transactions with a parked dispute, sorted.
+    #[serde(default)]
+    pub(crate) pending_disputes: Vec<TransactionId>,
+    /// The IDs of the transactions that were charged back, sorted.
+    pub(crate) charged_back: Vec<TransactionId>,
+    /// The number of withdrawals rejected for insufficient funds.
+    #[serde(default)]
+    pub(crate) rejected_withdrawals: u64,
+}
+
+/// A recorded deposit or withdrawal.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub(crate) struct TransactionSnapshot {
+    pub(crate) tx: TransactionId,
+    #[serde(rename = "type")]
+    pub(crate) kind: StoredKind,
+    #[serde(with = "raw")]
+    pub(crate) amount: Money,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) currency: Option<Currency>,
+    /// Whether its last dispute was resolved.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub(crate) resolved: bool,
+}
+
+/// The balances of an account in a named currency.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub(crate) struct BalancesSnapshot {
+    pub(crate) currency: Currency,
+    #[serde(with = "raw")]
+    pub(crate) available: Money,
+    #[serde(with = "raw")]
+    pub(crate) held: Money,
+    #[serde(with = "raw")]
+    pub(crate) total: Money,
+    #[serde(with = "raw")]
+    pub(crate) manual_held: Money,
+}
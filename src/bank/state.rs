@@ -1,16 +1,50 @@
 //! The `State` module manages the accounts and processes transactions in a banking system.
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::{Arc, Mutex},
+};
 
+use thiserror::Error;
 use tokio::sync::mpsc;
+use tracing::{instrument, warn};
 
-use crate::bank::{Account, ClientId, Transaction, TransactionError};
+use crate::bank::{
+    Account, AuditEntry, BATCH_SIZE, ClientId, Config, ParseError, Request, Snapshot,
+    SnapshotError, Stats, Transaction, TransactionError, TransactionId, TransactionObserver,
+    TransactionType,
+    observer::{self, AccountCallback, SharedObserver},
+    sharded::{self, SharedOwners},
+};
 
 /// Represents the state of the banking system, including all accounts.
+/// The default state has no receiver and is fed synchronously through `process_all`.
+#[derive(Default)]
 pub struct State {
     /// A map of client IDs to their respective accounts.
     accounts: HashMap<ClientId, Account>,
     /// A channel receiver for processing incoming transactions.
-    receiver: mpsc::Receiver<Transaction>,
+    receiver: Option<mpsc::Receiver<Transaction>>,
+    /// A channel receiver for the input rows that couldn't be parsed, reported alongside the transactions of `receiver`.
+    ingest_errors_receiver: Option<mpsc::UnboundedReceiver<ParseError>>,
+    /// The input rows that couldn't be parsed, in the order they were reported.
+    ingest_errors: Vec<ParseError>,
+    /// The configuration applied to every account.
+    config: Config,
+    /// The client that owns each recorded deposit and withdrawal.
+    /// IDs must stay unique for the whole run, so this grows with every transaction, even with a finality window.
+    transaction_owners: HashMap<TransactionId, ClientId>,
+    /// The client that recorded the ID of the transaction being processed by a worker of a `ShardedState`, as checked
+    /// across all workers. `transaction_owners` is checked when it's not set.
+    checked_owner: Option<Option<ClientId>>,
+    /// Notified about every processed transaction. Errors are logged as `tracing` warnings when not set.
+    observer: Option<Box<dyn TransactionObserver>>,
+    /// Called with the account changed by every applied transaction, see `with_observer`.
+    account_observer: Option<AccountCallback>,
+    /// Every applied transaction in order, only recorded when enabled with `with_audit`.
+    audit_log: Option<Vec<AuditEntry>>,
+    /// Counters of the processed transactions.
+    stats: Stats,
 }
 
 impl State {
@@ -18,15 +52,194 @@ impl State {
     pub fn new(receiver: mpsc::Receiver<Transaction>) -> Self {
         State {
             accounts: HashMap::new(),
-            receiver,
+            receiver: Some(receiver),
+            ..Default::default()
         }
     }
 
+    /// Creates a new instance of `State` without a receiver, to be fed synchronously
+    /// with `process_transaction` or `process_all`. No tokio runtime is needed.
+    ///
+    /// ```
+    /// use bank::{State, Transaction, TransactionType};
+    ///
+    /// let mut state = State::new_standalone();
+    /// state.process_all(
+    ///     [
+    ///         Transaction::new(TransactionType::Deposit, 1, 1, "2".parse().ok()),
+    ///         Transaction::new(TransactionType::Withdrawal, 1, 2, "0.5".parse().ok()),
+    ///     ]
+    ///     .into_iter(),
+    /// );
+    /// assert_eq!(state.get_all_accounts()[&1].available().to_string(), "1.5000");
+    /// ```
+    pub fn new_standalone() -> Self {
+        State::default()
+    }
+
+    /// Restores the state saved by `snapshot`, processing further transactions from the receiver.
+    pub fn restore(snapshot: Snapshot, receiver: mpsc::Receiver<Transaction>) -> Self {
+        State::restore_standalone(snapshot).with_receiver(receiver)
+    }
+
+    /// Restores the state saved by `snapshot`, without a receiver. See `new_standalone`.
+    pub fn restore_standalone(snapshot: Snapshot) -> Self {
+        let mut state = State::default();
+        for account in snapshot.accounts {
+            let account = Account::from_snapshot(account, state.config);
+            let client_id = account.client_id();
+            state.transaction_owners.extend(
+                account
+                    .recorded_transactions()
+                    .map(|transaction_id| (transaction_id, client_id)),
+            );
+            state.accounts.insert(client_id, account);
+        }
+        state
+    }
+
+    /// Captures the state of every account, so processing can later continue from this point with `restore`.
+    /// The configuration isn't part of the snapshot.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            accounts: self
+                .accounts_sorted()
+                .into_iter()
+                .map(Account::snapshot)
+                .collect(),
+        }
+    }
+
+    /// Saves the snapshot of the state to a JSON file, see `Snapshot::write`.
+    pub fn save_snapshot(&self, path: &Path) -> Result<(), SnapshotError> {
+        self.snapshot().write(path)
+    }
+
+    /// Restores a state without a receiver from a snapshot file written by `save_snapshot`.
+    pub fn load_snapshot(path: &Path) -> Result<Self, SnapshotError> {
+        Ok(State::restore_standalone(Snapshot::read(path)?))
+    }
+
+    /// Sets the receiver the transactions are processed from by `run`.
+    pub fn with_receiver(mut self, receiver: mpsc::Receiver<Transaction>) -> Self {
+        self.receiver = Some(receiver);
+        self
+    }
+
+    /// Sets the receiver of the rows that couldn't be parsed, which `run` and `serve` record next to the transactions of the
+    /// receiver, see `record_ingest_error`. An error sent before a transaction is recorded before the transaction is
+    /// processed.
+    pub fn with_ingest_errors(mut self, receiver: mpsc::UnboundedReceiver<ParseError>) -> Self {
+        self.ingest_errors_receiver = Some(receiver);
+        self
+    }
+
+    /// Sets the configuration used for the accounts, including the ones that already exist (e.g. restored ones).
+    pub fn with_config(mut self, config: Config) -> Self {
+        self.config = config;
+        for account in self.accounts.values_mut() {
+            account.set_config(config);
+        }
+        self
+    }
+
+    /// Sends every failed transaction with its error to the given sink, instead of logging it as a `tracing` warning.
+    /// This is a shorthand for `with_transaction_observer` with the sink, so it replaces an observer set before.
+    pub fn with_error_sink(
+        self,
+        error_sink: mpsc::UnboundedSender<(Transaction, TransactionError)>,
+    ) -> Self {
+        self.with_transaction_observer(error_sink)
+    }
+
+    /// Notifies the observer about every transaction processed from now on, accepted or rejected, instead of logging
+    /// the rejected ones as `tracing` warnings. Replaces the observer or error sink set before.
+    ///
+    /// ```
+    /// use bank::{State, Transaction, TransactionError, TransactionObserver, TransactionType};
+    ///
+    /// struct Alerts(Vec<String>);
+    ///
+    /// impl TransactionObserver for Alerts {
+    ///     fn on_rejected(&mut self, transaction: &Transaction, error: &TransactionError) {
+    ///         self.0.push(format!("{}: {error}", transaction.get_transaction_id()));
+    ///     }
+    /// }
+    ///
+    /// let mut state = State::new_standalone().with_transaction_observer(Alerts(vec![]));
+    /// state.process_all(
+    ///     [Transaction::new(TransactionType::Withdrawal, 1, 1, "1".parse().ok())].into_iter(),
+    /// );
+    /// ```
+    pub fn with_transaction_observer(
+        mut self,
+        observer: impl TransactionObserver + 'static,
+    ) -> Self {
+        self.observer = Some(Box::new(observer));
+        self
+    }
+
+    /// Calls `observer` with the client and its account after every transaction applied from now on, e.g. to push
+    /// balance changes to a dashboard. Rejected transactions don't change the account, so it's not called for them.
+    /// Replaces the callback set before, independently of `with_transaction_observer`.
+    ///
+    /// ```
+    /// use std::sync::{Arc, Mutex};
+    ///
+    /// use bank::{State, Transaction, TransactionType};
+    ///
+    /// let totals = Arc::new(Mutex::new(vec![]));
+    /// let mut state = State::new_standalone().with_observer({
+    ///     let totals = totals.clone();
+    ///     move |client, account| totals.lock().unwrap().push((client, account.total().to_string()))
+    /// });
+    /// state.process_all(
+    ///     [Transaction::new(TransactionType::Deposit, 1, 1, "2".parse().ok())].into_iter(),
+    /// );
+    /// assert_eq!(*totals.lock().unwrap(), [(1, "2.0000".to_string())]);
+    /// ```
+    pub fn with_observer(
+        mut self,
+        observer: impl FnMut(ClientId, &Account) + Send + 'static,
+    ) -> Self {
+        self.account_observer = Some(Box::new(observer));
+        self
+    }
+
+    /// Records every successfully applied transaction together with the resulting balances, see `audit_log`.
+    pub fn with_audit(mut self) -> Self {
+        self.audit_log = Some(Vec::new());
+        self
+    }
+
+    /// Returns the applied transactions in the order they were applied, or `None` if auditing isn't enabled.
+    pub fn audit_log(&self) -> Option<&[AuditEntry]> {
+        self.audit_log.as_deref()
+    }
+
+    /// Gets the input rows that couldn't be parsed, as recorded by `record_ingest_error`, `process_rows` and `run`.
+    /// A caller can tell from them whether the whole input made it to the accounts.
+    pub fn ingest_errors(&self) -> &[ParseError] {
+        &self.ingest_errors
+    }
+
+    /// Records an input row that couldn't be parsed, logging it as a `tracing` warning.
+    pub fn record_ingest_error(&mut self, error: ParseError) {
+        warn!(line = error.line, "{error}");
+        self.ingest_errors.push(error);
+    }
+
     /// Retrieves an account by client ID, or creates a new one if it doesn't exist.
     pub fn get_or_create_account(&mut self, client_id: ClientId) -> &mut Account {
-        self.accounts
-            .entry(client_id)
-            .or_insert(Account::new(client_id))
+        self.accounts.entry(client_id).or_insert_with(|| {
+            self.stats.count_account();
+            Account::with_config(client_id, self.config)
+        })
+    }
+
+    /// Retrieves the account of the given client, without creating it if it doesn't exist.
+    pub fn get_account(&self, client_id: ClientId) -> Option<&Account> {
+        self.accounts.get(&client_id)
     }
 
     /// Retrieves all accounts in the state.
@@ -34,25 +247,482 @@ impl State {
         &self.accounts
     }
 
+    /// Retrieves all accounts sorted by client ID, for output that doesn't depend on the hash map order.
+    pub fn accounts_sorted(&self) -> Vec<&Account> {
+        let mut accounts = self.accounts.values().collect::<Vec<_>>();
+        accounts.sort_unstable_by_key(|account| account.client_id());
+        accounts
+    }
+
+    /// Runs `Account::check_invariants` on every account, returning the failures sorted by client ID.
+    pub fn verify_all(&self) -> Result<(), Vec<(ClientId, String)>> {
+        let failures = self
+            .accounts_sorted()
+            .into_iter()
+            .filter_map(|account| {
+                account
+                    .check_invariants()
+                    .err()
+                    .map(|err| (account.client_id(), err))
+            })
+            .collect::<Vec<_>>();
+        if failures.is_empty() {
+            Ok(())
+        } else {
+            Err(failures)
+        }
+    }
+
+    /// Gets the counters of the transactions processed so far, together with the number of currently locked accounts.
+    pub fn stats(&self) -> Stats {
+        let mut stats = self.stats.clone();
+        stats.set_locked_accounts(
+            self.accounts
+                .values()
+                .filter(|account| account.is_locked())
+                .count() as u64,
+        );
+        stats
+    }
+
+    /// Consumes the state, returning all accounts.
+    pub fn into_accounts(self) -> HashMap<ClientId, Account> {
+        self.accounts
+    }
+
     /// Processes a transaction, updating the account state accordingly.
-    fn process_transaction(&mut self, transaction: Transaction) -> Result<(), TransactionError> {
-        let account = self.get_or_create_account(transaction.get_client_id());
-        account.process_transaction(transaction)
+    /// Transaction IDs of deposits and withdrawals must be unique across all clients.
+    /// Disputes, resolves and chargebacks referencing a transaction of another client are rejected.
+    /// Errors are returned with the client, transaction ID and type of the failed transaction attached.
+    /// The observer, if there is one, is notified either way.
+    ///
+    /// ```
+    /// use bank::{Money, State, Transaction, TransactionError, TransactionType};
+    ///
+    /// let mut state = State::new_standalone();
+    /// let deposit = Transaction::new(TransactionType::Deposit, 1, 1, "1".parse().ok());
+    /// state.process_transaction(deposit).unwrap();
+    /// state
+    ///     .process_transaction(Transaction::new(TransactionType::Dispute, 1, 1, None))
+    ///     .unwrap();
+    ///
+    /// let withdrawal = Transaction::new(TransactionType::Withdrawal, 1, 2, "0.5".parse().ok());
+    /// let err = state.process_transaction(withdrawal).unwrap_err();
+    /// assert!(matches!(err.error, TransactionError::InsufficientFunds { .. }));
+    ///
+    /// let account = &state.get_all_accounts()[&1];
+    /// assert_eq!(account.available(), Money::ZERO);
+    /// assert_eq!(account.held(), Money::from_raw(10000));
+    /// ```
+    pub fn process_transaction(&mut self, transaction: Transaction) -> Result<(), ProcessingError> {
+        self.stats.count_transaction(*transaction.get_type());
+        // The transaction is consumed by the account, keep a copy only if someone wants it back.
+        let observed = self.observer.is_some().then(|| transaction.clone());
+        let result = self
+            .apply_transaction(transaction)
+            .inspect_err(|err| self.stats.count_rejection(err.tx_type, &err.error));
+        if let (Some(observer), Some(transaction)) = (&mut self.observer, observed) {
+            match &result {
+                Ok(entry) => observer.on_accepted(&transaction, entry),
+                Err(err) => observer.on_rejected(&transaction, &err.error),
+            }
+        }
+        let entry = result?;
+        if let Some(account_observer) = &mut self.account_observer {
+            account_observer(entry.client_id, &self.accounts[&entry.client_id]);
+        }
+        if let Some(audit_log) = &mut self.audit_log {
+            audit_log.push(entry);
+        }
+        Ok(())
+    }
+
+    /// Applies the transaction to its account, creating the account if needed, and records it.
+    /// Returns the audit entry with the balances of the account right after the transaction.
+    fn apply_transaction(
+        &mut self,
+        transaction: Transaction,
+    ) -> Result<AuditEntry, ProcessingError> {
+        let client_id = transaction.get_client_id();
+        let transaction_id = transaction.get_transaction_id();
+        let tx_type = *transaction.get_type();
+        let line = transaction.get_line();
+        let file = transaction.get_file().cloned();
+        let context = |error| ProcessingError {
+            client_id,
+            transaction_id,
+            tx_type,
+            line,
+            file: file.clone(),
+            error,
+        };
+        let is_record = match tx_type {
+            TransactionType::Deposit | TransactionType::Withdrawal => {
+                if self.owner_of(transaction_id).is_some() {
+                    return Err(context(TransactionError::DuplicateTransactionId));
+                }
+                true
+            }
+            TransactionType::Dispute | TransactionType::Resolve | TransactionType::Chargeback => {
+                if let Some(owner) = self.owner_of(transaction_id)
+                    && owner != client_id
+                {
+                    return Err(context(TransactionError::DisputeClientMismatch));
+                }
+                false
+            }
+            TransactionType::Unlock
+            | TransactionType::Hold
+            | TransactionType::Release
+            | TransactionType::Freeze
+            | TransactionType::Unfreeze => false,
+        };
+
+        let amount = transaction.get_amount();
+        let account = self.get_or_create_account(client_id);
+        let currency = account.currency_of(&transaction);
+        account.process_transaction(transaction).map_err(context)?;
+        let balances = account.balances(currency);
+        let entry = AuditEntry {
+            transaction_id,
+            client_id,
+            tx_type,
+            amount,
+            currency,
+            available: balances.available(),
+            held: balances.held(),
+            total: balances.total(),
+        };
+        if is_record {
+            self.transaction_owners.insert(transaction_id, client_id);
+        }
+        Ok(entry)
+    }
+
+    /// Gets the client that recorded the transaction ID, as checked across the workers of a `ShardedState` if this is
+    /// one of them.
+    fn owner_of(&self, transaction_id: TransactionId) -> Option<ClientId> {
+        match self.checked_owner {
+            Some(owner) => owner,
+            None => self.transaction_owners.get(&transaction_id).copied(),
+        }
+    }
+
+    /// Gets the client that owns each recorded deposit and withdrawal.
+    pub(crate) fn transaction_owners(&self) -> impl Iterator<Item = (TransactionId, ClientId)> {
+        self.transaction_owners
+            .iter()
+            .map(|(transaction_id, client_id)| (*transaction_id, *client_id))
+    }
+
+    /// Creates a state without a receiver from the parts of another one, see `into_parts`.
+    pub(crate) fn from_parts(
+        accounts: HashMap<ClientId, Account>,
+        transaction_owners: HashMap<TransactionId, ClientId>,
+        config: Config,
+        stats: Stats,
+    ) -> Self {
+        State {
+            accounts,
+            transaction_owners,
+            config,
+            stats,
+            ..Default::default()
+        }
+    }
+
+    /// Takes the accounts, the owners of the recorded transactions, the configuration and the counters out of the
+    /// state, dropping the rest.
+    pub(crate) fn into_parts(
+        self,
+    ) -> (
+        HashMap<ClientId, Account>,
+        HashMap<TransactionId, ClientId>,
+        Config,
+        Stats,
+    ) {
+        (
+            self.accounts,
+            self.transaction_owners,
+            self.config,
+            self.stats,
+        )
+    }
+
+    /// Splits the state into `parts` states by `client_id % parts`, each with the same configuration, observers and auditing.
+    /// The parts notify the observers in turn, so they see the transactions of all of them.
+    /// Panics if `parts` is zero.
+    pub(crate) fn split(self, parts: usize) -> Vec<State> {
+        assert!(parts > 0, "A state can't be split into zero parts");
+        let observer = self.observer.map(|observer| Arc::new(Mutex::new(observer)));
+        let account_observer = self
+            .account_observer
+            .map(|observer| Arc::new(Mutex::new(observer)));
+        let mut states = (0..parts)
+            .map(|_| State {
+                config: self.config,
+                observer: observer.clone().map(|observer| {
+                    Box::new(SharedObserver(observer)) as Box<dyn TransactionObserver>
+                }),
+                account_observer: account_observer.clone().map(|shared| {
+                    Box::new(move |client_id, account: &Account| {
+                        observer::lock(&shared)(client_id, account)
+                    }) as AccountCallback
+                }),
+                audit_log: self.audit_log.as_ref().map(|_| Vec::new()),
+                ..Default::default()
+            })
+            .collect::<Vec<_>>();
+        for (client_id, account) in self.accounts {
+            states[usize::from(client_id) % parts]
+                .accounts
+                .insert(client_id, account);
+        }
+        // The entries recorded so far stay first once the parts are merged back, the counters are just summed up.
+        states[0].audit_log = self.audit_log;
+        states[0].stats = self.stats;
+        states[0].ingest_errors = self.ingest_errors;
+        for (transaction_id, client_id) in self.transaction_owners {
+            states[usize::from(client_id) % parts]
+                .transaction_owners
+                .insert(transaction_id, client_id);
+        }
+        states
+    }
+
+    /// Moves the accounts of `other` into this state, appending its audit log. The states are expected to hold different clients.
+    pub(crate) fn merge(&mut self, other: State) {
+        self.accounts.extend(other.accounts);
+        self.transaction_owners.extend(other.transaction_owners);
+        self.stats.merge(&other.stats);
+        self.ingest_errors.extend(other.ingest_errors);
+        if let (Some(audit_log), Some(other)) = (&mut self.audit_log, other.audit_log) {
+            audit_log.extend(other);
+        }
+    }
+
+    /// Processes a transaction, logging a failure as a `tracing` warning unless the observer was notified about it.
+    /// Returns whether the transaction was applied.
+    fn handle_transaction(&mut self, transaction: Transaction) -> bool {
+        let Err(e) = self.process_transaction(transaction) else {
+            return true;
+        };
+        if self.observer.is_none() {
+            warn!(
+                client = e.client_id,
+                tx = e.transaction_id,
+                r#type = %e.tx_type,
+                reason = %e.error,
+                "Transaction rejected"
+            );
+        }
+        false
+    }
+
+    /// Processes all transactions in order without any async machinery.
+    /// This is the cheaper option when all the input is available upfront.
+    #[instrument(skip_all)]
+    pub fn process_all(&mut self, transactions: impl Iterator<Item = Transaction>) {
+        for transaction in transactions {
+            self.handle_transaction(transaction);
+        }
+    }
+
+    /// Processes the parsed input rows in order like `process_all`, recording the ones that couldn't be parsed with
+    /// `record_ingest_error` instead of dropping them.
+    #[instrument(skip_all)]
+    pub fn process_rows(&mut self, rows: impl Iterator<Item = Result<Transaction, ParseError>>) {
+        for row in rows {
+            match row {
+                Ok(transaction) => {
+                    self.handle_transaction(transaction);
+                }
+                Err(error) => self.record_ingest_error(error),
+            }
+        }
+    }
+
+    /// Processes the transactions in order like `process_all`, but stops at the first one that fails and returns it
+    /// with its error. The transactions after it are not taken from the iterator.
+    pub fn try_process_all(
+        &mut self,
+        transactions: impl Iterator<Item = Transaction>,
+    ) -> Result<(), (Transaction, TransactionError)> {
+        for transaction in transactions {
+            self.try_process(transaction)?;
+        }
+        Ok(())
+    }
+
+    /// Processes the transaction, handing it back with the error if it fails.
+    fn try_process(
+        &mut self,
+        transaction: Transaction,
+    ) -> Result<(), (Transaction, TransactionError)> {
+        // The transaction is consumed by the account, keep a copy to return with the error.
+        let failed = transaction.clone();
+        self.process_transaction(transaction)
+            .map_err(|err| (failed, err.error))
     }
 
     /// Runs the state management loop, processing transactions from the receiver.
+    /// Transactions are applied strictly in the order they were sent: the mpsc channel is FIFO and this single loop is
+    /// its only consumer, so the result is the same as `process_all` over the same sequence.
+    /// With several senders the order between them is the order in which their sends completed.
+    /// Up to `BATCH_SIZE` transactions are taken from the channel at once, see `send_batch` for the sending side.
+    /// The rows of `with_ingest_errors` are recorded in between, so `ingest_errors` covers the whole input once it returns.
+    /// Returns immediately if the state was created without a receiver.
+    #[instrument(skip_all)]
     pub async fn run(&mut self) {
-        while let Some(transaction) = self.receiver.recv().await {
-            if let Err(e) = self.process_transaction(transaction) {
-                eprintln!("Error processing transaction: {e}");
+        let Some(mut receiver) = self.receiver.take() else {
+            return;
+        };
+        let mut batch = Vec::with_capacity(BATCH_SIZE);
+        while receiver.recv_many(&mut batch, BATCH_SIZE).await > 0 {
+            for transaction in batch.drain(..) {
+                self.receive_ingest_errors();
+                self.handle_transaction(transaction);
+            }
+        }
+        self.receive_ingest_errors();
+        self.receiver = Some(receiver);
+    }
+
+    /// Runs the loop of a worker of a `ShardedState` like `run`, with the transaction IDs checked against the owners
+    /// shared by all workers. Every transaction comes with its sequence number, and waits for the deposits and
+    /// withdrawals with the same ID sent before it to be resolved by their workers.
+    #[instrument(skip_all)]
+    pub(crate) async fn run_shard(
+        &mut self,
+        mut receiver: mpsc::Receiver<(u64, Transaction)>,
+        owners: Arc<SharedOwners>,
+    ) {
+        let mut batch = Vec::with_capacity(BATCH_SIZE);
+        while receiver.recv_many(&mut batch, BATCH_SIZE).await > 0 {
+            for (sequence, transaction) in batch.drain(..) {
+                let transaction_id = transaction.get_transaction_id();
+                let tx_type = *transaction.get_type();
+                if matches!(
+                    tx_type,
+                    TransactionType::Deposit
+                        | TransactionType::Withdrawal
+                        | TransactionType::Dispute
+                        | TransactionType::Resolve
+                        | TransactionType::Chargeback
+                ) {
+                    self.checked_owner = Some(owners.owner_before(transaction_id, sequence).await);
+                }
+                let applied = self.handle_transaction(transaction);
+                self.checked_owner = None;
+                if sharded::is_record(&tx_type) {
+                    owners.resolve(transaction_id, sequence, applied);
+                }
             }
         }
     }
+
+    /// Records the rows that couldn't be parsed which were sent so far through the receiver of `with_ingest_errors`.
+    fn receive_ingest_errors(&mut self) {
+        let Some(receiver) = &mut self.ingest_errors_receiver else {
+            return;
+        };
+        let errors = std::iter::from_fn(|| receiver.try_recv().ok()).collect::<Vec<_>>();
+        for error in errors {
+            self.record_ingest_error(error);
+        }
+    }
+
+    /// Runs the loop like `run`, but stops at the first transaction that fails and returns it with its error.
+    /// The receiver is dropped then, so the senders see a closed channel instead of filling it with transactions
+    /// that would never be processed.
+    #[instrument(skip_all)]
+    pub async fn run_strict(&mut self) -> Result<(), (Transaction, TransactionError)> {
+        let Some(mut receiver) = self.receiver.take() else {
+            return Ok(());
+        };
+        let mut batch = Vec::with_capacity(BATCH_SIZE);
+        while receiver.recv_many(&mut batch, BATCH_SIZE).await > 0 {
+            for transaction in batch.drain(..) {
+                self.try_process(transaction)?;
+            }
+        }
+        self.receiver = Some(receiver);
+        Ok(())
+    }
+
+    /// Processes requests until the channel is closed, like `run` does with transactions, answering the queries
+    /// in between. A query sees all the transactions sent before it, since they are handled in order.
+    /// The rows of `with_ingest_errors` are recorded in between too.
+    #[instrument(skip_all)]
+    pub async fn serve(&mut self, mut requests: mpsc::Receiver<Request>) {
+        while let Some(request) = requests.recv().await {
+            self.receive_ingest_errors();
+            match request {
+                Request::Transaction(transaction) => {
+                    self.handle_transaction(transaction);
+                }
+                Request::Submit(transaction, reply) => {
+                    // The submitter may not wait for the result anymore, there is nobody to report to then.
+                    let _ = reply.send(self.process_transaction(transaction));
+                }
+                Request::Query(query) => query(self),
+            }
+        }
+        self.receive_ingest_errors();
+    }
+}
+
+/// The error of a failed transaction, together with the transaction it failed for.
+#[derive(Error, Debug)]
+#[error("{tx_type} {transaction_id} for client {client_id}: {error}")]
+pub struct ProcessingError {
+    /// The client of the failed transaction.
+    pub client_id: ClientId,
+    /// The ID of the failed transaction.
+    pub transaction_id: TransactionId,
+    /// The type of the failed transaction.
+    pub tx_type: TransactionType,
+    /// The line of the input the failed transaction was read from, if known.
+    pub line: Option<u64>,
+    /// The name of the input file the failed transaction was read from, when reading several of them.
+    pub file: Option<Arc<str>>,
+    /// The reason the transaction failed.
+    #[source]
+    pub error: TransactionError,
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::bank::{Transaction, TransactionType};
+    use tokio::sync::mpsc;
+
+    use crate::bank::{
+        AuditEntry, Money, ProcessingError, Request, SnapshotError, State, Transaction,
+        TransactionError, TransactionReader, TransactionType,
+    };
+
+    /// Generates a deterministic mix of deposits and withdrawals over a few hundred clients.
+    fn generate_transactions(count: u32) -> Vec<Transaction> {
+        (0..count)
+            .map(|tx| {
+                let client = (tx % 500) as u16;
+                if tx % 3 == 2 {
+                    Transaction::new(
+                        TransactionType::Withdrawal,
+                        client,
+                        tx,
+                        Some(Money::from_raw(5000)),
+                    )
+                } else {
+                    Transaction::new(
+                        TransactionType::Deposit,
+                        client,
+                        tx,
+                        Some(Money::from_raw(10000)),
+                    )
+                }
+            })
+            .collect()
+    }
 
     #[tokio::test]
     async fn test_account_creation() {
@@ -60,7 +730,12 @@ mod tests {
         let mut state = super::State::new(receiver);
         assert!(state.get_all_accounts().is_empty());
         sender
-            .send(Transaction::new(TransactionType::Deposit, 1, 1, Some(1000)))
+            .send(Transaction::new(
+                TransactionType::Deposit,
+                1,
+                1,
+                Some(Money::from_raw(1000)),
+            ))
             .await
             .unwrap();
         drop(sender); // Close the sender to signal no more transactions will be sent
@@ -69,4 +744,559 @@ mod tests {
         assert_eq!(accounts.len(), 1);
         assert!(accounts.contains_key(&1));
     }
+
+    #[tokio::test]
+    async fn test_negative_amount_is_skipped() {
+        let (sender, receiver) = tokio::sync::mpsc::channel(100);
+        let mut state = super::State::new(receiver);
+        sender
+            .send(Transaction::new(
+                TransactionType::Deposit,
+                1,
+                1,
+                Some(Money::from_raw(-1000)),
+            ))
+            .await
+            .unwrap();
+        sender
+            .send(Transaction::new(
+                TransactionType::Deposit,
+                1,
+                2,
+                Some(Money::from_raw(1000)),
+            ))
+            .await
+            .unwrap();
+        drop(sender);
+        state.run().await;
+        let mut writer = csv::Writer::from_writer(vec![]);
+        writer.serialize(&state.get_all_accounts()[&1]).unwrap();
+        let output = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+        assert_eq!(
+            output,
+            "client,available,held,total,locked\n1,0.1000,0.0000,0.1000,false\n"
+        );
+    }
+
+    #[test]
+    fn test_process_all() {
+        let mut state = State::default();
+        state.process_all(generate_transactions(1500).into_iter());
+        let accounts = state.get_all_accounts();
+        assert_eq!(accounts.len(), 500);
+        let mut writer = csv::Writer::from_writer(vec![]);
+        writer.serialize(&accounts[&0]).unwrap();
+        let output = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+        // Client 0 gets tx 0 (deposit), 500 (withdrawal) and 1000 (deposit).
+        assert_eq!(
+            output,
+            "client,available,held,total,locked\n0,1.5000,0.0000,1.5000,false\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_ingest_errors() {
+        let input = "type, client, tx, amount\n\
+                     deposit, 1, 1, 2.0\n\
+                     deposit, 1, 2, two\n\
+                     withdrawal, 1, 3, 0.5\n\
+                     refund, 1, 4, 1.0\n";
+        let rows = || TransactionReader::new(input.as_bytes()).unwrap();
+        let lines = |state: &State| {
+            state
+                .ingest_errors()
+                .iter()
+                .map(|err| err.line)
+                .collect::<Vec<_>>()
+        };
+
+        let mut state = State::new_standalone();
+        state.process_rows(rows());
+        assert_eq!(lines(&state), [3, 5]);
+        assert_eq!(state.stats().transactions(), 2);
+
+        let (sender, receiver) = mpsc::channel(10);
+        let (errors_sender, errors_receiver) = mpsc::unbounded_channel();
+        let mut channel_state = State::new(receiver).with_ingest_errors(errors_receiver);
+        for row in rows() {
+            match row {
+                Ok(transaction) => sender.send(transaction).await.unwrap(),
+                Err(err) => errors_sender.send(err).unwrap(),
+            }
+        }
+        drop(sender);
+        channel_state.run().await;
+        assert_eq!(lines(&channel_state), [3, 5]);
+        assert_eq!(channel_state.snapshot(), state.snapshot());
+    }
+
+    #[tokio::test]
+    async fn test_run_without_receiver() {
+        let mut state = State::default();
+        state.run().await;
+        assert!(state.get_all_accounts().is_empty());
+    }
+
+    #[test]
+    fn test_dispute_client_mismatch() {
+        let mut state = State::default();
+        assert!(
+            state
+                .process_transaction(Transaction::new(
+                    TransactionType::Deposit,
+                    1,
+                    1,
+                    Some(Money::from_raw(1000))
+                ))
+                .is_ok()
+        );
+        for tx_type in [
+            TransactionType::Dispute,
+            TransactionType::Resolve,
+            TransactionType::Chargeback,
+        ] {
+            assert!(matches!(
+                state.process_transaction(Transaction::new(tx_type, 2, 1, None)),
+                Err(ProcessingError {
+                    error: TransactionError::DisputeClientMismatch,
+                    ..
+                })
+            ));
+        }
+        // The mismatched disputes must not create an account for the other client.
+        assert_eq!(state.get_all_accounts().len(), 1);
+        assert!(
+            state
+                .process_transaction(Transaction::new(TransactionType::Dispute, 1, 1, None))
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_dispute_unknown_transaction() {
+        let mut state = State::default();
+        assert!(matches!(
+            state.process_transaction(Transaction::new(TransactionType::Dispute, 2, 1, None)),
+            Err(ProcessingError {
+                error: TransactionError::TransactionDoesNotExist,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_error_context() {
+        let mut state = State::default();
+        let err = state
+            .process_transaction(Transaction::new(
+                TransactionType::Withdrawal,
+                7,
+                42,
+                Some(Money::from_raw(1000)),
+            ))
+            .unwrap_err();
+        assert_eq!(err.client_id, 7);
+        assert_eq!(err.transaction_id, 42);
+        assert_eq!(err.tx_type, TransactionType::Withdrawal);
+        assert!(matches!(
+            err.error,
+            TransactionError::InsufficientFunds { .. }
+        ));
+        assert_eq!(
+            err.to_string(),
+            "withdrawal 42 for client 7: Insufficient funds for transaction: requested 0.1000 but only 0.0000 available"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_run_strict() {
+        let transactions = [
+            Transaction::new(TransactionType::Deposit, 1, 1, Some(Money::from_raw(1000))),
+            Transaction::new(
+                TransactionType::Withdrawal,
+                1,
+                2,
+                Some(Money::from_raw(5000)),
+            )
+            .with_line(3),
+            Transaction::new(TransactionType::Deposit, 1, 3, Some(Money::from_raw(1000))),
+        ];
+        let mut state = State::default();
+        let (transaction, error) = state
+            .try_process_all(transactions.iter().cloned())
+            .unwrap_err();
+        assert_eq!(
+            (transaction.get_transaction_id(), transaction.get_line()),
+            (2, Some(3))
+        );
+        assert_eq!(transaction.get_amount(), Some(Money::from_raw(5000)));
+        assert!(matches!(error, TransactionError::InsufficientFunds { .. }));
+        assert_eq!(
+            state.get_account(1).unwrap().available(),
+            Money::from_raw(1000)
+        );
+
+        let (sender, receiver) = mpsc::channel(10);
+        let mut state = State::new(receiver);
+        for transaction in &transactions[..2] {
+            sender.send(transaction.clone()).await.unwrap();
+        }
+        let (transaction, error) = state.run_strict().await.unwrap_err();
+        assert_eq!(transaction.get_transaction_id(), 2);
+        assert!(matches!(error, TransactionError::InsufficientFunds { .. }));
+        // Nothing is processed after the failure, the channel is closed instead.
+        assert!(sender.send(transactions[2].clone()).await.is_err());
+        assert_eq!(
+            state.get_account(1).unwrap().available(),
+            Money::from_raw(1000)
+        );
+
+        let (sender, receiver) = mpsc::channel(10);
+        let mut state = State::new(receiver);
+        sender.send(transactions[0].clone()).await.unwrap();
+        drop(sender);
+        assert!(state.run_strict().await.is_ok());
+
+        // The lenient loop skips the failed withdrawal and processes the rest of the same input.
+        let (sender, receiver) = mpsc::channel(10);
+        let mut state = State::new(receiver);
+        for transaction in &transactions {
+            sender.send(transaction.clone()).await.unwrap();
+        }
+        drop(sender);
+        state.run().await;
+        assert_eq!(
+            state.get_account(1).unwrap().available(),
+            Money::from_raw(2000)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_error_sink() {
+        let (sender, receiver) = mpsc::channel(100);
+        let (error_sender, mut error_receiver) = mpsc::unbounded_channel();
+        let mut state = State::new(receiver).with_error_sink(error_sender);
+        sender
+            .send(Transaction::new(
+                TransactionType::Deposit,
+                1,
+                1,
+                Some(Money::from_raw(1000)),
+            ))
+            .await
+            .unwrap();
+        sender
+            .send(Transaction::new(
+                TransactionType::Withdrawal,
+                1,
+                2,
+                Some(Money::from_raw(5000)),
+            ))
+            .await
+            .unwrap();
+        drop(sender);
+        state.run().await;
+        drop(state);
+
+        let (transaction, error) = error_receiver.recv().await.unwrap();
+        assert_eq!(transaction.get_transaction_id(), 2);
+        assert!(matches!(error, TransactionError::InsufficientFunds { .. }));
+        assert!(error_receiver.recv().await.is_none());
+    }
+
+    #[test]
+    fn test_duplicate_transaction_id_across_clients() {
+        let mut state = State::default();
+        assert!(
+            state
+                .process_transaction(Transaction::new(
+                    TransactionType::Deposit,
+                    1,
+                    7,
+                    Some(Money::from_raw(1000))
+                ))
+                .is_ok()
+        );
+        for tx_type in [TransactionType::Deposit, TransactionType::Withdrawal] {
+            assert!(matches!(
+                state.process_transaction(Transaction::new(
+                    tx_type,
+                    2,
+                    7,
+                    Some(Money::from_raw(500))
+                )),
+                Err(ProcessingError {
+                    error: TransactionError::DuplicateTransactionId,
+                    ..
+                })
+            ));
+        }
+        assert_eq!(state.get_all_accounts().len(), 1);
+
+        // A dispute of tx 7 is unambiguous and belongs to client 1.
+        assert!(
+            state
+                .process_transaction(Transaction::new(TransactionType::Dispute, 1, 7, None))
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_rejected_transaction_id_can_be_reused() {
+        let mut state = State::default();
+        assert!(
+            state
+                .process_transaction(Transaction::new(
+                    TransactionType::Withdrawal,
+                    1,
+                    7,
+                    Some(Money::from_raw(1000))
+                ))
+                .is_err()
+        );
+        assert!(
+            state
+                .process_transaction(Transaction::new(
+                    TransactionType::Deposit,
+                    2,
+                    7,
+                    Some(Money::from_raw(1000))
+                ))
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_accounts_sorted() {
+        let mut state = State::default();
+        state.process_all(
+            [300, 7, 65535, 0, 42]
+                .into_iter()
+                .zip(1..)
+                .map(|(client, tx)| {
+                    Transaction::new(
+                        TransactionType::Deposit,
+                        client,
+                        tx,
+                        Some(Money::from_raw(1000)),
+                    )
+                }),
+        );
+        let clients = state
+            .accounts_sorted()
+            .iter()
+            .map(|account| account.client_id())
+            .collect::<Vec<_>>();
+        assert_eq!(clients, [0, 7, 42, 300, 65535]);
+    }
+
+    #[test]
+    fn test_get_account() {
+        let mut state = State::default();
+        state.process_all(
+            [
+                Transaction::new(TransactionType::Deposit, 1, 1, Some(Money::from_raw(15000))),
+                Transaction::new(
+                    TransactionType::Withdrawal,
+                    1,
+                    2,
+                    Some(Money::from_raw(5000)),
+                ),
+            ]
+            .into_iter(),
+        );
+        let account = state.get_account(1).unwrap();
+        assert_eq!(account.client_id(), 1);
+        assert_eq!(account.available(), Money::from_raw(10000));
+        assert_eq!(account.held(), Money::ZERO);
+        assert_eq!(account.total(), Money::from_raw(10000));
+        assert!(!account.is_locked());
+
+        assert!(state.get_account(2).is_none());
+        assert_eq!(state.get_all_accounts().len(), 1);
+    }
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let mut transactions = generate_transactions(3000);
+        // Disputes spanning the snapshot, some settled before it and some after.
+        for (tx, kind) in [
+            (10, TransactionType::Dispute),
+            (20, TransactionType::Dispute),
+            (10, TransactionType::Chargeback),
+            (1700, TransactionType::Dispute),
+            (20, TransactionType::Resolve),
+            (1700, TransactionType::Chargeback),
+            (10, TransactionType::Dispute),
+        ] {
+            let client = (tx % 500) as u16;
+            transactions.insert(tx as usize + 50, Transaction::new(kind, client, tx, None));
+        }
+        let (first, second) = transactions.split_at(1500);
+
+        let mut replayed = State::default();
+        replayed.process_all(transactions.iter().cloned());
+
+        let mut state = State::default();
+        state.process_all(first.iter().cloned());
+        let json = serde_json::to_string(&state.snapshot()).unwrap();
+        let mut restored = State::restore_standalone(serde_json::from_str(&json).unwrap());
+        assert_eq!(restored.snapshot(), state.snapshot());
+        restored.process_all(second.iter().cloned());
+
+        assert_eq!(restored.snapshot(), replayed.snapshot());
+        // Transaction IDs recorded before the snapshot are still known.
+        assert!(matches!(
+            restored.process_transaction(Transaction::new(
+                TransactionType::Deposit,
+                1,
+                1,
+                Some(Money::from_raw(1))
+            )),
+            Err(ProcessingError {
+                error: TransactionError::DuplicateTransactionId,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_audit_log() {
+        let mut state = State::default().with_audit();
+        state.process_all(
+            [
+                Transaction::new(TransactionType::Deposit, 1, 1, Some(Money::from_raw(20000))),
+                Transaction::new(
+                    TransactionType::Withdrawal,
+                    1,
+                    2,
+                    Some(Money::from_raw(50000)),
+                ),
+                Transaction::new(
+                    TransactionType::Withdrawal,
+                    1,
+                    3,
+                    Some(Money::from_raw(5000)),
+                ),
+                Transaction::new(TransactionType::Dispute, 1, 1, None),
+            ]
+            .into_iter(),
+        );
+        let audit_log = state.audit_log().unwrap();
+        assert_eq!(audit_log.len(), 3);
+        assert_eq!(
+            audit_log[1],
+            AuditEntry {
+                transaction_id: 3,
+                client_id: 1,
+                tx_type: TransactionType::Withdrawal,
+                amount: Some(Money::from_raw(5000)),
+                currency: None,
+                available: Money::from_raw(15000),
+                held: Money::ZERO,
+                total: Money::from_raw(15000),
+            }
+        );
+        assert_eq!(
+            (
+                audit_log[2].available,
+                audit_log[2].held,
+                audit_log[2].total
+            ),
+            (
+                Money::from_raw(-5000),
+                Money::from_raw(20000),
+                Money::from_raw(15000)
+            )
+        );
+
+        assert!(State::default().audit_log().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_serve() {
+        let (sender, receiver) = mpsc::channel(10);
+        let handle = tokio::spawn(async move {
+            let mut state = State::new_standalone();
+            state.serve(receiver).await;
+            state
+        });
+        let balance = |sender: mpsc::Sender<Request>| async move {
+            let (query, balance) = Request::query(|state: &State| {
+                state.get_account(1).map(|account| account.available())
+            });
+            sender.send(query).await.unwrap();
+            balance.await.unwrap()
+        };
+
+        assert_eq!(balance(sender.clone()).await, None);
+        for tx in 1..=3 {
+            let deposit = Transaction::new(
+                TransactionType::Deposit,
+                1,
+                tx,
+                Some(Money::from_raw(10000)),
+            );
+            sender.send(Request::Transaction(deposit)).await.unwrap();
+        }
+        assert_eq!(balance(sender.clone()).await, Some(Money::from_raw(30000)));
+        let (submit, result) = Request::submit(Transaction::new(
+            TransactionType::Withdrawal,
+            1,
+            4,
+            Some(Money::from_raw(40000)),
+        ));
+        sender.send(submit).await.unwrap();
+        assert!(matches!(
+            result.await.unwrap(),
+            Err(ProcessingError {
+                error: TransactionError::InsufficientFunds { .. },
+                ..
+            })
+        ));
+        drop(sender);
+        assert_eq!(
+            handle.await.unwrap().get_account(1).unwrap().total(),
+            Money::from_raw(30000)
+        );
+    }
+
+    #[test]
+    fn test_verify_all() {
+        let mut state = State::default();
+        state.process_all(generate_transactions(1000).into_iter());
+        assert_eq!(state.verify_all(), Ok(()));
+
+        let mut snapshot = state.snapshot();
+        snapshot.accounts[3].total += Money::from_raw(1);
+        let client = snapshot.accounts[3].client;
+        let failures = State::restore_standalone(snapshot)
+            .verify_all()
+            .unwrap_err();
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].0, client);
+    }
+
+    #[test]
+    fn test_snapshot_file() {
+        let path = std::env::temp_dir().join(format!("bank-snapshot-{}.json", std::process::id()));
+        let transactions = generate_transactions(1000);
+        let (first, second) = transactions.split_at(500);
+
+        let mut state = State::default();
+        state.process_all(first.iter().cloned());
+        state.save_snapshot(&path).unwrap();
+        let mut restored = State::load_snapshot(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        restored.process_all(second.iter().cloned());
+
+        let mut replayed = State::default();
+        replayed.process_all(transactions.into_iter());
+        assert_eq!(restored.snapshot(), replayed.snapshot());
+
+        assert!(matches!(
+            State::load_snapshot(&path),
+            Err(SnapshotError::Io(_))
+        ));
+    }
 }
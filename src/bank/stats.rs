@@ -0,0 +1,93 @@
+//! Counters of the transactions processed by a `State`.
+use std::collections::BTreeMap;
+
+use crate::bank::{TransactionError, TransactionType};
+
+/// Counts of the transactions a `State` processed and rejected, and of the accounts it created.
+/// The counters cover the transactions processed since the state was created or restored, they are not part of a snapshot.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// Processed transactions by type, indexed like `TransactionType::ALL`.
+    by_type: [u64; TransactionType::ALL.len()],
+    /// Rejected transactions by type, indexed like `TransactionType::ALL`.
+    failed_by_type: [u64; TransactionType::ALL.len()],
+    /// Rejected transactions by the name of the error variant.
+    rejections: BTreeMap<&'static str, u64>,
+    /// Accounts created by the processed transactions.
+    accounts_created: u64,
+    /// Accounts locked at the time the stats were taken.
+    locked_accounts: u64,
+}
+
+impl Stats {
+    /// Gets the number of processed transactions, including the rejected ones.
+    pub fn transactions(&self) -> u64 {
+        self.by_type.iter().sum()
+    }
+
+    /// Gets the number of processed transactions of the given type, including the rejected ones.
+    pub fn count(&self, tx_type: TransactionType) -> u64 {
+        self.by_type[tx_type as usize]
+    }
+
+    /// Gets the number of rejected transactions of the given type.
+    pub fn failed(&self, tx_type: TransactionType) -> u64 {
+        self.failed_by_type[tx_type as usize]
+    }
+
+    /// Gets the number of rejected transactions.
+    pub fn rejected(&self) -> u64 {
+        self.rejections.values().sum()
+    }
+
+    /// Gets the number of rejected transactions by the name of the `TransactionError` variant, e.g. `InsufficientFunds`.
+    pub fn rejections(&self) -> &BTreeMap<&'static str, u64> {
+        &self.rejections
+    }
+
+    /// Gets the number of accounts created by the processed transactions.
+    pub fn accounts_created(&self) -> u64 {
+        self.accounts_created
+    }
+
+    /// Gets the number of locked accounts.
+    pub fn locked_accounts(&self) -> u64 {
+        self.locked_accounts
+    }
+
+    /// Counts a processed transaction.
+    pub(crate) fn count_transaction(&mut self, tx_type: TransactionType) {
+        self.by_type[tx_type as usize] += 1;
+    }
+
+    /// Counts a rejected transaction.
+    pub(crate) fn count_rejection(&mut self, tx_type: TransactionType, error: &TransactionError) {
+        self.failed_by_type[tx_type as usize] += 1;
+        *self.rejections.entry(error.name()).or_default() += 1;
+    }
+
+    /// Counts a created account.
+    pub(crate) fn count_account(&mut self) {
+        self.accounts_created += 1;
+    }
+
+    /// Sets the number of locked accounts.
+    pub(crate) fn set_locked_accounts(&mut self, locked_accounts: u64) {
+        self.locked_accounts = locked_accounts;
+    }
+
+    /// Adds the counters of `other`, which covers different transactions.
+    pub(crate) fn merge(&mut self, other: &Stats) {
+        for (count, other) in self.by_type.iter_mut().zip(other.by_type) {
+            *count += other;
+        }
+        for (count, other) in self.failed_by_type.iter_mut().zip(other.failed_by_type) {
+            *count += other;
+        }
+        for (name, count) in &other.rejections {
+            *self.rejections.entry(name).or_default() += count;
+        }
+        self.accounts_created += other.accounts_created;
+        self.locked_accounts += other.locked_accounts;
+    }
+}
@@ -1,13 +1,13 @@
 //! Transaction module for handling various types of banking transactions.
-use serde::{Deserialize, de};
+use std::{fmt, sync::Arc};
 
-use crate::bank::{
-    DECIMAL_PRECISION, TransactionId,
-    types::{ClientId, Money},
-};
+use serde::{Deserialize, Serialize, de};
+
+use crate::bank::{Currency, Money, TransactionId, types::ClientId};
 
 /// Enum representing the type of transaction.
-#[derive(Deserialize, Debug, Clone)]
+/// It's read case-insensitively and with surrounding whitespace trimmed, so `Deposit` and ` DEPOSIT` are deposits too.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
 #[serde(rename_all = "lowercase")]
 pub enum TransactionType {
     Deposit,
@@ -15,19 +15,91 @@ pub enum TransactionType {
     Dispute,
     Resolve,
     Chargeback,
+    /// Administrative unlock of an account locked by a chargeback.
+    Unlock,
+    /// Manual hold of an amount, moving it from available to held.
+    Hold,
+    /// Release of a manually held amount, moving it from held back to available.
+    Release,
+    /// Administrative lock of an account, without a dispute.
+    Freeze,
+    /// Administrative unlock of an account, whether it was frozen or locked by a chargeback.
+    Unfreeze,
+}
+
+impl TransactionType {
+    /// Every transaction type, in the order of declaration.
+    pub const ALL: [TransactionType; 10] = [
+        TransactionType::Deposit,
+        TransactionType::Withdrawal,
+        TransactionType::Dispute,
+        TransactionType::Resolve,
+        TransactionType::Chargeback,
+        TransactionType::Unlock,
+        TransactionType::Hold,
+        TransactionType::Release,
+        TransactionType::Freeze,
+        TransactionType::Unfreeze,
+    ];
+
+    /// The names of the types as they are written in the input, in the order of declaration, so a type's
+    /// discriminant is the index of its name.
+    const NAMES: [&str; 10] = [
+        "deposit",
+        "withdrawal",
+        "dispute",
+        "resolve",
+        "chargeback",
+        "unlock",
+        "hold",
+        "release",
+        "freeze",
+        "unfreeze",
+    ];
+
+    /// Gets the name of the type, the way it's written in the input.
+    pub fn as_str(&self) -> &'static str {
+        Self::NAMES[*self as usize]
+    }
+}
+
+impl fmt::Display for TransactionType {
+    /// Formats the type the same way it's written in the input.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
 }
 
-/// Custom deserializer for monetary values to handle fixed-point representation.
-fn deserialize_money<'de, D>(deserializer: D) -> Result<Option<Money>, D::Error>
-where
-    D: de::Deserializer<'de>,
-{
-    let value: Option<f64> = Option::deserialize(deserializer)?;
-    Ok(value.map(|v| (v * DECIMAL_PRECISION) as Money))
+impl<'de> Deserialize<'de> for TransactionType {
+    fn deserialize<D: de::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_str(TransactionTypeVisitor)
+    }
+}
+
+/// Parses a transaction type from any string, borrowed or not, ignoring its case.
+struct TransactionTypeVisitor;
+
+impl de::Visitor<'_> for TransactionTypeVisitor {
+    type Value = TransactionType;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a transaction type")
+    }
+
+    fn visit_str<E: de::Error>(self, name: &str) -> Result<TransactionType, E> {
+        let name = name.trim();
+        TransactionType::ALL
+            .into_iter()
+            .find(|tx_type| tx_type.as_str().eq_ignore_ascii_case(name))
+            .ok_or_else(|| E::unknown_variant(name, &TransactionType::NAMES))
+    }
 }
 
 /// Represents a banking transaction.
-#[derive(Deserialize, Debug, Clone)]
+/// It serializes to the same fields it's read from, with the amount written with four decimal places and left empty
+/// when there is none, so written transactions can be read back unchanged. The currency is left out when there is none,
+/// so a CSV writer needs to be `flexible` for transactions that mix the implicit currency with explicit ones.
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Transaction {
     /// The type of transaction (e.g., Deposit, Withdrawal, etc.)
     #[serde(rename = "type")]
@@ -41,9 +113,22 @@ pub struct Transaction {
     #[serde(rename = "tx")]
     transaction_id: TransactionId,
 
-    /// The amount involved in the transaction, if applicable.
-    #[serde(rename = "amount", deserialize_with = "deserialize_money")]
+    /// The amount involved in the transaction, if applicable. An empty or left out amount is `None`.
+    #[serde(rename = "amount", default)]
     amount: Option<Money>,
+
+    /// The currency of a deposit, withdrawal, hold or release, `None` for the implicit currency of single-currency feeds.
+    /// Disputes, resolves and chargebacks always apply to the currency of the transaction they reference.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    currency: Option<Currency>,
+
+    /// The 1-based line of the input the transaction was read from, if it was read from one.
+    #[serde(skip)]
+    line: Option<u64>,
+
+    /// The name of the input file the transaction was read from, when reading several of them.
+    #[serde(skip)]
+    file: Option<Arc<str>>,
 }
 
 impl Transaction {
@@ -67,7 +152,58 @@ impl Transaction {
         self.client_id
     }
 
-    #[cfg(test)]
+    /// Gets the currency of the transaction, `None` for the implicit one.
+    pub fn get_currency(&self) -> Option<Currency> {
+        self.currency
+    }
+
+    /// Sets the currency of the transaction.
+    pub fn with_currency(mut self, currency: Currency) -> Self {
+        self.currency = Some(currency);
+        self
+    }
+
+    /// Gets the line of the input the transaction was read from, if known.
+    pub fn get_line(&self) -> Option<u64> {
+        self.line
+    }
+
+    /// Sets the line of the input the transaction was read from.
+    pub fn with_line(mut self, line: u64) -> Self {
+        self.line = Some(line);
+        self
+    }
+
+    /// Gets the name of the input file the transaction was read from, if known.
+    pub fn get_file(&self) -> Option<&Arc<str>> {
+        self.file.as_ref()
+    }
+
+    /// Sets the name of the input file the transaction was read from.
+    pub fn with_file(mut self, file: Arc<str>) -> Self {
+        self.file = Some(file);
+        self
+    }
+
+    /// Formats the transaction as the CSV row it's written as, e.g. `withdrawal,1,2,5.0000`.
+    /// The amount is left empty when there is none, and the currency is only added when there is one.
+    pub fn to_row(&self) -> String {
+        let amount = self.amount.map(|amount| amount.to_string());
+        let mut row = format!(
+            "{},{},{},{}",
+            self.tx_type,
+            self.client_id,
+            self.transaction_id,
+            amount.unwrap_or_default()
+        );
+        if let Some(currency) = self.currency {
+            row.push_str(&format!(",{currency}"));
+        }
+        row
+    }
+
+    /// Creates a new transaction. The amount is only used by deposits, withdrawals, holds and releases,
+    /// and optionally carried by disputes, resolves and chargebacks.
     pub fn new(
         tx_type: TransactionType,
         client_id: ClientId,
@@ -79,6 +215,9 @@ impl Transaction {
             client_id,
             transaction_id,
             amount,
+            currency: None,
+            line: None,
+            file: None,
         }
     }
 }
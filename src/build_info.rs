@@ -0,0 +1,69 @@
+//! The version of the binary and what it was built with, for `--version` and the `version` command.
+use std::io::{self, Write};
+
+use clap::ValueEnum;
+use serde::Serialize;
+
+use crate::{
+    cli::{Compression, InputFormat},
+    output::OutputFormat,
+};
+
+/// The version, the enabled cargo features and the supported formats of the binary.
+#[derive(Serialize, Debug)]
+pub struct BuildInfo {
+    /// The name of the crate.
+    pub name: &'static str,
+    /// The version of the crate.
+    pub version: &'static str,
+    /// The optional cargo features the binary was built with.
+    pub features: Vec<&'static str>,
+    /// The formats accepted by `--input-format`.
+    pub input_formats: Vec<String>,
+    /// The compressions accepted by `--compression`.
+    pub compressions: Vec<String>,
+    /// The formats accepted by `--output-format`.
+    pub output_formats: Vec<String>,
+}
+
+impl BuildInfo {
+    /// Gets the information about the running binary.
+    pub fn current() -> Self {
+        BuildInfo {
+            name: env!("CARGO_PKG_NAME"),
+            version: env!("CARGO_PKG_VERSION"),
+            features: [("decimal", cfg!(feature = "decimal"))]
+                .into_iter()
+                .filter_map(|(feature, enabled)| enabled.then_some(feature))
+                .collect(),
+            input_formats: value_names::<InputFormat>(),
+            compressions: value_names::<Compression>(),
+            output_formats: value_names::<OutputFormat>(),
+        }
+    }
+
+    /// Writes the information as a JSON object, or as `name version` followed by a line for each list.
+    pub fn write(&self, json: bool, mut writer: impl Write) -> io::Result<()> {
+        if json {
+            serde_json::to_writer(&mut writer, self)?;
+            return writeln!(writer);
+        }
+        writeln!(writer, "{} {}", self.name, self.version)?;
+        match self.features.as_slice() {
+            [] => writeln!(writer, "features: none")?,
+            features => writeln!(writer, "features: {}", features.join(", "))?,
+        }
+        writeln!(writer, "input formats: {}", self.input_formats.join(", "))?;
+        writeln!(writer, "compressions: {}", self.compressions.join(", "))?;
+        writeln!(writer, "output formats: {}", self.output_formats.join(", "))
+    }
+}
+
+/// Lists the names of the values of a command line enum, as they are given on the command line.
+fn value_names<T: ValueEnum>() -> Vec<String> {
+    T::value_variants()
+        .iter()
+        .filter_map(|value| value.to_possible_value())
+        .map(|value| value.get_name().to_string())
+        .collect()
+}
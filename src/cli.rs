@@ -0,0 +1,525 @@
+//! Command line arguments of the `bank` binary.
+use std::path::{Path, PathBuf};
+
+use bank::{Config, DisputePolicy, LockPolicy, Money, RoundingMode, WithdrawalDisputePolicy};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+
+use crate::output::OutputFormat;
+
+/// Processes a file of transactions and prints the resulting accounts.
+#[derive(Parser, Debug)]
+#[command(
+    about,
+    disable_version_flag = true,
+    args_conflicts_with_subcommands = true,
+    after_help = "Examples:\n  bank transactions.csv > accounts.csv\n  bank 00.csv 01.csv 02.csv > accounts.csv\n  generate-transactions | bank > accounts.csv\n  bank validate transactions.csv"
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Print the version, the enabled features and the supported formats, without reading any input.
+    #[arg(long, short = 'V')]
+    pub version: bool,
+
+    /// Print the version as JSON.
+    #[arg(long, requires = "version")]
+    pub json: bool,
+
+    /// Arguments of `process`, which is run when no command is given.
+    #[command(flatten)]
+    pub process: ProcessArgs,
+}
+
+/// The commands of the binary.
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Processes the transactions and prints the resulting accounts. This is the default command.
+    Process(ProcessArgs),
+    /// Checks the transactions for malformed rows and structural problems without processing them.
+    Validate(InputArgs),
+    /// Serves the engine over HTTP: `POST /transactions` submits a transaction as JSON, `GET /accounts` and
+    /// `GET /accounts/{client}` return the current balances.
+    Serve(ServeArgs),
+    /// Prints the version, the enabled features and the supported formats, like `--version`.
+    Version(VersionArgs),
+}
+
+/// Arguments of the `version` command.
+#[derive(Args, Debug)]
+pub struct VersionArgs {
+    /// Print the version as JSON.
+    #[arg(long)]
+    pub json: bool,
+}
+
+/// The transactions input.
+#[derive(Args, Debug)]
+pub struct InputArgs {
+    /// The files with the transactions, processed one after another in the given order, each with its own header row.
+    /// Reads from stdin when it's `-` or omitted.
+    #[arg(id = "input", value_name = "INPUT", default_value = "-")]
+    pub inputs: Vec<PathBuf>,
+
+    /// Format of the input. Defaults to `jsonl` for `.jsonl` and `.ndjson` files and to `csv` otherwise.
+    #[arg(long, value_enum)]
+    pub input_format: Option<InputFormat>,
+
+    /// Compression of the input. Defaults to `gzip` for `.gz` files, `zstd` for `.zst` files and `none` otherwise,
+    /// so it has to be given for compressed stdin.
+    #[arg(long, value_enum)]
+    pub compression: Option<Compression>,
+
+    /// How amounts with more than four decimal places are handled.
+    /// Defaults to `reject` with `--strict` and for `validate`, and to `half-even` otherwise.
+    #[arg(long, value_enum)]
+    pub rounding: Option<RoundingArg>,
+}
+
+impl InputArgs {
+    /// Returns the rounding mode of the amounts, either the explicit one or the default of the mode of the run.
+    pub fn rounding_mode(&self, strict: bool) -> RoundingMode {
+        match self.rounding {
+            Some(rounding) => rounding.into(),
+            None if strict => RoundingMode::Reject,
+            None => RoundingMode::HalfEven,
+        }
+    }
+
+    /// Returns the format of the input, either the explicit one or the one matching the file extension.
+    /// The extension of a compressed file is the one before the compression's, e.g. `jsonl` for `.jsonl.gz`.
+    pub fn format(&self, input: &Path) -> InputFormat {
+        self.input_format.unwrap_or_else(|| {
+            let input = match Compression::from_extension(input) {
+                Compression::None => input,
+                _ => Path::new(input.file_stem().unwrap_or_default()),
+            };
+            match input.extension().and_then(|extension| extension.to_str()) {
+                Some("jsonl" | "ndjson") => InputFormat::Jsonl,
+                _ => InputFormat::Csv,
+            }
+        })
+    }
+
+    /// Returns the compression of the input, either the explicit one or the one matching the file extension.
+    pub fn compression(&self, input: &Path) -> Compression {
+        self.compression
+            .unwrap_or_else(|| Compression::from_extension(input))
+    }
+}
+
+/// Options of the engine, shared by the commands running it.
+#[derive(Args, Debug)]
+pub struct EngineArgs {
+    /// How to handle a dispute of a deposit whose funds are no longer available.
+    #[arg(long, value_enum, default_value_t = DisputePolicyArg::AllowNegative)]
+    pub dispute_policy: DisputePolicyArg,
+
+    /// How a dispute of a withdrawal affects the balances.
+    #[arg(long, value_enum, default_value_t = WithdrawalDisputePolicyArg::HoldFromTotal)]
+    pub withdrawal_dispute_policy: WithdrawalDisputePolicyArg,
+
+    /// Reject disputes, resolves and chargebacks whose amount doesn't match the referenced transaction.
+    #[arg(long)]
+    pub verify_dispute_amounts: bool,
+
+    /// Which transactions are blocked on a locked account.
+    #[arg(long, value_enum, default_value_t = LockPolicyArg::FundsMovement)]
+    pub lock_policy: LockPolicyArg,
+
+    /// Park disputes of unknown transactions and apply them if the transaction arrives later in the input.
+    #[arg(long)]
+    pub park_unknown_disputes: bool,
+
+    /// How far below zero a withdrawal may take the available balance of an account, e.g. `100` or `12.5`.
+    #[arg(long, default_value = "0", value_parser = parse_overdraft)]
+    pub overdraft: Money,
+
+    /// Accept deposits, withdrawals, holds and releases of zero instead of rejecting them.
+    #[arg(long)]
+    pub allow_zero_amounts: bool,
+
+    /// Keep only this many of the most recent deposits and withdrawals of each client disputable, forgetting the older
+    /// ones and the charged back ones to bound the memory used. Disputes of forgotten transactions are rejected.
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    pub finality_window: Option<u32>,
+
+    /// Reject deposits and withdrawals of more than this amount, e.g. `1000000`, to catch bogus rows. No limit by default.
+    #[arg(long, value_parser = parse_max_amount)]
+    pub max_amount: Option<Money>,
+}
+
+/// Arguments of the `serve` command.
+#[derive(Args, Debug)]
+pub struct ServeArgs {
+    /// The port to listen on. With `0` a free port is picked, and logged with `--verbose`.
+    #[arg(long, default_value_t = 8080)]
+    pub port: u16,
+
+    /// The address to listen on.
+    #[arg(long, default_value = "127.0.0.1")]
+    pub host: String,
+
+    #[command(flatten)]
+    pub engine: EngineArgs,
+
+    /// Number of decimal places of the balances in the responses.
+    #[arg(long, default_value_t = Money::DECIMAL_PLACES)]
+    pub precision: usize,
+
+    /// Capacity of the channel between the requests and the engine.
+    #[arg(long, default_value_t = bank::DEFAULT_CHANNEL_SIZE, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    pub channel_size: usize,
+
+    /// Log every served request.
+    #[arg(long, short)]
+    pub verbose: bool,
+}
+
+/// Arguments of the `process` command.
+#[derive(Args, Debug)]
+pub struct ProcessArgs {
+    #[command(flatten)]
+    pub source: InputArgs,
+
+    #[command(flatten)]
+    pub engine: EngineArgs,
+
+    /// Number of decimal places of the balances in the output.
+    #[arg(long, default_value_t = Money::DECIMAL_PLACES)]
+    pub precision: usize,
+
+    /// Format of the account report.
+    #[arg(long, value_enum, default_value_t = OutputFormat::Csv)]
+    pub output_format: OutputFormat,
+
+    /// Capacity of the channel between the reader and the engine, used for non-file inputs and with `--workers`.
+    #[arg(long, default_value_t = bank::DEFAULT_CHANNEL_SIZE, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    pub channel_size: usize,
+
+    /// Report ingestion statistics, such as how often the reader waited for a full channel, on stderr.
+    #[arg(long, short)]
+    pub verbose: bool,
+
+    /// Print a summary of the run on stderr: rows read and skipped, transactions by type, rejections by reason and accounts.
+    #[arg(long, alias = "print-stats")]
+    pub stats: bool,
+
+    /// Check that every account is consistent after processing, and exit with an error instead of reporting if one isn't.
+    #[arg(long)]
+    pub self_check: bool,
+
+    /// Serve the engine over TCP on this address instead of reading the input, until Ctrl-C.
+    /// Every line a client sends is a transaction in the JSON Lines format, or `dump` to get the account report,
+    /// which is sent back as CSV followed by an empty line.
+    #[arg(long, value_name = "ADDR", conflicts_with_all = ["input", "workers"])]
+    pub listen: Option<String>,
+
+    /// Keep following the input file once its end is reached, processing the rows appended to it as they arrive, until
+    /// Ctrl-C. A truncated or replaced file is read again from its start. The account report is written on SIGHUP,
+    /// every `--report-interval` and at the end.
+    #[arg(long, conflicts_with_all = ["listen", "workers", "fail_fast", "validate"])]
+    pub watch: bool,
+
+    /// Write the account report every this many seconds with `--watch`. On stdout every report is followed by an empty
+    /// line.
+    #[arg(long, value_name = "SECONDS", requires = "watch", value_parser = clap::value_parser!(u64).range(1..))]
+    pub report_interval: Option<u64>,
+
+    /// Show a progress bar on stderr while the input files are read, with the rows per second and the rejected
+    /// transactions. It's only drawn if all inputs are regular files and stderr is a terminal.
+    #[arg(long, conflicts_with_all = ["listen", "watch"])]
+    pub progress: bool,
+
+    /// Number of worker tasks processing the transactions, with the clients partitioned between them.
+    /// The report is the same as with a single worker, transaction IDs are still checked across all clients.
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..))]
+    pub workers: u16,
+
+    /// Stop at the first malformed row or rejected transaction, without a report, and exit with code 3.
+    /// The offending row or transaction is logged with its line.
+    #[arg(long, visible_alias = "strict", conflicts_with_all = ["listen", "workers"])]
+    pub fail_fast: bool,
+
+    /// Exit with code 0 even if malformed rows were skipped. Without it the report is still written, but the run exits
+    /// with code 4 so that a partially read input doesn't pass unnoticed.
+    #[arg(long, conflicts_with = "fail_fast")]
+    pub lenient: bool,
+
+    /// Dry run: process the input without writing the account report, a snapshot or the rejects, and instead list every
+    /// rejected transaction with its line, followed by a summary. Exits with code 1 if a transaction was rejected or a row
+    /// was malformed.
+    #[arg(long, conflicts_with_all = ["listen", "fail_fast", "rejects", "checkpoint", "snapshot_out", "output", "audit_log"])]
+    pub validate: bool,
+
+    /// Write the transactions the engine rejected, with the reason, to this CSV file.
+    /// The rejects are reported only there instead of on stderr.
+    #[arg(long)]
+    pub rejects: Option<PathBuf>,
+
+    /// Append every accepted transaction to this CSV file as it's applied, with the balances of its account right
+    /// after it. Every row is flushed, so the file is usable even if the run doesn't finish.
+    #[arg(long)]
+    pub audit_log: Option<PathBuf>,
+
+    /// Continue from the engine state saved in this file, if it exists, and save the final state back to it.
+    #[arg(long, conflicts_with_all = ["snapshot_in", "snapshot_out"])]
+    pub checkpoint: Option<PathBuf>,
+
+    /// Start from the engine state saved by an earlier run with `--snapshot-out`.
+    #[arg(long)]
+    pub snapshot_in: Option<PathBuf>,
+
+    /// Save the final engine state to this file, so a later run can continue from it with `--snapshot-in`.
+    #[arg(long)]
+    pub snapshot_out: Option<PathBuf>,
+
+    /// Write the account report to this file instead of stdout.
+    /// The file is replaced only once the whole report was written.
+    #[arg(long)]
+    pub output: Option<PathBuf>,
+}
+
+impl EngineArgs {
+    /// Builds the engine configuration from the arguments.
+    pub fn config(&self) -> Config {
+        Config {
+            dispute_policy: self.dispute_policy.into(),
+            withdrawal_dispute_policy: self.withdrawal_dispute_policy.into(),
+            verify_dispute_amounts: self.verify_dispute_amounts,
+            lock_policy: self.lock_policy.into(),
+            park_unknown_disputes: self.park_unknown_disputes,
+            overdraft_limit: self.overdraft,
+            allow_zero_amounts: self.allow_zero_amounts,
+            finality_window: self.finality_window.map(|window| window as usize),
+            max_amount: self.max_amount,
+        }
+    }
+}
+
+/// Parses the `--overdraft` limit, which can't be negative.
+fn parse_overdraft(input: &str) -> Result<Money, String> {
+    match bank::parse_money(input) {
+        Ok(limit) if limit.is_negative() => {
+            Err("the overdraft limit can't be negative".to_string())
+        }
+        result => result.map_err(|err| err.to_string()),
+    }
+}
+
+/// Parses the `--max-amount` limit, which can't be negative.
+fn parse_max_amount(input: &str) -> Result<Money, String> {
+    match bank::parse_money(input) {
+        Ok(limit) if limit.is_negative() => Err("the maximum amount can't be negative".to_string()),
+        result => result.map_err(|err| err.to_string()),
+    }
+}
+
+/// Format of the transactions input.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputFormat {
+    /// CSV with a `type,client,tx,amount` header row.
+    Csv,
+    /// One JSON object per line with the `type`, `client`, `tx` and `amount` fields.
+    #[value(alias = "json")]
+    Jsonl,
+}
+
+/// Compression of the transactions input.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    /// Not compressed.
+    None,
+    /// Compressed with gzip, possibly as several concatenated members.
+    #[value(alias = "gz")]
+    Gzip,
+    /// Compressed with Zstandard.
+    #[value(alias = "zst")]
+    Zstd,
+}
+
+impl Compression {
+    /// Gets the compression matching the extension of the file.
+    fn from_extension(path: &Path) -> Self {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("gz") => Compression::Gzip,
+            Some("zst") => Compression::Zstd,
+            _ => Compression::None,
+        }
+    }
+}
+
+/// Command line names of the `DisputePolicy` variants.
+#[derive(ValueEnum, Debug, Clone, Copy)]
+pub enum DisputePolicyArg {
+    /// Apply the dispute, even if available goes below zero.
+    AllowNegative,
+    /// Reject the dispute if there are not enough available funds.
+    RejectInsufficient,
+}
+
+impl From<DisputePolicyArg> for DisputePolicy {
+    fn from(arg: DisputePolicyArg) -> Self {
+        match arg {
+            DisputePolicyArg::AllowNegative => DisputePolicy::AllowNegativeAvailable,
+            DisputePolicyArg::RejectInsufficient => DisputePolicy::RejectIfInsufficientAvailable,
+        }
+    }
+}
+
+/// Command line names of the `WithdrawalDisputePolicy` variants.
+#[derive(ValueEnum, Debug, Clone, Copy)]
+pub enum WithdrawalDisputePolicyArg {
+    /// Hold the amount while in dispute, raising held and total, and make it available on a chargeback.
+    HoldFromTotal,
+    /// Keep the balances while in dispute and credit the amount on a chargeback.
+    CreditOnChargeback,
+    /// Reject disputes of withdrawals.
+    Reject,
+}
+
+impl From<WithdrawalDisputePolicyArg> for WithdrawalDisputePolicy {
+    fn from(arg: WithdrawalDisputePolicyArg) -> Self {
+        match arg {
+            WithdrawalDisputePolicyArg::HoldFromTotal => WithdrawalDisputePolicy::HoldFromTotal,
+            WithdrawalDisputePolicyArg::CreditOnChargeback => {
+                WithdrawalDisputePolicy::CreditOnChargeback
+            }
+            WithdrawalDisputePolicyArg::Reject => WithdrawalDisputePolicy::Reject,
+        }
+    }
+}
+
+/// Command line names of the `LockPolicy` variants.
+#[derive(ValueEnum, Debug, Clone, Copy)]
+pub enum LockPolicyArg {
+    /// Block deposits and withdrawals, disputes, holds and releases are still accepted.
+    FundsMovement,
+    /// Block every transaction.
+    All,
+}
+
+impl From<LockPolicyArg> for LockPolicy {
+    fn from(arg: LockPolicyArg) -> Self {
+        match arg {
+            LockPolicyArg::FundsMovement => LockPolicy::BlockFundsMovement,
+            LockPolicyArg::All => LockPolicy::BlockAll,
+        }
+    }
+}
+
+/// Command line names of the `RoundingMode` variants.
+#[derive(ValueEnum, Debug, Clone, Copy)]
+pub enum RoundingArg {
+    /// Reject the row as malformed.
+    Reject,
+    /// Drop the extra digits, rounding toward zero.
+    Truncate,
+    /// Round to four decimal places, a tie away from zero.
+    HalfUp,
+    /// Round to four decimal places, a tie to the even digit.
+    HalfEven,
+}
+
+impl From<RoundingArg> for RoundingMode {
+    fn from(arg: RoundingArg) -> Self {
+        match arg {
+            RoundingArg::Reject => RoundingMode::Reject,
+            RoundingArg::Truncate => RoundingMode::Truncate,
+            RoundingArg::HalfUp => RoundingMode::HalfUp,
+            RoundingArg::HalfEven => RoundingMode::HalfEven,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use clap::{CommandFactory, Parser, error::ErrorKind};
+
+    use crate::cli::{Cli, Command};
+
+    /// Parses the arguments after the binary name.
+    fn parse(args: &[&str]) -> Result<Cli, clap::Error> {
+        Cli::try_parse_from(std::iter::once("bank").chain(args.iter().copied()))
+    }
+
+    #[test]
+    fn test_command_definition() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn test_commands() {
+        // Without a command the arguments are the ones of `process`.
+        let cli = parse(&["input.csv", "--workers", "2"]).unwrap();
+        assert!(cli.command.is_none());
+        assert_eq!(cli.process.source.inputs, [PathBuf::from("input.csv")]);
+        assert_eq!(cli.process.workers, 2);
+        assert_eq!(
+            parse(&[]).unwrap().process.source.inputs,
+            [PathBuf::from("-")]
+        );
+
+        let Some(Command::Process(args)) = parse(&["process", "a.csv", "b.csv"]).unwrap().command
+        else {
+            panic!("Expected the process command");
+        };
+        assert_eq!(
+            args.source.inputs,
+            [PathBuf::from("a.csv"), PathBuf::from("b.csv")]
+        );
+
+        let Some(Command::Validate(args)) = parse(&["validate", "a.csv"]).unwrap().command else {
+            panic!("Expected the validate command");
+        };
+        assert_eq!(args.inputs, [PathBuf::from("a.csv")]);
+
+        let Some(Command::Serve(args)) = parse(&["serve", "--port", "0"]).unwrap().command else {
+            panic!("Expected the serve command");
+        };
+        assert_eq!((args.port, args.host.as_str()), (0, "127.0.0.1"));
+
+        assert!(matches!(
+            parse(&["version", "--json"]).unwrap().command,
+            Some(Command::Version(args)) if args.json
+        ));
+    }
+
+    #[test]
+    fn test_argument_errors() {
+        let cases: [(&[&str], ErrorKind); 10] = [
+            // An unknown flag is an error, not an input path.
+            (&["--frobnicate", "input.csv"], ErrorKind::UnknownArgument),
+            (&["validate", "--workers", "2"], ErrorKind::UnknownArgument),
+            (&["serve", "input.csv"], ErrorKind::UnknownArgument),
+            (&["--workers", "0"], ErrorKind::ValueValidation),
+            (
+                &["--workers", "2", "--fail-fast"],
+                ErrorKind::ArgumentConflict,
+            ),
+            (&["--lenient", "--strict"], ErrorKind::ArgumentConflict),
+            (
+                &["--listen", "127.0.0.1:0", "input.csv"],
+                ErrorKind::ArgumentConflict,
+            ),
+            (
+                &["--report-interval", "1"],
+                ErrorKind::MissingRequiredArgument,
+            ),
+            (&["--help"], ErrorKind::DisplayHelp),
+            (&["process", "--help"], ErrorKind::DisplayHelp),
+        ];
+        for (args, kind) in cases {
+            assert_eq!(parse(args).unwrap_err().kind(), kind, "{args:?}");
+        }
+        // After a flag of the implicit `process`, a command name is just another input path.
+        let cli = parse(&["--workers", "2", "validate", "a.csv"]).unwrap();
+        assert!(cli.command.is_none());
+        assert_eq!(
+            cli.process.source.inputs,
+            [PathBuf::from("validate"), PathBuf::from("a.csv")]
+        );
+    }
+}
@@ -0,0 +1,43 @@
+//! The errors that end a run of the binary, reported by `main` with a message and a non-zero exit code.
+use std::io;
+
+use bank::{HeaderError, SnapshotError};
+use thiserror::Error;
+
+use crate::output::OutputError;
+
+/// A failure of the binary outside of the transactions themselves, which are reported as they are processed.
+#[derive(Error, Debug)]
+pub enum AppError {
+    /// The command line couldn't be parsed, or the help or version was asked for. Clap prints it itself.
+    #[error(transparent)]
+    Args(#[from] clap::Error),
+    /// A file or socket couldn't be opened, read or written.
+    #[error("{context}: {source}")]
+    Io {
+        /// What was being done, e.g. `Failed to open transactions.csv`.
+        context: String,
+        #[source]
+        source: io::Error,
+    },
+    /// The CSV input couldn't be read.
+    #[error("Failed to read the input: {0}")]
+    Csv(#[from] csv::Error),
+    /// The header row of the CSV input doesn't have the expected columns.
+    #[error(transparent)]
+    Header(#[from] HeaderError),
+    /// A snapshot couldn't be loaded or saved.
+    #[error(transparent)]
+    Snapshot(#[from] SnapshotError),
+    /// The report or the rejects couldn't be written.
+    #[error(transparent)]
+    Output(#[from] OutputError),
+}
+
+impl AppError {
+    /// Wraps an I/O error with what was being done when it happened.
+    pub fn io(context: impl Into<String>) -> impl FnOnce(io::Error) -> Self {
+        let context = context.into();
+        move |source| AppError::Io { context, source }
+    }
+}
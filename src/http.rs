@@ -0,0 +1,126 @@
+//! Serving the engine over HTTP: transactions are submitted one by one and the balances can be queried at any time.
+use axum::{
+    Json, Router,
+    extract::{Path, State as Shared},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+};
+use bank::{AccountSummary, ClientId, Request, State, parse_json_transaction};
+use tokio::{
+    net::TcpListener,
+    sync::{mpsc, oneshot},
+};
+use tracing::info;
+
+use crate::{cli::ServeArgs, error::AppError};
+
+/// The handle of the engine shared by the request handlers.
+#[derive(Clone)]
+struct Engine {
+    /// The channel to the state, which handles the requests in the order they arrive.
+    sender: mpsc::Sender<Request>,
+    /// Number of decimal places of the balances in the responses.
+    precision: usize,
+}
+
+impl Engine {
+    /// Sends the request to the state and waits for its result.
+    async fn send<T>(
+        &self,
+        (request, result): (Request, oneshot::Receiver<T>),
+    ) -> Result<T, StatusCode> {
+        self.sender
+            .send(request)
+            .await
+            .map_err(|_| StatusCode::SERVICE_UNAVAILABLE)?;
+        result.await.map_err(|_| StatusCode::SERVICE_UNAVAILABLE)
+    }
+}
+
+/// Serves the engine until Ctrl-C.
+pub async fn serve(args: ServeArgs) -> Result<(), AppError> {
+    let listener = TcpListener::bind((args.host.as_str(), args.port))
+        .await
+        .map_err(AppError::io(format!(
+            "Failed to listen on {}:{}",
+            args.host, args.port
+        )))?;
+    if let Ok(addr) = listener.local_addr() {
+        info!("Listening on {addr}");
+    }
+
+    let (sender, receiver) = mpsc::channel(args.channel_size);
+    let mut state = State::new_standalone().with_config(args.engine.config());
+    let handle = tokio::spawn(async move { state.serve(receiver).await });
+    let engine = Engine {
+        sender,
+        precision: args.precision,
+    };
+    let result = axum::serve(listener, router(engine))
+        .with_graceful_shutdown(async {
+            let _ = tokio::signal::ctrl_c().await;
+        })
+        .await;
+    result.map_err(AppError::io("The server failed"))?;
+    // The router and with it the last sender is gone, which lets the state finish.
+    handle
+        .await
+        .expect("Failed to join the state handling task");
+    Ok(())
+}
+
+/// The routes of the server.
+fn router(engine: Engine) -> Router {
+    Router::new()
+        .route("/transactions", post(submit))
+        .route("/accounts", get(accounts))
+        .route("/accounts/{client}", get(account))
+        .with_state(engine)
+}
+
+/// Processes a transaction given as a JSON object, in the JSON Lines input format.
+/// Responds with 400 and the reason if it's malformed or rejected by the engine.
+async fn submit(Shared(engine): Shared<Engine>, body: String) -> Response {
+    let transaction = match parse_json_transaction(body.trim()) {
+        Ok(transaction) => transaction,
+        Err(message) => return (StatusCode::BAD_REQUEST, message).into_response(),
+    };
+    match engine.send(Request::submit(transaction)).await {
+        Ok(Ok(())) => StatusCode::OK.into_response(),
+        Ok(Err(err)) => (StatusCode::BAD_REQUEST, err.error.to_string()).into_response(),
+        Err(status) => status.into_response(),
+    }
+}
+
+/// Responds with the summaries of all accounts, sorted by client ID.
+async fn accounts(Shared(engine): Shared<Engine>) -> Result<Json<Vec<AccountSummary>>, StatusCode> {
+    let precision = engine.precision;
+    let summaries = engine
+        .send(Request::query(move |state| {
+            state
+                .accounts_sorted()
+                .into_iter()
+                .flat_map(|account| account.summaries(precision))
+                .collect()
+        }))
+        .await?;
+    Ok(Json(summaries))
+}
+
+/// Responds with the summaries of the account of the client, one per currency, or 404 if there is no such account.
+async fn account(
+    Shared(engine): Shared<Engine>,
+    Path(client): Path<ClientId>,
+) -> Result<Json<Vec<AccountSummary>>, StatusCode> {
+    let precision = engine.precision;
+    engine
+        .send(Request::query(move |state| {
+            state
+                .get_account(client)
+                .map(|account| account.summaries(precision))
+        }))
+        .await?
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
+}
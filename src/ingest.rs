@@ -0,0 +1,352 @@
+//! Feeding the parsed input rows to the engine, either directly, through a channel or across worker tasks.
+use bank::{BATCH_SIZE, ParseError, ShardedState, State, Transaction, TransactionError};
+use thiserror::Error;
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, instrument};
+
+/// Counters of the ingestion, reported at the end of the run.
+#[derive(Default, Debug)]
+pub struct Counters {
+    /// Rows taken from the input, including the malformed ones.
+    pub read: usize,
+    /// Rows that couldn't be parsed.
+    pub skipped: usize,
+    /// Times the reader had to wait because the channel to the state was full.
+    pub waits: usize,
+}
+
+/// Parsed input rows, either transactions or the reason a row was rejected.
+pub type Rows = Box<dyn Iterator<Item = Result<Transaction, ParseError>>>;
+
+/// The first failure, which stops the ingestion with `--fail-fast`.
+#[derive(Error, Debug)]
+pub enum Failure {
+    /// A row couldn't be parsed.
+    #[error(transparent)]
+    Malformed(ParseError),
+    /// The engine rejected a transaction, printed as the row it was read from.
+    #[error(
+        "Rejected transaction at line {line} `{row}`: {1}",
+        line = bank::line_in_file(.0.get_file().map(|file| &**file), .0.get_line().unwrap_or_default()),
+        row = .0.to_row()
+    )]
+    Rejected(Transaction, TransactionError),
+}
+
+/// Stops taking rows once the token is cancelled. A row read before the cancellation was noticed is dropped,
+/// so everything that was handed on is processed completely.
+pub fn until_cancelled(rows: Rows, token: CancellationToken) -> Rows {
+    Box::new(rows.take_while(move |_| !token.is_cancelled()))
+}
+
+/// Counts all rows in `read` and the malformed ones in `skipped`, passing them on to be recorded by the state.
+fn count_rows<'a>(
+    rows: impl Iterator<Item = Result<Transaction, ParseError>> + 'a,
+    read: &'a mut usize,
+    skipped: &'a mut usize,
+) -> impl Iterator<Item = Result<Transaction, ParseError>> + 'a {
+    rows.inspect(move |row| {
+        *read += 1;
+        if row.is_err() {
+            *skipped += 1;
+        }
+    })
+}
+
+/// Takes rows until the first malformed one, which is kept in `malformed`. All rows taken are counted in `read`.
+fn until_malformed<'a>(
+    rows: impl Iterator<Item = Result<Transaction, ParseError>> + 'a,
+    read: &'a mut usize,
+    malformed: &'a mut Option<ParseError>,
+) -> impl Iterator<Item = Transaction> + 'a {
+    rows.map_while(move |row| {
+        *read += 1;
+        row.map_err(|err| *malformed = Some(err)).ok()
+    })
+}
+
+/// Sends the batch with `bank::send_batch`, adding the times it waited for the channel to `waits`.
+async fn send_batch(
+    sender: &mpsc::Sender<Transaction>,
+    batch: &mut Vec<Transaction>,
+    waits: &mut usize,
+) -> Result<(), mpsc::error::SendError<()>> {
+    *waits += bank::send_batch(sender, batch).await?;
+    Ok(())
+}
+
+/// Processes all transactions directly on the current thread.
+/// With `fail_fast` the first malformed row or rejected transaction stops the processing and is returned.
+#[instrument(skip_all)]
+pub fn process_sync(
+    reader: Rows,
+    mut state: State,
+    counters: &mut Counters,
+    fail_fast: bool,
+) -> Result<State, Failure> {
+    if !fail_fast {
+        state.process_rows(count_rows(
+            reader,
+            &mut counters.read,
+            &mut counters.skipped,
+        ));
+        return Ok(state);
+    }
+    let mut malformed = None;
+    state
+        .try_process_all(until_malformed(reader, &mut counters.read, &mut malformed))
+        .map_err(|(transaction, error)| Failure::Rejected(transaction, error))?;
+    match malformed {
+        Some(err) => Err(Failure::Malformed(err)),
+        None => Ok(state),
+    }
+}
+
+/// Streams the transactions in batches through a channel to the state handling task, and the malformed rows through
+/// another one for the state to record them.
+/// With `fail_fast` the first malformed row or rejected transaction stops the processing and is returned.
+#[instrument(skip_all, fields(channel_size))]
+pub async fn process_channel(
+    reader: Rows,
+    state: State,
+    channel_size: usize,
+    counters: &mut Counters,
+    fail_fast: bool,
+) -> Result<State, Failure> {
+    let (sender, receiver) = mpsc::channel(channel_size);
+    let (errors_sender, errors_receiver) = mpsc::unbounded_channel();
+    let mut state = state
+        .with_receiver(receiver)
+        .with_ingest_errors(errors_receiver);
+
+    let handle = tokio::spawn(async move {
+        if fail_fast {
+            state.run_strict().await.map(|()| state)
+        } else {
+            state.run().await;
+            Ok(state)
+        }
+    });
+
+    let mut malformed = None;
+    let rows: Box<dyn Iterator<Item = Result<Transaction, ParseError>> + '_> = if fail_fast {
+        Box::new(until_malformed(reader, &mut counters.read, &mut malformed).map(Ok))
+    } else {
+        Box::new(count_rows(
+            reader,
+            &mut counters.read,
+            &mut counters.skipped,
+        ))
+    };
+    // The rows are handed over in batches, so that the state isn't woken up for every single one of them.
+    let batch_size = BATCH_SIZE.min(channel_size);
+    let mut batch = Vec::with_capacity(batch_size);
+    let mut closed = false;
+    for row in rows {
+        match row {
+            Ok(transaction) => batch.push(transaction),
+            Err(err) => {
+                // The transactions before it are sent first, so the state records it in input order.
+                closed = send_batch(&sender, &mut batch, &mut counters.waits)
+                    .await
+                    .is_err();
+                let _ = errors_sender.send(err);
+            }
+        }
+        if batch.len() >= batch_size {
+            closed = send_batch(&sender, &mut batch, &mut counters.waits)
+                .await
+                .is_err();
+        }
+        if closed {
+            // The state stopped, either at a rejected transaction with `fail_fast` or because its task failed.
+            // Both are reported below, and none of the remaining rows could be processed anyway.
+            break;
+        }
+    }
+    if !closed {
+        // A failure to send the last partial batch is reported the same way.
+        let _ = send_batch(&sender, &mut batch, &mut counters.waits).await;
+    }
+
+    drop(sender); // Close the sender to signal no more transactions will be sent
+    drop(errors_sender);
+    let state = handle
+        .await
+        .expect("Failed to join the state handling task")
+        .map_err(|(transaction, error)| Failure::Rejected(transaction, error))?;
+    match malformed {
+        Some(err) => Err(Failure::Malformed(err)),
+        None => Ok(state),
+    }
+}
+
+/// Streams the transactions to `workers` tasks processing them in parallel, partitioned by client.
+/// The malformed rows are recorded in the merged state once the workers are done.
+#[instrument(skip_all, fields(workers, channel_size))]
+pub async fn process_sharded(
+    reader: Rows,
+    state: State,
+    workers: usize,
+    channel_size: usize,
+    counters: &mut Counters,
+) -> State {
+    let sharded = ShardedState::spawn(state, workers, channel_size);
+    let mut malformed = Vec::new();
+    for row in count_rows(reader, &mut counters.read, &mut counters.skipped) {
+        let transaction = match row {
+            Ok(transaction) => transaction,
+            Err(err) => {
+                malformed.push(err);
+                continue;
+            }
+        };
+        let result = match sharded.try_send(transaction) {
+            Err(TrySendError::Full(transaction)) => {
+                counters.waits += 1;
+                sharded.send(transaction).await
+            }
+            result => result.map_err(|err| mpsc::error::SendError(err.into_inner())),
+        };
+        if let Err(err) = result {
+            // A worker stopped, which `finish` reports.
+            error!("Error sending transaction: {err}");
+            break;
+        }
+    }
+    let mut state = sharded.finish().await;
+    for err in malformed {
+        state.record_ingest_error(err);
+    }
+    state
+}
+
+#[cfg(test)]
+mod tests {
+    use bank::{Money, State, Transaction, TransactionReader, TransactionType};
+    use tokio_util::sync::CancellationToken;
+
+    use crate::ingest::{Counters, Failure, Rows, process_channel, process_sync, until_cancelled};
+
+    /// Deposits to ten clients followed by a dispute of each of them, 40 transactions in total.
+    fn transactions() -> Vec<Transaction> {
+        (0..40)
+            .map(|tx| match tx {
+                0..20 => Transaction::new(
+                    TransactionType::Deposit,
+                    tx as u16 % 10,
+                    tx,
+                    Some(Money::from_raw(10000)),
+                ),
+                _ => Transaction::new(TransactionType::Dispute, tx as u16 % 10, tx - 20, None),
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_ingestion() {
+        let token = CancellationToken::new();
+        let cancel = token.clone();
+        // Cancels while the 26th row is being read, as a Ctrl-C in the middle of the input would.
+        let rows: Rows = Box::new(transactions().into_iter().enumerate().map(
+            move |(index, row)| {
+                if index == 25 {
+                    cancel.cancel();
+                }
+                Ok(row)
+            },
+        ));
+
+        let mut counters = Counters::default();
+        let state = process_channel(
+            until_cancelled(rows, token),
+            State::new_standalone(),
+            1,
+            &mut counters,
+            false,
+        )
+        .await
+        .unwrap();
+        assert_eq!(counters.read, 25);
+
+        let mut expected = State::new_standalone();
+        expected.process_all(transactions().into_iter().take(25));
+        assert_eq!(state.snapshot(), expected.snapshot());
+        assert_eq!(state.get_account(4).unwrap().held(), Money::from_raw(10000));
+        assert_eq!(state.get_account(5).unwrap().held(), Money::ZERO);
+    }
+
+    #[tokio::test]
+    async fn test_malformed_row_is_counted() {
+        let input = "type, client, tx, amount\n\
+                     deposit, 1, 1, 2.0\n\
+                     deposit, 1, 2, two\n\
+                     withdrawal, 1, 3, 0.5\n";
+        let rows: Rows = Box::new(TransactionReader::new(input.as_bytes()).unwrap());
+        let mut counters = Counters::default();
+        let state = process_sync(rows, State::new_standalone(), &mut counters, false).unwrap();
+        assert_eq!((counters.read, counters.skipped), (3, 1));
+        assert_eq!(
+            state.get_account(1).unwrap().available(),
+            Money::from_raw(15000)
+        );
+        assert_eq!(
+            state
+                .ingest_errors()
+                .iter()
+                .map(|err| err.line)
+                .collect::<Vec<_>>(),
+            [3]
+        );
+
+        // Through the channel the malformed row reaches the state as well.
+        let rows: Rows = Box::new(TransactionReader::new(input.as_bytes()).unwrap());
+        let mut counters = Counters::default();
+        let state = process_channel(rows, State::new_standalone(), 1, &mut counters, false)
+            .await
+            .unwrap();
+        assert_eq!((counters.read, counters.skipped), (3, 1));
+        assert_eq!(state.ingest_errors().len(), 1);
+        assert_eq!(state.ingest_errors()[0].record, "deposit,1,2,two");
+    }
+
+    #[tokio::test]
+    async fn test_fail_fast() {
+        let input = "type, client, tx, amount\n\
+                     deposit, 1, 1, 2.0\n\
+                     withdrawal, 1, 2, 5.0\n\
+                     deposit, 1, 3, two\n\
+                     deposit, 1, 4, 1.0\n";
+        let rows = || -> Rows { Box::new(TransactionReader::new(input.as_bytes()).unwrap()) };
+
+        let mut counters = Counters::default();
+        let failure = process_sync(rows(), State::new_standalone(), &mut counters, true)
+            .err()
+            .unwrap();
+        assert!(
+            matches!(&failure, Failure::Rejected(transaction, _) if transaction.get_line() == Some(3))
+        );
+        assert_eq!(counters.read, 2);
+
+        let mut counters = Counters::default();
+        let failure = process_channel(rows(), State::new_standalone(), 1, &mut counters, true)
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(
+            failure.to_string(),
+            "Rejected transaction at line 3 `withdrawal,1,2,5.0000`: Insufficient funds for transaction: requested 5.0000 but only 2.0000 available"
+        );
+
+        // Without the withdrawal it's the malformed row that stops the processing.
+        let input = input.replace("withdrawal, 1, 2, 5.0", "withdrawal, 1, 2, 0.5");
+        let rows: Rows = Box::new(TransactionReader::new(std::io::Cursor::new(input)).unwrap());
+        let mut counters = Counters::default();
+        let failure = process_sync(rows, State::new_standalone(), &mut counters, true)
+            .err()
+            .unwrap();
+        assert!(matches!(&failure, Failure::Malformed(err) if err.line == 4));
+        assert_eq!((counters.read, counters.skipped), (3, 0));
+    }
+}
@@ -0,0 +1,50 @@
+//! Simple banking engine processing deposits, withdrawals, disputes, resolves and chargebacks.
+//!
+//! Transactions can be processed synchronously with [`process`] or [`State::new_standalone`],
+//! or streamed into a [`State`] through a channel.
+use std::collections::HashMap;
+
+mod bank;
+
+pub use bank::*;
+
+/// Processes all transactions in order and returns the resulting accounts, keyed by client ID.
+/// Transactions that fail are skipped and logged as `tracing` warnings, which are only printed if the caller
+/// installed a subscriber.
+pub fn process(transactions: impl IntoIterator<Item = Transaction>) -> HashMap<ClientId, Account> {
+    let mut state = State::new_standalone();
+    state.process_all(transactions.into_iter());
+    state.into_accounts()
+}
+
+#[cfg(test)]
+mod tests {
+    use csv::{ReaderBuilder, Trim};
+
+    use crate::{Transaction, process};
+
+    #[test]
+    fn test_process() {
+        let input = "type, client, tx, amount\n\
+                     deposit, 1, 1, 2.0\n\
+                     deposit, 2, 2, 1.0\n\
+                     withdrawal, 1, 3, 0.5\n\
+                     withdrawal, 2, 4, 5.0\n";
+        let transactions = ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_reader(input.as_bytes())
+            .into_deserialize::<Transaction>()
+            .map(Result::unwrap);
+        let accounts = process(transactions);
+        assert_eq!(accounts.len(), 2);
+
+        let mut writer = csv::Writer::from_writer(vec![]);
+        writer.serialize(&accounts[&1]).unwrap();
+        writer.serialize(&accounts[&2]).unwrap();
+        let output = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+        assert_eq!(
+            output,
+            "client,available,held,total,locked\n1,1.5000,0.0000,1.5000,false\n2,1.0000,0.0000,1.0000,false\n"
+        );
+    }
+}
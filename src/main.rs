@@ -1,48 +1,408 @@
-use csv::{ReaderBuilder, Trim};
-use tokio::sync::mpsc;
+use std::{
+    fs::File,
+    io::{BufReader, IsTerminal, Read},
+    path::Path,
+    sync::Arc,
+    time::Duration,
+};
 
-mod bank;
+use flate2::read::MultiGzDecoder;
 
-/// The size of the channel for processing transactions.
-const CHANNEL_SIZE: usize = 100;
+use audit::{AuditLog, RunObserver};
+use bank::{JsonLinesReader, State, TransactionReader};
+use build_info::BuildInfo;
+use clap::{CommandFactory, Parser, error::ErrorKind};
+use cli::{Command, Compression, InputArgs, InputFormat, ProcessArgs};
+use error::AppError;
+use ingest::{Counters, Rows, process_channel, process_sharded, process_sync};
+use progress::{CountingReader, Progress, ProgressBar};
+use tokio::{net::TcpListener, sync::mpsc};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+use tracing_subscriber::EnvFilter;
+
+mod audit;
+mod build_info;
+mod cli;
+mod error;
+mod http;
+mod ingest;
+mod output;
+mod progress;
+mod rejects;
+mod server;
+mod validate;
+mod watch;
+
+/// Exit code of a run stopped by `--fail-fast`.
+const FAIL_FAST_EXIT_CODE: i32 = 3;
+
+/// Exit code of a run that skipped malformed rows, unless `--lenient` is passed.
+const INGEST_ERRORS_EXIT_CODE: i32 = 4;
+
+/// Logs the error and exits with a non-zero code.
+/// Only for failures deep inside a reader or an observer, which can't return an `AppError` to `main`.
+fn exit_with_error(err: impl std::fmt::Display) -> ! {
+    error!("{err}");
+    std::process::exit(1);
+}
+
+/// Logs to stderr, filtered by `RUST_LOG` (e.g. `RUST_LOG=bank=debug`).
+/// Without it only warnings and errors are logged, or also info with `verbose`.
+fn init_tracing(verbose: bool) {
+    let default = if verbose { "info" } else { "warn" };
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(default));
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr)
+        .with_ansi(std::io::stderr().is_terminal())
+        .init();
+}
+
+/// Stops the run when the wrapped decoder fails. A corrupted archive can't be read any further, so unlike a malformed
+/// row it isn't skipped, the report would silently miss the rest of the input.
+struct ExitOnError<R>(R);
+
+impl<R: Read> Read for ExitOnError<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self.0.read(buf) {
+            Err(err) if err.kind() != std::io::ErrorKind::Interrupted => {
+                exit_with_error(format!("Failed to decompress the input: {err}"))
+            }
+            result => result,
+        }
+    }
+}
+
+/// Opens the inputs and chains their rows in the given order. All of them are opened and their headers checked
+/// before any row is read. With several inputs every row is tagged with the name of its file.
+/// The bytes read from the files are counted into the progress, if any.
+fn open_input(args: &InputArgs, progress: Option<&Arc<Progress>>) -> Result<Rows, AppError> {
+    let inputs = args
+        .inputs
+        .iter()
+        .map(|path| {
+            let rows = open_file(args, path, progress)?;
+            if args.inputs.len() == 1 {
+                return Ok(rows);
+            }
+            let file: Arc<str> = Arc::from(path.display().to_string());
+            Ok(Box::new(rows.map(move |row| {
+                row.map(|transaction| transaction.with_file(file.clone()))
+                    .map_err(|err| err.with_file(file.clone()))
+            })) as Rows)
+        })
+        .collect::<Result<Vec<_>, AppError>>()?;
+    Ok(Box::new(inputs.into_iter().flatten()))
+}
+
+/// Opens a single input and creates the reader matching its format.
+fn open_file(
+    args: &InputArgs,
+    path: &Path,
+    progress: Option<&Arc<Progress>>,
+) -> Result<Rows, AppError> {
+    let input: Box<dyn Read> = if path == Path::new("-") {
+        Box::new(std::io::stdin())
+    } else {
+        let file =
+            File::open(path).map_err(AppError::io(format!("Failed to open {}", path.display())))?;
+        // Counted before decompression, so that the progress compares to the size of the file.
+        match progress {
+            Some(progress) => Box::new(CountingReader::new(file, progress.clone())),
+            None => Box::new(file),
+        }
+    };
+    read_rows(input, args.format(path), args.compression(path))
+}
+
+/// Decompresses the input if needed and creates the reader of the format.
+fn read_rows(
+    input: Box<dyn Read>,
+    format: InputFormat,
+    compression: Compression,
+) -> Result<Rows, AppError> {
+    let input: Box<dyn Read> = match compression {
+        Compression::None => input,
+        Compression::Gzip => Box::new(ExitOnError(MultiGzDecoder::new(input))),
+        Compression::Zstd => Box::new(ExitOnError(
+            zstd::Decoder::new(input).map_err(AppError::io("Failed to decompress the input"))?,
+        )),
+    };
+    Ok(match format {
+        InputFormat::Csv => {
+            let reader = TransactionReader::new(input)?;
+            bank::check_headers(reader.headers())?;
+            Box::new(reader)
+        }
+        InputFormat::Jsonl => Box::new(JsonLinesReader::new(BufReader::new(input))),
+    })
+}
 
 #[tokio::main]
 async fn main() {
-    let args = std::env::args().collect::<Vec<_>>();
-    if args.len() != 2 {
-        eprintln!("Usage: {} <input_csv_file>", args[0]);
-        std::process::exit(1);
+    match run().await {
+        Ok(()) => {}
+        // Clap prints the usage or the help itself, and picks the exit code.
+        Err(AppError::Args(err)) => err.exit(),
+        Err(err) => exit_with_error(err),
     }
-    let input_file = &args[1];
-
-    let (sender, receiver) = mpsc::channel(CHANNEL_SIZE);
-    let mut state = bank::State::new(receiver);
+}
 
-    let handle = tokio::spawn(async move {
-        state.run().await;
-        state
+/// Parses the command line and runs the command. Returning the failure instead of exiting lets `main` report all of
+/// them the same way.
+async fn run() -> Result<(), AppError> {
+    let cli = cli::Cli::try_parse()?;
+    if let Some(json) = match &cli.command {
+        Some(Command::Version(args)) => Some(args.json),
+        _ if cli.version => Some(cli.json),
+        _ => None,
+    } {
+        return BuildInfo::current()
+            .write(json, std::io::stdout().lock())
+            .map_err(AppError::io("Failed to write the version"));
+    }
+    init_tracing(match &cli.command {
+        Some(Command::Process(args)) => args.verbose,
+        Some(Command::Validate(_)) => false,
+        Some(Command::Serve(args)) => args.verbose,
+        Some(Command::Version(_)) => false,
+        None => cli.process.verbose,
     });
+    if let Some(mode) = match &cli.command {
+        Some(Command::Process(args)) => Some(args.source.rounding_mode(args.fail_fast)),
+        // Validating reports extra digits as problems instead of hiding them by rounding.
+        Some(Command::Validate(args)) => Some(args.rounding_mode(true)),
+        Some(Command::Serve(_) | Command::Version(_)) => None,
+        None => Some(cli.process.source.rounding_mode(cli.process.fail_fast)),
+    } {
+        bank::set_rounding_mode(mode);
+    }
+    match cli.command {
+        Some(Command::Process(args)) => process(args).await,
+        Some(Command::Validate(args)) => {
+            let report = validate::validate(open_input(&args, None)?);
+            report
+                .write(std::io::stdout().lock())
+                .map_err(AppError::io("Failed to write the report"))?;
+            if !report.problems.is_empty() {
+                std::process::exit(1);
+            }
+            Ok(())
+        }
+        Some(Command::Serve(args)) => http::serve(args).await,
+        Some(Command::Version(_)) => Ok(()),
+        None => process(cli.process).await,
+    }
+}
 
-    let mut reader = ReaderBuilder::new()
-        .trim(Trim::All)
-        .from_path(input_file)
-        .expect("Failed to read CSV file");
+/// The error of `--watch` without a single input file to follow.
+fn watched_input_error() -> AppError {
+    cli::Cli::command()
+        .error(
+            ErrorKind::ArgumentConflict,
+            "--watch follows a single input file, not stdin or several files",
+        )
+        .into()
+}
 
-    for transaction in reader.deserialize().flatten() {
-        if let Err(err) = sender.send(transaction).await {
-            eprintln!("Error sending transaction: {err}");
+/// Processes the input and writes the account report.
+async fn process(cli: ProcessArgs) -> Result<(), AppError> {
+    // The first Ctrl-C stops reading the input, the accounts processed so far are still reported.
+    // A second one exits right away, e.g. when the input blocks.
+    let token = CancellationToken::new();
+    tokio::spawn({
+        let token = token.clone();
+        async move {
+            if tokio::signal::ctrl_c().await.is_ok() {
+                token.cancel();
+                if tokio::signal::ctrl_c().await.is_ok() {
+                    std::process::exit(130);
+                }
+            }
         }
+    });
+
+    // A checkpoint doesn't exist yet on the first run, unlike an explicit `--snapshot-in`.
+    let snapshot_in = cli
+        .snapshot_in
+        .as_deref()
+        .or(cli.checkpoint.as_deref().filter(|path| path.exists()));
+    let state = snapshot_in
+        .map(State::load_snapshot)
+        .transpose()?
+        .unwrap_or_else(State::new_standalone);
+    let mut state = state.with_config(cli.engine.config());
+    let mut observer = RunObserver::default();
+    // The metadata of the inputs, if all of them are regular files.
+    let regular_files = cli
+        .source
+        .inputs
+        .iter()
+        .map(|input| input.metadata().ok().filter(|metadata| metadata.is_file()))
+        .collect::<Option<Vec<_>>>();
+    // Drawn only on a terminal, where it doesn't end up in a log.
+    let progress = (cli.progress && regular_files.is_some() && std::io::stderr().is_terminal())
+        .then(Arc::<Progress>::default);
+    observer.progress = progress.clone();
+    let mut rejects = None;
+    if cli.rejects.is_some() || cli.validate {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        observer.rejects = Some(sender);
+        rejects = Some(receiver);
+    }
+    if let Some(path) = &cli.audit_log {
+        observer.audit_log = Some(AuditLog::create(path).map_err(AppError::io(format!(
+            "Failed to create the audit log {}",
+            path.display()
+        )))?);
+    }
+    if observer.rejects.is_some() || observer.audit_log.is_some() || observer.progress.is_some() {
+        state = state.with_transaction_observer(observer);
     }
 
-    drop(sender); // Close the sender to signal no more transactions will be sent
-    let state = handle
+    let mut counters = Counters::default();
+    let state = if cli.watch {
+        let [path] = cli.source.inputs.as_slice() else {
+            return Err(watched_input_error());
+        };
+        if path == Path::new("-") {
+            return Err(watched_input_error());
+        }
+        let reader = ingest::until_cancelled(
+            watch::follow(&cli.source, path, token.clone())?,
+            token.clone(),
+        );
+        let report = watch::Report {
+            format: cli.output_format,
+            precision: cli.precision,
+            output: cli.output.clone(),
+            interval: cli.report_interval.map(Duration::from_secs),
+        };
+        watch::process_watched(reader, state, cli.channel_size, report, &mut counters).await?
+    } else if let Some(addr) = &cli.listen {
+        let listener = TcpListener::bind(addr)
+            .await
+            .map_err(AppError::io(format!("Failed to listen on {addr}")))?;
+        server::listen(
+            listener,
+            state,
+            cli.channel_size,
+            cli.precision,
+            token.clone(),
+        )
         .await
-        .expect("Failed to join the state handling task");
+    } else {
+        let rows = match &progress {
+            Some(progress) => progress.count_rows(open_input(&cli.source, Some(progress))?),
+            None => open_input(&cli.source, None)?,
+        };
+        let reader = ingest::until_cancelled(rows, token.clone());
+        let total = regular_files
+            .iter()
+            .flatten()
+            .map(|metadata| metadata.len())
+            .sum();
+        let bar = progress.map(|progress| ProgressBar::start(progress, total));
+        // A regular file can be read at the pace of the processing, so there is no need for the channel.
+        let result = if cli.workers > 1 {
+            Ok(process_sharded(
+                reader,
+                state,
+                cli.workers.into(),
+                cli.channel_size,
+                &mut counters,
+            )
+            .await)
+        } else if regular_files.is_some() {
+            process_sync(reader, state, &mut counters, cli.fail_fast)
+        } else {
+            process_channel(
+                reader,
+                state,
+                cli.channel_size,
+                &mut counters,
+                cli.fail_fast,
+            )
+            .await
+        };
+        if let Some(bar) = bar {
+            bar.finish();
+        }
+        match result {
+            Ok(state) => state,
+            Err(failure) => {
+                error!("{failure}");
+                std::process::exit(FAIL_FAST_EXIT_CODE);
+            }
+        }
+    };
+    // Listening and watching go on until Ctrl-C, so it doesn't cut them short.
+    if token.is_cancelled() && cli.listen.is_none() && !cli.watch {
+        warn!(
+            read = counters.read,
+            "Interrupted after {} rows, the report covers only those", counters.read
+        );
+    }
+    if counters.skipped > 0 {
+        warn!(
+            skipped = counters.skipped,
+            "Skipped {} malformed rows", counters.skipped
+        );
+    }
+    info!(
+        waits = counters.waits,
+        "The reader waited {} times for a full channel", counters.waits
+    );
 
-    let mut writer = csv::Writer::from_writer(std::io::stdout());
-    for account in state.get_all_accounts().values() {
-        if let Err(err) = writer.serialize(account) {
-            eprintln!("Error writing account: {err}");
+    if cli.stats {
+        output::write_stats(
+            counters.read,
+            counters.skipped,
+            &state.stats(),
+            std::io::stderr().lock(),
+        )
+        .map_err(AppError::io("Failed to write the stats"))?;
+    }
+    if cli.validate
+        && let Some(mut receiver) = rejects
+    {
+        let report = validate::dry_run_report(&mut receiver, &state.stats(), counters.skipped);
+        report
+            .write(std::io::stdout().lock())
+            .map_err(AppError::io("Failed to write the report"))?;
+        if !report.problems.is_empty() || report.malformed > 0 {
+            std::process::exit(1);
         }
+        return Ok(());
+    }
+    if cli.self_check
+        && let Err(failures) = state.verify_all()
+    {
+        for (client, failure) in failures {
+            error!(client, "Account {client} is inconsistent: {failure}");
+        }
+        std::process::exit(1);
+    }
+    if let Some(path) = cli.snapshot_out.as_ref().or(cli.checkpoint.as_ref()) {
+        state.save_snapshot(path)?;
+    }
+    if let (Some(path), Some(mut receiver)) = (&cli.rejects, rejects) {
+        output::write_atomically(path, |writer| rejects::write_rejects(&mut receiver, writer))?;
+    }
+
+    let summaries = state
+        .accounts_sorted()
+        .into_iter()
+        .flat_map(|account| account.summaries(cli.precision));
+    match &cli.output {
+        Some(path) => output::write_atomically(path, |writer| {
+            output::write_report(summaries, cli.output_format, writer)
+        })?,
+        None => output::write_report(summaries, cli.output_format, std::io::stdout().lock())?,
+    }
+    if !cli.lenient && !state.ingest_errors().is_empty() {
+        std::process::exit(INGEST_ERRORS_EXIT_CODE);
     }
+    Ok(())
 }
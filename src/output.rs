@@ -0,0 +1,268 @@
+//! Writing the final account report and the run statistics.
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use bank::{AccountSummary, Stats, TransactionType};
+use clap::ValueEnum;
+use thiserror::Error;
+
+/// Format of the account report.
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// One CSV row per account, with a header row.
+    #[default]
+    Csv,
+    /// A JSON array with one object per account.
+    Json,
+    /// One JSON object per account and line, written as the accounts come.
+    #[value(alias = "jsonl")]
+    Ndjson,
+}
+
+/// Writes the account summaries to `writer` in the given format.
+/// The CSV report only has a `currency` column if some summary is in a named currency, in which case it's empty
+/// for the implicit currency. The JSON objects only have a `currency` field for named currencies.
+/// All formats serialize the same `AccountSummary`, so they carry the same fields and amounts.
+pub fn write_report(
+    summaries: impl Iterator<Item = AccountSummary>,
+    format: OutputFormat,
+    mut writer: impl Write,
+) -> Result<(), OutputError> {
+    match format {
+        OutputFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(writer);
+            let summaries = summaries.collect::<Vec<_>>();
+            if summaries.iter().any(|summary| summary.currency.is_some()) {
+                writer.write_record([
+                    "client",
+                    "currency",
+                    "available",
+                    "held",
+                    "total",
+                    "locked",
+                ])?;
+                for summary in summaries {
+                    writer.write_record([
+                        summary.client.to_string(),
+                        summary
+                            .currency
+                            .map(|currency| currency.to_string())
+                            .unwrap_or_default(),
+                        summary.available.to_string(),
+                        summary.held.to_string(),
+                        summary.total.to_string(),
+                        summary.locked.to_string(),
+                    ])?;
+                }
+            } else {
+                for summary in summaries {
+                    writer.serialize(summary)?;
+                }
+            }
+            writer.flush()?;
+        }
+        OutputFormat::Json => {
+            serde_json::to_writer(&mut writer, &summaries.collect::<Vec<_>>())?;
+            writeln!(writer)?;
+        }
+        OutputFormat::Ndjson => {
+            for summary in summaries {
+                serde_json::to_writer(&mut writer, &summary)?;
+                writeln!(writer)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Writes a summary of the run: the rows read and skipped by the binary and the counters of the engine.
+/// Types and errors that didn't occur are left out.
+pub fn write_stats(
+    read: usize,
+    skipped: usize,
+    stats: &Stats,
+    mut writer: impl Write,
+) -> std::io::Result<()> {
+    writeln!(writer, "Rows read: {read}")?;
+    writeln!(writer, "Rows skipped: {skipped}")?;
+    let types = TransactionType::ALL
+        .into_iter()
+        .filter(|tx_type| stats.count(*tx_type) > 0)
+        .map(|tx_type| match stats.failed(tx_type) {
+            0 => format!("{tx_type} {}", stats.count(tx_type)),
+            failed => format!("{tx_type} {} ({failed} failed)", stats.count(tx_type)),
+        })
+        .collect::<Vec<_>>();
+    writeln!(writer, "Transactions: {}", types.join(", "))?;
+    write!(writer, "Rejected: {}", stats.rejected())?;
+    if !stats.rejections().is_empty() {
+        let rejections = stats
+            .rejections()
+            .iter()
+            .map(|(name, count)| format!("{name} {count}"))
+            .collect::<Vec<_>>();
+        write!(writer, " ({})", rejections.join(", "))?;
+    }
+    writeln!(writer)?;
+    writeln!(writer, "Accounts created: {}", stats.accounts_created())?;
+    writeln!(writer, "Locked accounts: {}", stats.locked_accounts())
+}
+
+/// Writes a file atomically: `write` fills a temporary file next to `path`, which is renamed to `path` only if it succeeds.
+/// On failure the temporary file is removed and any previous content of `path` stays untouched.
+pub fn write_atomically(
+    path: &Path,
+    write: impl FnOnce(&mut BufWriter<File>) -> Result<(), OutputError>,
+) -> Result<(), OutputError> {
+    let temp_path = temp_path(path);
+    let result = File::create(&temp_path)
+        .map_err(OutputError::from)
+        .and_then(|file| {
+            let mut writer = BufWriter::new(file);
+            write(&mut writer)?;
+            let file = writer.into_inner().map_err(|err| err.into_error())?;
+            file.sync_all()?;
+            Ok(())
+        })
+        .and_then(|()| Ok(fs::rename(&temp_path, path)?));
+    if result.is_err() {
+        // The temporary file may not even exist, there is nothing more to do if it can't be removed.
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+/// Path of the temporary file used while writing `path`, in the same directory so the rename doesn't cross filesystems.
+fn temp_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".{}.tmp", std::process::id()));
+    path.with_file_name(name)
+}
+
+/// Errors that can occur while writing the report.
+#[derive(Error, Debug)]
+pub enum OutputError {
+    #[error("Failed to write the report: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Failed to write CSV: {0}")]
+    Csv(#[from] csv::Error),
+    #[error("Failed to write JSON: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use bank::{Account, Money, Transaction, TransactionType};
+
+    use std::{fs, io::Write};
+
+    use crate::output::{OutputError, OutputFormat, temp_path, write_atomically, write_report};
+
+    /// Creates an account with 1.5 available and 0.25 held.
+    fn account() -> Account {
+        let mut account = Account::new(1);
+        for transaction in [
+            Transaction::new(TransactionType::Deposit, 1, 1, Some(Money::from_raw(15000))),
+            Transaction::new(TransactionType::Deposit, 1, 2, Some(Money::from_raw(2500))),
+            Transaction::new(TransactionType::Dispute, 1, 2, None),
+        ] {
+            account.process_transaction(transaction).unwrap();
+        }
+        account
+    }
+
+    #[test]
+    fn test_csv_report() {
+        let mut output = vec![];
+        write_report(
+            [account().summary(4)].into_iter(),
+            OutputFormat::Csv,
+            &mut output,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,available,held,total,locked\n1,1.5000,0.2500,1.7500,false\n"
+        );
+    }
+
+    #[test]
+    fn test_json_report() {
+        let mut output = vec![];
+        write_report(
+            [account().summary(4)].into_iter(),
+            OutputFormat::Json,
+            &mut output,
+        )
+        .unwrap();
+        let report: serde_json::Value = serde_json::from_slice(&output).unwrap();
+        assert_eq!(
+            report,
+            serde_json::json!([{
+                "client": 1,
+                "available": "1.5000",
+                "held": "0.2500",
+                "total": "1.7500",
+                "locked": false,
+            }])
+        );
+    }
+
+    #[test]
+    fn test_ndjson_report() {
+        let mut second = Account::new(2);
+        second
+            .process_transaction(Transaction::new(
+                TransactionType::Deposit,
+                2,
+                3,
+                Some(Money::from_raw(1)),
+            ))
+            .unwrap();
+        let mut output = vec![];
+        write_report(
+            [account().summary(4), second.summary(4)].into_iter(),
+            OutputFormat::Ndjson,
+            &mut output,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "{\"client\":1,\"available\":\"1.5000\",\"held\":\"0.2500\",\"total\":\"1.7500\",\"locked\":false}\n\
+             {\"client\":2,\"available\":\"0.0001\",\"held\":\"0.0000\",\"total\":\"0.0001\",\"locked\":false}\n"
+        );
+    }
+
+    #[test]
+    fn test_write_atomically() {
+        let path = std::env::temp_dir().join(format!("bank-report-{}.csv", std::process::id()));
+        fs::write(&path, "previous report\n").unwrap();
+
+        // A failure halfway through leaves the previous report in place.
+        let result = write_atomically(&path, |writer| {
+            writer.write_all(b"client,available")?;
+            Err(OutputError::Io(std::io::Error::other("simulated failure")))
+        });
+        assert!(result.is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "previous report\n");
+        assert!(!temp_path(&path).exists());
+
+        write_atomically(&path, |writer| {
+            write_report(
+                [account().summary(2)].into_iter(),
+                OutputFormat::Csv,
+                writer,
+            )
+        })
+        .unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "client,available,held,total,locked\n1,1.50,0.25,1.75,false\n"
+        );
+        assert!(!temp_path(&path).exists());
+        fs::remove_file(&path).unwrap();
+    }
+}
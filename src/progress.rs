@@ -0,0 +1,190 @@
+//! The progress bar of `--progress`, drawn on stderr while a large input is processed.
+use std::{
+    io::{self, Read, Write},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crate::ingest::Rows;
+
+/// How often the bar is redrawn.
+const REDRAW_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Width of the bar itself, without the counters next to it.
+const BAR_WIDTH: usize = 30;
+
+/// The counters the bar is drawn from, updated by the ingestion as it goes.
+#[derive(Default, Debug)]
+pub struct Progress {
+    /// Bytes read from the input files, before decompression.
+    bytes: AtomicU64,
+    /// Rows taken from the input, including the malformed ones.
+    rows: AtomicU64,
+    /// Transactions rejected by the engine.
+    rejected: AtomicU64,
+}
+
+impl Progress {
+    /// Counts a rejected transaction.
+    pub fn count_rejected(&self) {
+        self.rejected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts the rows taken from `rows`.
+    pub fn count_rows(self: &Arc<Self>, rows: Rows) -> Rows {
+        let progress = self.clone();
+        Box::new(rows.inspect(move |_| {
+            progress.rows.fetch_add(1, Ordering::Relaxed);
+        }))
+    }
+
+    /// Renders the bar for `total` input bytes after `elapsed`, e.g.
+    /// `[=======>      ]  45% 4.5 MB/10.0 MB, 120000 rows (60000 rows/s), 12 rejected`.
+    fn render(&self, total: u64, elapsed: Duration) -> String {
+        let bytes = self.bytes.load(Ordering::Relaxed).min(total);
+        let rows = self.rows.load(Ordering::Relaxed);
+        let done = if total == 0 {
+            1.0
+        } else {
+            bytes as f64 / total as f64
+        };
+        let filled = (done * BAR_WIDTH as f64) as usize;
+        let bar = match filled {
+            BAR_WIDTH => "=".repeat(BAR_WIDTH),
+            _ => format!(
+                "{}>{}",
+                "=".repeat(filled),
+                " ".repeat(BAR_WIDTH - filled - 1)
+            ),
+        };
+        let rate = rows as f64 / elapsed.as_secs_f64().max(f64::EPSILON);
+        format!(
+            "[{bar}] {:>3}% {}/{}, {rows} rows ({rate:.0} rows/s), {} rejected",
+            (done * 100.0) as u32,
+            format_bytes(bytes),
+            format_bytes(total),
+            self.rejected.load(Ordering::Relaxed)
+        )
+    }
+}
+
+/// Formats a number of bytes with a binary unit, e.g. `4.5 MB`.
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    match unit {
+        0 => format!("{bytes} B"),
+        _ => format!("{value:.1} {}", UNITS[unit]),
+    }
+}
+
+/// Counts the bytes read through it into the progress.
+pub struct CountingReader<R> {
+    /// The reader being counted.
+    inner: R,
+    /// Where the bytes are counted.
+    progress: Arc<Progress>,
+}
+
+impl<R> CountingReader<R> {
+    /// Wraps the reader.
+    pub fn new(inner: R, progress: Arc<Progress>) -> Self {
+        CountingReader { inner, progress }
+    }
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.progress
+            .bytes
+            .fetch_add(read as u64, Ordering::Relaxed);
+        Ok(read)
+    }
+}
+
+/// The thread redrawing the bar until `finish` is called.
+pub struct ProgressBar {
+    /// Stops the thread.
+    done: Arc<AtomicBool>,
+    /// The thread, which draws the bar a last time before it ends.
+    thread: JoinHandle<()>,
+}
+
+impl ProgressBar {
+    /// Starts drawing the progress of reading `total` bytes.
+    pub fn start(progress: Arc<Progress>, total: u64) -> Self {
+        let done = Arc::new(AtomicBool::new(false));
+        let thread = thread::spawn({
+            let done = done.clone();
+            move || {
+                let start = Instant::now();
+                loop {
+                    let finished = done.load(Ordering::Relaxed);
+                    // Redrawn in place, the line is only ended once the input is done.
+                    let line = progress.render(total, start.elapsed());
+                    let _ = write!(io::stderr(), "\r{line}");
+                    if finished {
+                        let _ = writeln!(io::stderr());
+                        return;
+                    }
+                    thread::sleep(REDRAW_INTERVAL);
+                }
+            }
+        });
+        ProgressBar { done, thread }
+    }
+
+    /// Draws the final state of the bar and ends its line, so that what is logged next starts on a line of its own.
+    pub fn finish(self) {
+        self.done.store(true, Ordering::Relaxed);
+        let _ = self.thread.join();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::Read,
+        sync::{Arc, atomic::Ordering},
+        time::Duration,
+    };
+
+    use crate::progress::{CountingReader, Progress, format_bytes};
+
+    #[test]
+    fn test_render() {
+        let progress = Arc::new(Progress::default());
+        let mut reader = CountingReader::new(&[0u8; 4608][..], progress.clone());
+        let mut buf = vec![];
+        reader.read_to_end(&mut buf).unwrap();
+        assert_eq!(progress.bytes.load(Ordering::Relaxed), 4608);
+
+        progress.rows.store(1200, Ordering::Relaxed);
+        progress.count_rejected();
+        assert_eq!(
+            progress.render(9216, Duration::from_secs(2)),
+            "[===============>              ]  50% 4.5 KB/9.0 KB, 1200 rows (600 rows/s), 1 rejected"
+        );
+        assert_eq!(
+            progress.render(4608, Duration::from_secs(1)),
+            "[==============================] 100% 4.5 KB/4.5 KB, 1200 rows (1200 rows/s), 1 rejected"
+        );
+    }
+
+    #[test]
+    fn test_format_bytes() {
+        assert_eq!(format_bytes(0), "0 B");
+        assert_eq!(format_bytes(1023), "1023 B");
+        assert_eq!(format_bytes(10 * 1024 * 1024 * 1024), "10.0 GB");
+    }
+}
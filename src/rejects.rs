@@ -0,0 +1,128 @@
+//! Report of the transactions the engine refused, with the reason for each of them.
+use std::io::Write;
+
+use bank::{ClientId, Money, Transaction, TransactionError, TransactionId, TransactionType};
+use serde::Serialize;
+use tokio::sync::mpsc;
+
+use crate::output::OutputError;
+
+/// A row of the rejects report.
+#[derive(Serialize, Debug)]
+pub struct RejectedTransaction {
+    /// The line of the input the transaction was read from, prefixed with its file when reading several.
+    pub line: Option<String>,
+    /// The type of the transaction.
+    #[serde(rename = "type")]
+    pub tx_type: TransactionType,
+    /// The client of the transaction.
+    pub client: ClientId,
+    /// The ID of the transaction.
+    pub tx: TransactionId,
+    /// The amount of the transaction, if it had one.
+    pub amount: Option<Money>,
+    /// Why the transaction was rejected.
+    pub reason: String,
+    /// The amount a transaction rejected for insufficient funds needed.
+    pub requested: Option<Money>,
+    /// The available balance of the account when a transaction was rejected for insufficient funds.
+    pub available: Option<Money>,
+}
+
+impl RejectedTransaction {
+    /// Creates the report row of a transaction that failed with the given error.
+    pub fn new(transaction: &Transaction, error: &TransactionError) -> Self {
+        let (requested, available) = match *error {
+            TransactionError::InsufficientFunds {
+                requested,
+                available,
+            } => (Some(requested), Some(available)),
+            _ => (None, None),
+        };
+        RejectedTransaction {
+            line: transaction
+                .get_line()
+                .map(|line| bank::line_in_file(transaction.get_file().map(|file| &**file), line)),
+            tx_type: *transaction.get_type(),
+            client: transaction.get_client_id(),
+            tx: transaction.get_transaction_id(),
+            amount: transaction.get_amount(),
+            reason: error.to_string(),
+            requested,
+            available,
+        }
+    }
+}
+
+/// Writes every rejected transaction received so far as CSV, in the order they were rejected.
+pub fn write_rejects(
+    receiver: &mut mpsc::UnboundedReceiver<(Transaction, TransactionError)>,
+    writer: impl Write,
+) -> Result<(), OutputError> {
+    let mut writer = csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_writer(writer);
+    writer.write_record([
+        "line",
+        "type",
+        "client",
+        "tx",
+        "amount",
+        "reason",
+        "requested",
+        "available",
+    ])?;
+    while let Ok((transaction, error)) = receiver.try_recv() {
+        writer.serialize(RejectedTransaction::new(&transaction, &error))?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use bank::{State, Transaction, TransactionReader};
+    use tokio::sync::mpsc;
+
+    use crate::rejects::write_rejects;
+
+    #[test]
+    fn test_rejects_report() {
+        let input = "type, client, tx, amount\n\
+                     deposit, 1, 1, 1.0\n\
+                     withdrawal, 1, 2, 2.5\n\
+                     dispute, 1, 9,\n\
+                     deposit, 2, 1, 3.0\n\
+                     dispute, 1, 1,\n\
+                     resolve, 1, 3,\n";
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let mut state = State::new_standalone().with_error_sink(sender);
+        state.process_all(
+            TransactionReader::new(input.as_bytes())
+                .unwrap()
+                .map(Result::unwrap),
+        );
+
+        let mut output = vec![];
+        write_rejects(&mut receiver, &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "line,type,client,tx,amount,reason,requested,available\n\
+             3,withdrawal,1,2,2.5000,Insufficient funds for transaction: requested 2.5000 but only 1.0000 available,2.5000,1.0000\n\
+             4,dispute,1,9,,Transaction does not exist,,\n\
+             5,deposit,2,1,3.0000,Transaction ID is already used by another transaction,,\n\
+             7,resolve,1,3,,Transaction does not exist,,\n"
+        );
+    }
+
+    #[test]
+    fn test_empty_rejects_report() {
+        let (_sender, mut receiver) = mpsc::unbounded_channel::<(Transaction, _)>();
+        let mut output = vec![];
+        write_rejects(&mut receiver, &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "line,type,client,tx,amount,reason,requested,available\n"
+        );
+    }
+}
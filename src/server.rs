@@ -0,0 +1,145 @@
+//! Serving the engine over TCP: clients send transactions as JSON lines and can ask for the current accounts.
+use std::{io, net::SocketAddr};
+
+use bank::{ParseError, Request, State, parse_json_transaction};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+    task::JoinSet,
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, instrument, warn};
+
+use crate::output::{self, OutputFormat};
+
+/// The line a client sends to get the account report.
+const DUMP: &str = "dump";
+
+/// Accepts connections until the token is cancelled, feeding their transactions to the state in the order they arrive.
+/// Returns the state once every connection was closed.
+pub async fn listen(
+    listener: TcpListener,
+    mut state: State,
+    channel_size: usize,
+    precision: usize,
+    token: CancellationToken,
+) -> State {
+    if let Ok(addr) = listener.local_addr() {
+        info!("Listening on {addr}");
+    }
+    let (sender, receiver) = mpsc::channel(channel_size);
+    let handle = tokio::spawn(async move {
+        state.serve(receiver).await;
+        state
+    });
+
+    let mut connections = JoinSet::new();
+    loop {
+        tokio::select! {
+            _ = token.cancelled() => break,
+            accepted = listener.accept() => match accepted {
+                Ok((stream, peer)) => {
+                    connections.spawn(handle_connection(
+                        stream,
+                        peer,
+                        sender.clone(),
+                        precision,
+                        token.clone(),
+                    ));
+                }
+                Err(err) => warn!("Failed to accept a connection: {err}"),
+            },
+        }
+    }
+
+    // The connections stop on the cancellation too, closing the channel once they are all gone.
+    drop(sender);
+    connections.join_all().await;
+    handle
+        .await
+        .expect("Failed to join the state handling task")
+}
+
+/// Serves a single client until it disconnects or the token is cancelled.
+#[instrument(skip_all, fields(%peer))]
+async fn handle_connection(
+    stream: TcpStream,
+    peer: SocketAddr,
+    sender: mpsc::Sender<Request>,
+    precision: usize,
+    token: CancellationToken,
+) {
+    info!("Client connected");
+    if let Err(err) = serve_client(stream, &sender, precision, &token).await {
+        warn!("Connection failed: {err}");
+    }
+}
+
+/// Reads the lines of the client, sending its transactions to the state and answering `dump` with the account report.
+/// Malformed lines are answered with the reason they were rejected.
+async fn serve_client(
+    stream: TcpStream,
+    sender: &mpsc::Sender<Request>,
+    precision: usize,
+    token: &CancellationToken,
+) -> io::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    let mut line_number = 0;
+    loop {
+        let line = tokio::select! {
+            _ = token.cancelled() => return Ok(()),
+            line = lines.next_line() => line?,
+        };
+        let Some(line) = line else {
+            return Ok(());
+        };
+        line_number += 1;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        if line == DUMP {
+            let (query, summaries) = Request::query(move |state| {
+                state
+                    .accounts_sorted()
+                    .into_iter()
+                    .flat_map(|account| account.summaries(precision))
+                    .collect::<Vec<_>>()
+            });
+            if sender.send(query).await.is_err() {
+                return Ok(());
+            }
+            let Ok(summaries) = summaries.await else {
+                return Ok(());
+            };
+            let mut report = Vec::new();
+            output::write_report(summaries.into_iter(), OutputFormat::Csv, &mut report)
+                .map_err(io::Error::other)?;
+            report.push(b'\n');
+            writer.write_all(&report).await?;
+            continue;
+        }
+
+        match parse_json_transaction(line) {
+            Ok(transaction) => {
+                let request = Request::Transaction(transaction.with_line(line_number));
+                if sender.send(request).await.is_err() {
+                    return Ok(());
+                }
+            }
+            Err(message) => {
+                let err = ParseError {
+                    file: None,
+                    line: line_number,
+                    record: line.to_string(),
+                    message,
+                };
+                warn!("{err}");
+                writer.write_all(format!("{err}\n").as_bytes()).await?;
+            }
+        }
+    }
+}
@@ -0,0 +1,219 @@
+//! Checking the input for malformed rows and structural problems, without processing the transactions,
+//! and reporting what the engine rejected in a dry run.
+use std::{
+    collections::{HashMap, hash_map::Entry},
+    io::{self, Write},
+    sync::Arc,
+};
+
+use bank::{
+    ClientId, Money, Stats, Transaction, TransactionError, TransactionId, TransactionType,
+    line_in_file,
+};
+use tokio::sync::mpsc;
+
+use crate::ingest::Rows;
+
+/// A problem found in the input.
+#[derive(Debug, PartialEq)]
+pub struct Problem {
+    /// The name of the file of the row with the problem, when reading several.
+    pub file: Option<Arc<str>>,
+    /// The 1-based line of the row with the problem.
+    pub line: u64,
+    /// Description of the problem.
+    pub message: String,
+}
+
+/// The outcome of validating the input.
+#[derive(Default, Debug)]
+pub struct Report {
+    /// Number of rows of each transaction type, in the order the types first appear in the input,
+    /// or in the order of declaration after a dry run.
+    pub counts: Vec<(TransactionType, u64)>,
+    /// Number of rows that couldn't be parsed.
+    pub malformed: u64,
+    /// The problems found, in the order of the input.
+    pub problems: Vec<Problem>,
+}
+
+impl Report {
+    /// Writes the problems followed by the row counts.
+    pub fn write(&self, mut writer: impl Write) -> io::Result<()> {
+        for problem in &self.problems {
+            writeln!(
+                writer,
+                "Line {}: {}",
+                line_in_file(problem.file.as_deref(), problem.line),
+                problem.message
+            )?;
+        }
+        for (tx_type, count) in &self.counts {
+            writeln!(writer, "{tx_type}: {count}")?;
+        }
+        writeln!(writer, "malformed: {}", self.malformed)?;
+        writeln!(writer, "Found {} problems", self.problems.len())
+    }
+}
+
+/// Streams the rows through a lightweight checker. Only the client, file and line of each deposit and withdrawal are kept,
+/// to find reused transaction IDs and references to unknown transactions. Balances are not tracked, so problems that
+/// depend on them (e.g. insufficient funds) are not reported.
+pub fn validate(rows: Rows) -> Report {
+    let mut report = Report::default();
+    let mut recorded: HashMap<TransactionId, (ClientId, Option<Arc<str>>, u64)> = HashMap::new();
+    for row in rows {
+        match row {
+            Ok(transaction) => {
+                let tx_type = *transaction.get_type();
+                match report.counts.iter_mut().find(|(seen, _)| *seen == tx_type) {
+                    Some((_, count)) => *count += 1,
+                    None => report.counts.push((tx_type, 1)),
+                }
+                let line = transaction.get_line().unwrap_or_default();
+                if let Some(message) = check(&transaction, line, &mut recorded) {
+                    report.problems.push(Problem {
+                        file: transaction.get_file().cloned(),
+                        line,
+                        message,
+                    });
+                }
+            }
+            Err(err) => {
+                report.malformed += 1;
+                report.problems.push(Problem {
+                    file: err.file,
+                    line: err.line,
+                    message: err.message,
+                });
+            }
+        }
+    }
+    report
+}
+
+/// Builds the report of a dry run from the transactions the engine rejected, as received from the error sink,
+/// and the statistics of the run. Malformed rows were reported while reading, so only their number is included.
+pub fn dry_run_report(
+    rejected: &mut mpsc::UnboundedReceiver<(Transaction, TransactionError)>,
+    stats: &Stats,
+    malformed: usize,
+) -> Report {
+    let mut problems = Vec::new();
+    while let Ok((transaction, error)) = rejected.try_recv() {
+        problems.push(Problem {
+            file: transaction.get_file().cloned(),
+            line: transaction.get_line().unwrap_or_default(),
+            message: format!(
+                "The {} of client {} with ID {} was rejected: {error}",
+                transaction.get_type(),
+                transaction.get_client_id(),
+                transaction.get_transaction_id()
+            ),
+        });
+    }
+    Report {
+        counts: TransactionType::ALL
+            .into_iter()
+            .map(|tx_type| (tx_type, stats.count(tx_type)))
+            .filter(|(_, count)| *count > 0)
+            .collect(),
+        malformed: malformed as u64,
+        problems,
+    }
+}
+
+/// Checks a single transaction against the deposits and withdrawals seen so far, recording it if it's one of them.
+fn check(
+    transaction: &Transaction,
+    line: u64,
+    recorded: &mut HashMap<TransactionId, (ClientId, Option<Arc<str>>, u64)>,
+) -> Option<String> {
+    let tx_type = transaction.get_type();
+    let id = transaction.get_transaction_id();
+    let client = transaction.get_client_id();
+    let has_amount = transaction
+        .get_amount()
+        .is_some_and(|amount| amount > Money::ZERO);
+    match tx_type {
+        TransactionType::Deposit | TransactionType::Withdrawal => {
+            if !has_amount {
+                return Some(format!("The {tx_type} has no positive amount"));
+            }
+            match recorded.entry(id) {
+                Entry::Occupied(entry) => Some(format!(
+                    "Transaction ID {id} is already used at line {}",
+                    line_in_file(entry.get().1.as_deref(), entry.get().2)
+                )),
+                Entry::Vacant(entry) => {
+                    entry.insert((client, transaction.get_file().cloned(), line));
+                    None
+                }
+            }
+        }
+        TransactionType::Dispute | TransactionType::Resolve | TransactionType::Chargeback => {
+            match recorded.get(&id) {
+                None => Some(format!("The {tx_type} references unknown transaction {id}")),
+                Some((owner, ..)) if *owner != client => Some(format!(
+                    "The {tx_type} references transaction {id} of client {owner}"
+                )),
+                Some(_) => None,
+            }
+        }
+        TransactionType::Hold | TransactionType::Release if !has_amount => {
+            Some(format!("The {tx_type} has no positive amount"))
+        }
+        TransactionType::Hold
+        | TransactionType::Release
+        | TransactionType::Unlock
+        | TransactionType::Freeze
+        | TransactionType::Unfreeze => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bank::{TransactionReader, TransactionType};
+
+    use super::{Problem, validate};
+
+    #[test]
+    fn test_validate() {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,1.0\n\
+                     deposit,2,1,1.0\n\
+                     withdrawal,1,2,1.00001\n\
+                     dispute,1,3,\n\
+                     dispute,2,1,\n\
+                     withdrawal,1,4,\n\
+                     resolve,1,1,\n";
+        let report = validate(Box::new(
+            TransactionReader::new(std::io::Cursor::new(input)).unwrap(),
+        ));
+        assert_eq!(
+            report.counts,
+            [
+                (TransactionType::Deposit, 2),
+                (TransactionType::Dispute, 2),
+                (TransactionType::Withdrawal, 1),
+                (TransactionType::Resolve, 1),
+            ]
+        );
+        assert_eq!(report.malformed, 1);
+        let problem = |line, message: &str| Problem {
+            file: None,
+            line,
+            message: message.to_string(),
+        };
+        assert_eq!(
+            report.problems,
+            [
+                problem(3, "Transaction ID 1 is already used at line 2"),
+                problem(4, "Amount `1.00001` has more than four decimal places"),
+                problem(5, "The dispute references unknown transaction 3"),
+                problem(6, "The dispute references transaction 1 of client 1"),
+                problem(7, "The withdrawal has no positive amount"),
+            ]
+        );
+    }
+}
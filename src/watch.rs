@@ -0,0 +1,351 @@
+//! Following a growing input file with `--watch`: the rows appended to it are processed as they arrive, and the
+//! account report is written periodically and on SIGHUP.
+use std::{
+    fs::{self, File, Metadata},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread,
+    time::Duration,
+};
+
+use bank::{AccountSummary, Request, State};
+use tokio::{
+    sync::mpsc::{self, error::TrySendError},
+    time::{Instant, Interval},
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, instrument, warn};
+
+use crate::{
+    cli::InputArgs,
+    error::AppError,
+    ingest::{Counters, Rows},
+    output::{self, OutputError, OutputFormat},
+};
+
+/// How long to wait before looking for new rows once the end of the file was reached.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Reads a file that is still being written to. At its end it waits for more data instead of returning EOF, until
+/// the file is rotated or the token is cancelled.
+struct Tail {
+    /// The path of the file, which may point to a new file once it's rotated.
+    path: PathBuf,
+    /// The file being read.
+    file: File,
+    /// How many bytes were read from the file.
+    position: u64,
+    /// Set when the end of the file was returned because the file was truncated or replaced.
+    rotated: Arc<AtomicBool>,
+    /// Stops waiting for more data.
+    token: CancellationToken,
+}
+
+impl Tail {
+    /// Opens the file to be read from its start.
+    fn open(path: &Path, token: CancellationToken) -> io::Result<Self> {
+        Ok(Tail {
+            path: path.to_owned(),
+            file: File::open(path)?,
+            position: 0,
+            rotated: Arc::default(),
+            token,
+        })
+    }
+
+    /// Checks whether the path now points to a different file, or the file got shorter than what was read from it.
+    /// A file that is missing is assumed to be being replaced, the new one is picked up once it's there.
+    fn is_rotated(&self) -> bool {
+        match (fs::metadata(&self.path), self.file.metadata()) {
+            (Ok(current), Ok(open)) => current.len() < self.position || !same_file(&current, &open),
+            _ => false,
+        }
+    }
+}
+
+impl Read for Tail {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let read = self.file.read(buf)?;
+            if read > 0 || buf.is_empty() {
+                self.position += read as u64;
+                return Ok(read);
+            }
+            if self.token.is_cancelled() {
+                return Ok(0);
+            }
+            if self.is_rotated() {
+                self.rotated.store(true, Ordering::Relaxed);
+                return Ok(0);
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+/// Checks whether both metadata belong to the same file.
+#[cfg(unix)]
+fn same_file(a: &Metadata, b: &Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    a.dev() == b.dev() && a.ino() == b.ino()
+}
+
+/// Checks whether both metadata belong to the same file. Without inodes only a truncation can be detected.
+#[cfg(not(unix))]
+fn same_file(_: &Metadata, _: &Metadata) -> bool {
+    true
+}
+
+/// Opens the file and follows it until the token is cancelled. When it's rotated the new file is read from its
+/// start, with its own header row.
+pub fn follow(args: &InputArgs, path: &Path, token: CancellationToken) -> Result<Rows, AppError> {
+    let format = args.format(path);
+    let compression = args.compression(path);
+    let open = move |path: &Path| -> Result<(Rows, Arc<AtomicBool>), AppError> {
+        let tail = Tail::open(path, token.clone())
+            .map_err(AppError::io(format!("Failed to open {}", path.display())))?;
+        let rotated = tail.rotated.clone();
+        Ok((
+            crate::read_rows(Box::new(tail), format, compression)?,
+            rotated,
+        ))
+    };
+    let (mut rows, mut rotated) = open(path)?;
+    let path = path.to_owned();
+    Ok(Box::new(std::iter::from_fn(move || {
+        loop {
+            if let Some(row) = rows.next() {
+                return Some(row);
+            }
+            if !rotated.load(Ordering::Relaxed) {
+                return None;
+            }
+            info!("{} was rotated, reading it from the start", path.display());
+            match open(&path) {
+                Ok(next) => (rows, rotated) = next,
+                Err(err) => {
+                    error!("Failed to follow {}: {err}", path.display());
+                    return None;
+                }
+            }
+        }
+    })))
+}
+
+/// Where and how the account report is written while following the input.
+pub struct Report {
+    /// The format of the report.
+    pub format: OutputFormat,
+    /// The number of decimal places of the balances.
+    pub precision: usize,
+    /// The file replaced with every report, or stdout without one.
+    pub output: Option<PathBuf>,
+    /// The time between two reports, if they are written periodically and not only on SIGHUP.
+    pub interval: Option<Duration>,
+}
+
+impl Report {
+    /// Writes the report. On stdout every report is followed by an empty line to tell it apart from the next one.
+    fn write(&self, summaries: Vec<AccountSummary>) -> Result<(), OutputError> {
+        match &self.output {
+            Some(path) => output::write_atomically(path, |writer| {
+                output::write_report(summaries.into_iter(), self.format, writer)
+            }),
+            None => {
+                let mut stdout = io::stdout().lock();
+                output::write_report(summaries.into_iter(), self.format, &mut stdout)?;
+                writeln!(stdout)?;
+                stdout.flush()?;
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Processes the followed rows until they end, writing the report in the meantime.
+#[instrument(skip_all, fields(channel_size))]
+pub async fn process_watched(
+    reader: Rows,
+    state: State,
+    channel_size: usize,
+    report: Report,
+    counters: &mut Counters,
+) -> Result<State, AppError> {
+    // Registered before reading, so that a SIGHUP can't end the run with its default action.
+    let hangups = Hangups::new().map_err(AppError::io("Failed to listen for SIGHUP"))?;
+    let (sender, receiver) = mpsc::channel(channel_size);
+    let (errors_sender, errors_receiver) = mpsc::unbounded_channel();
+    let mut state = state.with_ingest_errors(errors_receiver);
+    let handle = tokio::spawn(async move {
+        state.serve(receiver).await;
+        state
+    });
+    let stop = CancellationToken::new();
+    let reporter = tokio::spawn(report_until_stopped(
+        sender.clone(),
+        report,
+        hangups,
+        stop.clone(),
+    ));
+
+    // Reading blocks this task while waiting for new rows, the state and the reports are handled by the others.
+    for row in reader {
+        counters.read += 1;
+        let transaction = match row {
+            Ok(transaction) => transaction,
+            Err(err) => {
+                counters.skipped += 1;
+                // Sent ahead of the following transactions, so the state records it in input order.
+                let _ = errors_sender.send(err);
+                continue;
+            }
+        };
+        let result = match sender.try_send(Request::Transaction(transaction)) {
+            Err(TrySendError::Full(request)) => {
+                counters.waits += 1;
+                sender.send(request).await
+            }
+            result => result.map_err(|err| mpsc::error::SendError(err.into_inner())),
+        };
+        if result.is_err() {
+            // The state task failed, which joining it reports.
+            break;
+        }
+    }
+
+    stop.cancel();
+    reporter.await.expect("Failed to join the report task");
+    drop(sender);
+    drop(errors_sender);
+    Ok(handle
+        .await
+        .expect("Failed to join the state handling task"))
+}
+
+/// Writes the report on every tick of the interval and every SIGHUP, until stopped.
+async fn report_until_stopped(
+    sender: mpsc::Sender<Request>,
+    report: Report,
+    mut hangups: Hangups,
+    stop: CancellationToken,
+) {
+    let mut interval = report
+        .interval
+        .map(|period| tokio::time::interval_at(Instant::now() + period, period));
+    loop {
+        tokio::select! {
+            _ = stop.cancelled() => break,
+            _ = tick(&mut interval) => {}
+            _ = hangups.recv() => {}
+        }
+        let precision = report.precision;
+        let (query, result) = Request::query(move |state: &State| {
+            state
+                .accounts_sorted()
+                .into_iter()
+                .flat_map(|account| account.summaries(precision))
+                .collect::<Vec<_>>()
+        });
+        if sender.send(query).await.is_err() {
+            break;
+        }
+        let Ok(summaries) = result.await else {
+            break;
+        };
+        if let Err(err) = report.write(summaries) {
+            warn!("Failed to write the report: {err}");
+        }
+    }
+}
+
+/// Waits for the next tick of the interval, or forever without one.
+async fn tick(interval: &mut Option<Interval>) {
+    match interval {
+        Some(interval) => {
+            interval.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+/// The SIGHUP signals asking for a report. There are none outside of Unix.
+struct Hangups {
+    #[cfg(unix)]
+    signal: tokio::signal::unix::Signal,
+}
+
+impl Hangups {
+    /// Starts listening for the signals.
+    fn new() -> io::Result<Self> {
+        Ok(Hangups {
+            #[cfg(unix)]
+            signal: tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())?,
+        })
+    }
+
+    /// Waits for the next signal.
+    async fn recv(&mut self) {
+        #[cfg(unix)]
+        if self.signal.recv().await.is_some() {
+            return;
+        }
+        std::future::pending().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::OpenOptions, io::Write, thread, time::Duration};
+
+    use bank::Money;
+    use clap::Parser;
+    use tokio_util::sync::CancellationToken;
+
+    use crate::{cli::InputArgs, watch::follow};
+
+    #[derive(Parser)]
+    struct Args {
+        #[command(flatten)]
+        source: InputArgs,
+    }
+
+    #[test]
+    fn test_follow_rotation() {
+        let path = std::env::temp_dir().join(format!("bank-watch-{}.csv", std::process::id()));
+        std::fs::write(&path, "type,client,tx,amount\ndeposit,1,1,1.0\n").unwrap();
+        let args = Args::parse_from(["bank", path.to_str().unwrap()]);
+        let token = CancellationToken::new();
+        let mut rows = follow(&args.source, &path, token.clone()).unwrap();
+        assert_eq!(rows.next().unwrap().unwrap().get_transaction_id(), 1);
+
+        let writer = thread::spawn({
+            let path = path.clone();
+            move || {
+                let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+                file.write_all(b"deposit,1,2,2.0\n").unwrap();
+                thread::sleep(Duration::from_millis(300));
+                // Replaced by a shorter file, which is read from its start.
+                std::fs::write(&path, "type,client,tx,amount\ndeposit,2,3,3\n").unwrap();
+                thread::sleep(Duration::from_millis(300));
+                token.cancel();
+            }
+        });
+        let rest = rows.map(Result::unwrap).collect::<Vec<_>>();
+        writer.join().unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            rest.iter()
+                .map(|transaction| (transaction.get_transaction_id(), transaction.get_amount()))
+                .collect::<Vec<_>>(),
+            [
+                (2, Some(Money::from_raw(20000))),
+                (3, Some(Money::from_raw(30000)))
+            ]
+        );
+    }
+}
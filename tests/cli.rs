@@ -0,0 +1,1137 @@
+//! Integration tests running the `bank` binary.
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::TcpStream,
+    process::{Command, Output, Stdio},
+};
+
+/// Runs the binary with the given arguments.
+fn run(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_bank"))
+        .args(args)
+        .output()
+        .expect("Failed to run the binary")
+}
+
+/// Runs the binary with the given arguments, writing `input` to its stdin.
+fn run_with_stdin(args: &[&str], input: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_bank"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Failed to run the binary");
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+/// Path to a file in the `tests/fixtures` directory.
+fn fixture(name: &str) -> String {
+    format!("{}/tests/fixtures/{name}", env!("CARGO_MANIFEST_DIR"))
+}
+
+#[test]
+fn test_malformed_rows_are_reported() {
+    let output = run(&[&fixture("malformed.csv")]);
+    // The report is still written, but the exit code tells that rows were skipped.
+    assert_eq!(output.status.code(), Some(4));
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(
+        stdout,
+        "client,available,held,total,locked\n1,2.5000,0.0000,2.5000,false\n"
+    );
+
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Malformed row at line 3 `deposti,1,2,1.0`"));
+    assert!(stderr.contains("Malformed row at line 4 `deposit,one,3,1.0`"));
+    assert!(stderr.contains("Malformed row at line 6 `deposit,1,5,1.2.3`"));
+    assert!(stderr.contains("Skipped 3 malformed rows"));
+
+    let lenient = run(&["--lenient", &fixture("malformed.csv")]);
+    assert!(lenient.status.success());
+    assert_eq!(String::from_utf8(lenient.stdout).unwrap(), stdout);
+
+    // Read through the channel instead of directly.
+    let input = std::fs::read_to_string(fixture("malformed.csv")).unwrap();
+    assert_eq!(run_with_stdin(&[], &input).status.code(), Some(4));
+    assert!(run_with_stdin(&["--lenient"], &input).status.success());
+
+    assert!(run(&[&fixture("mixed.csv")]).status.success());
+    let conflict = run(&["--lenient", "--fail-fast", &fixture("mixed.csv")]);
+    assert_eq!(conflict.status.code(), Some(2));
+}
+
+#[test]
+fn test_dispute_policy() {
+    let output = run(&[&fixture("dispute_withdrawn.csv")]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,available,held,total,locked\n1,-100.0000,100.0000,0.0000,false\n"
+    );
+
+    let output = run(&[
+        "--dispute-policy",
+        "reject-insufficient",
+        &fixture("dispute_withdrawn.csv"),
+    ]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,available,held,total,locked\n1,0.0000,0.0000,0.0000,false\n"
+    );
+    assert!(
+        String::from_utf8(output.stderr)
+            .unwrap()
+            .contains("Insufficient available funds to dispute the transaction")
+    );
+}
+
+#[test]
+fn test_withdrawal_dispute_policy() {
+    let input = "type, client, tx, amount\n\
+                 deposit, 1, 1, 4.0\n\
+                 withdrawal, 1, 2, 3.0\n\
+                 dispute, 1, 2,\n";
+    let cases = [
+        ("hold-from-total", "1,1.0000,3.0000,4.0000,false\n"),
+        ("credit-on-chargeback", "1,1.0000,0.0000,1.0000,false\n"),
+        ("reject", "1,1.0000,0.0000,1.0000,false\n"),
+    ];
+    for (policy, report) in cases {
+        let output = run_with_stdin(&["--withdrawal-dispute-policy", policy, "-"], input);
+        assert!(output.status.success());
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            format!("client,available,held,total,locked\n{report}")
+        );
+        assert_eq!(
+            String::from_utf8(output.stderr)
+                .unwrap()
+                .contains("Disputes of withdrawals are not accepted"),
+            policy == "reject"
+        );
+    }
+}
+
+#[test]
+fn test_stdin_input() {
+    let output = run_with_stdin(
+        &["-"],
+        "type, client, tx, amount\n\
+         deposit, 1, 1, 3.0\n\
+         withdrawal,  1 , 2, 1.25 \n",
+    );
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,available,held,total,locked\n1,1.7500,0.0000,1.7500,false\n"
+    );
+}
+
+#[test]
+fn test_precision() {
+    let output = run_with_stdin(
+        &["--precision", "2", "-"],
+        "type, client, tx, amount\n\
+         deposit, 1, 1, 1.5\n\
+         deposit, 1, 2, 0.0051\n",
+    );
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,available,held,total,locked\n1,1.51,0.00,1.51,false\n"
+    );
+}
+
+#[test]
+fn test_json_output() {
+    let output = run_with_stdin(
+        &["--output-format", "json", "-"],
+        "type, client, tx, amount\n\
+         deposit, 1, 1, 1.5\n",
+    );
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "[{\"client\":1,\"available\":\"1.5000\",\"held\":\"0.0000\",\"total\":\"1.5000\",\"locked\":false}]\n"
+    );
+}
+
+#[test]
+fn test_json_lines_input() {
+    let output = run(&["--rounding", "reject", &fixture("transactions.jsonl")]);
+    assert_eq!(output.status.code(), Some(4));
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,available,held,total,locked\n\
+         1,1.2500,0.0000,1.2500,false\n\
+         2,0.0000,2.0001,2.0001,false\n"
+    );
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Malformed row at line 5"));
+    assert!(stderr.contains("Amount `1.00001` has more than four decimal places"));
+}
+
+#[test]
+fn test_json_lines_from_stdin() {
+    let output = run_with_stdin(
+        &["--input-format", "jsonl", "-"],
+        "{\"type\": \"deposit\", \"client\": 1, \"tx\": 1, \"amount\": 0.0001}\n",
+    );
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,available,held,total,locked\n1,0.0001,0.0000,0.0001,false\n"
+    );
+}
+
+#[test]
+fn test_output_is_sorted_and_deterministic() {
+    let first = run(&[&fixture("many_clients.csv")]);
+    let second = run(&[&fixture("many_clients.csv")]);
+    assert!(first.status.success());
+    assert_eq!(first.stdout, second.stdout);
+
+    let stdout = String::from_utf8(first.stdout).unwrap();
+    let clients = stdout
+        .lines()
+        .skip(1)
+        .map(|row| row.split(',').next().unwrap().parse::<u16>().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(clients, (1..=20).collect::<Vec<_>>());
+}
+
+#[test]
+fn test_output_matches_golden_file() {
+    let expected = std::fs::read(fixture("many_clients.expected.csv")).unwrap();
+    for workers in ["1", "4"] {
+        let output = run(&[&fixture("many_clients.csv"), "--workers", workers]);
+        assert!(output.status.success());
+        assert_eq!(output.stdout, expected);
+    }
+}
+
+#[test]
+fn test_ndjson_output_matches_golden_file() {
+    let expected = std::fs::read_to_string(fixture("many_clients.expected.ndjson")).unwrap();
+    for format in ["ndjson", "jsonl"] {
+        let output = run(&[&fixture("many_clients.csv"), "--output-format", format]);
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), expected);
+    }
+
+    // Both golden files hold the same accounts, field by field.
+    let csv = std::fs::read_to_string(fixture("many_clients.expected.csv")).unwrap();
+    let mut rows = csv.lines();
+    let header = rows.next().unwrap().split(',').collect::<Vec<_>>();
+    let lines = expected.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), rows.clone().count());
+    for (row, line) in rows.zip(lines) {
+        let object: serde_json::Value = serde_json::from_str(line).unwrap();
+        for (field, value) in header.iter().zip(row.split(',')) {
+            let json = match &object[field] {
+                serde_json::Value::String(value) => value.clone(),
+                value => value.to_string(),
+            };
+            assert_eq!(json, value, "{field} in {line}");
+        }
+    }
+}
+
+/// The report of amounts across the whole range and precision is the same with and without the `decimal` feature.
+#[test]
+fn test_amounts_match_golden_file() {
+    let expected = std::fs::read(fixture("amounts.expected.csv")).unwrap();
+    let output = run(&[&fixture("amounts.csv")]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, expected);
+}
+
+/// A deposit beyond the range of `i64` is malformed by default, and processed with the `decimal` feature.
+#[test]
+fn test_large_amount() {
+    let input = "type,client,tx,amount\ndeposit,1,1,1000000000000000\n";
+    let output = run_with_stdin(&[], input);
+    assert_eq!(
+        output.status.code(),
+        Some(if cfg!(feature = "decimal") { 0 } else { 4 })
+    );
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    if cfg!(feature = "decimal") {
+        assert_eq!(
+            stdout,
+            "client,available,held,total,locked\n1,1000000000000000.0000,0.0000,1000000000000000.0000,false\n"
+        );
+    } else {
+        assert_eq!(stdout, "");
+        assert!(
+            String::from_utf8(output.stderr)
+                .unwrap()
+                .contains("out of range")
+        );
+    }
+}
+
+#[test]
+fn test_stdin_without_argument() {
+    let output = run_with_stdin(&[], "type,client,tx,amount\ndeposit,1,1,1.0\n");
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,available,held,total,locked\n1,1.0000,0.0000,1.0000,false\n"
+    );
+}
+
+#[test]
+fn test_empty_stdin() {
+    let output = run_with_stdin(&[], "");
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+    assert!(output.stderr.is_empty());
+}
+
+#[test]
+fn test_usage_documents_stdin() {
+    let output = run(&["--help"]);
+    assert!(output.status.success());
+    assert!(
+        String::from_utf8(output.stdout)
+            .unwrap()
+            .contains("Reads from stdin")
+    );
+}
+
+#[test]
+fn test_output_file() {
+    let path = std::env::temp_dir().join(format!("bank-cli-output-{}.csv", std::process::id()));
+    std::fs::write(&path, "previous report\n").unwrap();
+    let output = run(&[
+        &fixture("many_clients.csv"),
+        "--output",
+        path.to_str().unwrap(),
+    ]);
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+
+    let stdout = run(&[&fixture("many_clients.csv")]).stdout;
+    assert_eq!(std::fs::read(&path).unwrap(), stdout);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_output_file_failure() {
+    let output = run(&[
+        &fixture("many_clients.csv"),
+        "--output",
+        "/nonexistent-directory/accounts.csv",
+    ]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(
+        String::from_utf8(output.stderr)
+            .unwrap()
+            .contains("Failed to write the report")
+    );
+}
+
+#[test]
+fn test_missing_input() {
+    let output = run(&["/nonexistent-directory/transactions.csv"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Failed to open /nonexistent-directory/transactions.csv: "));
+    assert!(!stderr.contains("panicked"));
+
+    // An invalid argument is still reported by clap, with its own exit code.
+    let output = run(&["--workers", "0", &fixture("mixed.csv")]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(
+        String::from_utf8(output.stderr)
+            .unwrap()
+            .contains("invalid value '0' for '--workers <WORKERS>'")
+    );
+    assert!(run(&["--help"]).status.success());
+}
+
+#[test]
+fn test_csv_and_json_lines_give_identical_output() {
+    let csv = run(&[&fixture("mixed.csv")]);
+    let json_lines = run(&[&fixture("mixed.jsonl")]);
+    assert!(csv.status.success());
+    assert!(json_lines.status.success());
+    assert_eq!(csv.stdout, json_lines.stdout);
+    assert_eq!(
+        String::from_utf8(csv.stdout).unwrap(),
+        "client,available,held,total,locked\n\
+         1,8.2500,0.0000,8.2500,false\n\
+         2,0.0000,0.0000,0.0000,true\n\
+         3,0.0001,0.0000,0.0001,false\n"
+    );
+}
+
+#[test]
+fn test_json_input_format_alias() {
+    let output = run_with_stdin(
+        &["--input-format", "json"],
+        "{\"type\":\"deposit\",\"client\":1,\"tx\":1,\"amount\":\"10.5\"}\n\
+         {\"type\":\"deposit\",\"client\":1,\"tx\":2,\"amount\":\"ten\"}\n",
+    );
+    assert_eq!(output.status.code(), Some(4));
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,available,held,total,locked\n1,10.5000,0.0000,10.5000,false\n"
+    );
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Malformed row at line 2"));
+    assert!(stderr.contains("Invalid amount `ten`"));
+}
+
+#[test]
+fn test_checkpoint_resumes_processing() {
+    let path = std::env::temp_dir().join(format!("bank-checkpoint-{}.json", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let checkpoint = path.to_str().unwrap();
+
+    let first = run_with_stdin(
+        &["--checkpoint", checkpoint],
+        "type,client,tx,amount\n\
+         deposit,1,1,10.0\n\
+         deposit,2,2,5.0\n\
+         dispute,1,1,\n",
+    );
+    assert!(first.status.success());
+    let second = run_with_stdin(
+        &["--checkpoint", checkpoint],
+        "type,client,tx,amount\n\
+         deposit,2,1,1.0\n\
+         chargeback,1,1,\n\
+         withdrawal,2,3,2.0\n",
+    );
+    assert!(second.status.success());
+    std::fs::remove_file(&path).unwrap();
+
+    // Transaction 1 is still known after the restart, so its ID can't be reused and its dispute is still open.
+    assert!(
+        String::from_utf8(second.stderr)
+            .unwrap()
+            .contains("already used")
+    );
+    assert_eq!(
+        String::from_utf8(second.stdout).unwrap(),
+        "client,available,held,total,locked\n\
+         1,0.0000,0.0000,0.0000,true\n\
+         2,3.0000,0.0000,3.0000,false\n"
+    );
+}
+
+#[test]
+fn test_rejects_file() {
+    let path = std::env::temp_dir().join(format!("bank-rejects-{}.csv", std::process::id()));
+    let output = run_with_stdin(
+        &["--rejects", path.to_str().unwrap()],
+        "type,client,tx,amount\n\
+         deposit,1,1,1.0\n\
+         withdrawal,1,2,5.0\n\
+         chargeback,1,1,\n",
+    );
+    assert!(output.status.success());
+    assert!(output.stderr.is_empty());
+    let rejects = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(
+        rejects,
+        "line,type,client,tx,amount,reason,requested,available\n\
+         3,withdrawal,1,2,5.0000,Insufficient funds for transaction: requested 5.0000 but only 1.0000 available,5.0000,1.0000\n\
+         4,chargeback,1,1,,Transaction not in dispute,,\n"
+    );
+}
+
+#[test]
+fn test_empty_amount_is_rejected_not_malformed() {
+    // An empty amount column parses as a missing amount, which the engine rejects, so the run isn't failed by it.
+    let path = std::env::temp_dir().join(format!("bank-empty-amount-{}.csv", std::process::id()));
+    let output = run_with_stdin(
+        &["--rejects", path.to_str().unwrap()],
+        "type,client,tx,amount\n\
+         deposit,1,1,\n\
+         deposit,1,2,2.0\n\
+         withdrawal,1,3,\n\
+         withdrawal,1,4,\"\"\n",
+    );
+    assert!(output.status.success());
+    assert!(output.stderr.is_empty());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,available,held,total,locked\n1,2.0000,0.0000,2.0000,false\n"
+    );
+    let rejects = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(
+        rejects,
+        "line,type,client,tx,amount,reason,requested,available\n\
+         2,deposit,1,1,,Transaction 1 has no amount,,\n\
+         4,withdrawal,1,3,,Transaction 3 has no amount,,\n\
+         5,withdrawal,1,4,,Transaction 4 has no amount,,\n"
+    );
+}
+
+#[test]
+fn test_workers_give_same_output() {
+    let single = run(&[&fixture("many_clients.csv")]);
+    let sharded = run(&[&fixture("many_clients.csv"), "--workers", "4"]);
+    assert!(sharded.status.success());
+    assert_eq!(sharded.stdout, single.stdout);
+
+    let output = run(&[&fixture("many_clients.csv"), "--workers", "0"]);
+    assert!(!output.status.success());
+}
+
+/// The smallest and largest client IDs get accounts like any other, and end up first and last in the report.
+#[test]
+fn test_boundary_client_ids() {
+    let input = "type,client,tx,amount\n\
+                 deposit,65535,1,2.0\n\
+                 deposit,1,2,1.0\n\
+                 deposit,0,3,3.0\n\
+                 withdrawal,65535,4,0.5\n\
+                 dispute,0,3,\n\
+                 chargeback,0,3,\n";
+    let expected = "client,available,held,total,locked\n\
+                    0,0.0000,0.0000,0.0000,true\n\
+                    1,1.0000,0.0000,1.0000,false\n\
+                    65535,1.5000,0.0000,1.5000,false\n";
+    for workers in ["1", "2", "3"] {
+        let output = run_with_stdin(&["--workers", workers], input);
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), expected);
+    }
+
+    let output = run_with_stdin(&["--output-format", "json"], input);
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report[0]["client"], 0);
+    assert_eq!(report[2]["client"], 65535);
+}
+
+#[test]
+fn test_channel_size_one() {
+    let input = std::fs::read_to_string(fixture("many_clients.csv")).unwrap();
+    let expected = run(&[&fixture("many_clients.csv")]).stdout;
+    for workers in ["1", "3"] {
+        let output = run_with_stdin(
+            &["--channel-size", "1", "--workers", workers, "--verbose"],
+            &input,
+        );
+        assert!(output.status.success());
+        assert_eq!(output.stdout, expected);
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(stderr.contains("The reader waited"));
+    }
+
+    let output = run(&["--channel-size", "0"]);
+    assert!(!output.status.success());
+}
+
+#[test]
+fn test_snapshot_between_halves() {
+    let input = std::fs::read_to_string(fixture("mixed.csv")).unwrap();
+    let mut rows = input.lines();
+    let header = rows.next().unwrap();
+    let rows = rows.collect::<Vec<_>>();
+    let (first, second) = rows.split_at(rows.len() / 2);
+    let half = |rows: &[&str]| format!("{header}\n{}\n", rows.join("\n"));
+
+    let path = std::env::temp_dir().join(format!("bank-snapshot-{}.json", std::process::id()));
+    let snapshot = path.to_str().unwrap();
+    let output = run_with_stdin(&["--snapshot-out", snapshot], &half(first));
+    assert!(output.status.success());
+    let output = run_with_stdin(&["--snapshot-in", snapshot], &half(second));
+    std::fs::remove_file(&path).unwrap();
+    assert!(output.status.success());
+    assert_eq!(output.stdout, run(&[&fixture("mixed.csv")]).stdout);
+
+    let output = run_with_stdin(&["--snapshot-in", snapshot], &half(second));
+    assert_eq!(output.status.code(), Some(1));
+    assert!(
+        String::from_utf8(output.stderr)
+            .unwrap()
+            .contains("Failed to access the snapshot file")
+    );
+}
+
+#[test]
+fn test_header_without_amount() {
+    let output = run_with_stdin(&[], "type,client,tx\ndeposit,1,1\n");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8(output.stderr).unwrap().contains(
+        "Invalid CSV header, expected the columns `type,client,tx,amount` in any order (missing: [amount], unexpected: [])"
+    ));
+}
+
+#[test]
+fn test_overdraft() {
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,10.0\n\
+                 withdrawal,1,2,12.5\n\
+                 withdrawal,1,3,0.0001\n";
+    let output = run_with_stdin(&["--overdraft", "2.5"], input);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,available,held,total,locked\n1,-2.5000,0.0000,-2.5000,false\n"
+    );
+    assert!(
+        String::from_utf8(output.stderr)
+            .unwrap()
+            .contains("Insufficient funds")
+    );
+
+    let output = run_with_stdin(&["--overdraft", "-1"], input);
+    assert!(!output.status.success());
+}
+
+#[test]
+fn test_currencies() {
+    let output = run(&[&fixture("currencies.csv")]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,currency,available,held,total,locked\n\
+         1,EUR,0.0000,0.0000,0.0000,true\n\
+         1,USD,7.5000,0.0000,7.5000,true\n\
+         2,,3.0000,0.0000,3.0000,false\n\
+         2,EUR,1.0000,0.0000,1.0000,false\n"
+    );
+}
+
+#[test]
+fn test_validate_flag() {
+    let output = run(&["--validate", &fixture("insufficient_funds.csv")]);
+    assert_eq!(output.status.code(), Some(1));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(
+        stdout,
+        "Line 4: The withdrawal of client 1 with ID 3 was rejected: Insufficient funds for transaction: requested 4.0000 but only 3.0000 available\n\
+         deposit: 2\n\
+         withdrawal: 2\n\
+         malformed: 0\n\
+         Found 1 problems\n"
+    );
+
+    let output = run(&["--validate", &fixture("many_clients.csv")]);
+    assert!(output.status.success());
+    assert!(
+        !String::from_utf8(output.stdout)
+            .unwrap()
+            .contains("client,")
+    );
+}
+
+#[test]
+fn test_fail_fast() {
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,2.0\n\
+                 withdrawal,1,2,5.0\n\
+                 deposit,1,3,1.0\n";
+    let output = run_with_stdin(&["--fail-fast"], input);
+    assert_eq!(output.status.code(), Some(3));
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8(output.stderr).unwrap().contains(
+        "Rejected transaction at line 3 `withdrawal,1,2,5.0000`: Insufficient funds for transaction"
+    ));
+
+    let output = run(&["--fail-fast", &fixture("malformed.csv")]);
+    assert_eq!(output.status.code(), Some(3));
+    assert!(
+        String::from_utf8(output.stderr)
+            .unwrap()
+            .contains("Malformed row at line 3 `deposti,1,2,1.0`")
+    );
+
+    // `--strict` is the same, also for a file, which isn't read through the channel.
+    let output = run(&["--strict", &fixture("insufficient_funds.csv")]);
+    assert_eq!(output.status.code(), Some(3));
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8(output.stderr).unwrap().contains(
+        "Rejected transaction at line 4 `withdrawal,1,3,4.0000`: Insufficient funds for transaction"
+    ));
+
+    // Without it the same input is reported as usual.
+    let output = run_with_stdin(&[], input);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,available,held,total,locked\n1,3.0000,0.0000,3.0000,false\n"
+    );
+}
+
+#[test]
+fn test_rounding() {
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,1.00004\n\
+                 deposit,1,2,1.00005\n\
+                 deposit,1,3,1.00006\n\
+                 deposit,1,4,1.00015\n";
+    let output = run_with_stdin(&["--rounding", "half-even"], input);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,available,held,total,locked\n1,4.0003,0.0000,4.0003,false\n"
+    );
+
+    // Half-even is the default, unless the run is strict.
+    assert_eq!(
+        String::from_utf8(run_with_stdin(&[], input).stdout).unwrap(),
+        "client,available,held,total,locked\n1,4.0003,0.0000,4.0003,false\n"
+    );
+
+    let output = run_with_stdin(&["--rounding", "reject"], input);
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Amount `1.00005` has more than four decimal places"));
+    assert!(stderr.contains("Skipped 4 malformed rows"));
+
+    let output = run_with_stdin(&["--strict"], input);
+    assert_eq!(output.status.code(), Some(3));
+    assert!(
+        String::from_utf8(output.stderr)
+            .unwrap()
+            .contains("Amount `1.00004` has more than four decimal places")
+    );
+
+    for (mode, total) in [("truncate", "4.0001"), ("half-up", "4.0004")] {
+        let output = run_with_stdin(&["--rounding", mode], input);
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            format!("client,available,held,total,locked\n1,{total},0.0000,{total},false\n")
+        );
+    }
+}
+
+#[test]
+fn test_validate() {
+    let output = run(&["validate", &fixture("malformed.csv")]);
+    assert_eq!(output.status.code(), Some(1));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(!stdout.contains("client,available"));
+    assert!(stdout.contains("Line 3: unknown variant `deposti`"));
+    assert!(stdout.contains("malformed: 3\nFound 3 problems\n"));
+
+    let output = run(&["validate", &fixture("many_clients.csv")]);
+    assert!(output.status.success());
+    assert!(
+        String::from_utf8(output.stdout)
+            .unwrap()
+            .ends_with("Found 0 problems\n")
+    );
+
+    // Extra decimal places are a problem unless a rounding is asked for.
+    let input = "type,client,tx,amount\ndeposit,1,1,1.00005\n";
+    let output = run_with_stdin(&["validate"], input);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(
+        String::from_utf8(output.stdout)
+            .unwrap()
+            .ends_with("malformed: 1\nFound 1 problems\n")
+    );
+    let output = run_with_stdin(&["validate", "--rounding", "half-even"], input);
+    assert!(output.status.success());
+
+    // `process` is the default command.
+    let output = run(&["process", &fixture("many_clients.csv")]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, run(&[&fixture("many_clients.csv")]).stdout);
+}
+
+#[test]
+fn test_self_check() {
+    let output = run(&["--self-check", &fixture("mixed.csv")]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, run(&[&fixture("mixed.csv")]).stdout);
+}
+
+#[test]
+fn test_progress_is_not_drawn_into_a_pipe() {
+    let expected = std::fs::read(fixture("many_clients.expected.csv")).unwrap();
+    let output = run(&["--progress", &fixture("many_clients.csv")]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, expected);
+    assert!(output.stderr.is_empty());
+}
+
+#[test]
+fn test_stats() {
+    let output = run(&["--stats", "--workers", "2", &fixture("malformed.csv")]);
+    assert_eq!(output.status.code(), Some(4));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains(
+        "Rows read: 6\n\
+         Rows skipped: 3\n\
+         Transactions: deposit 2, withdrawal 1\n\
+         Rejected: 0\n\
+         Accounts created: 1\n\
+         Locked accounts: 0\n"
+    ));
+}
+
+#[test]
+fn test_print_stats() {
+    let output = run(&["--print-stats", &fixture("mixed.csv")]);
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains(
+        "Transactions: deposit 3, withdrawal 2 (1 failed), dispute 2, resolve 1, chargeback 1\n\
+         Rejected: 1 (InsufficientFunds 1)\n"
+    ));
+}
+
+#[test]
+fn test_listen() {
+    let mut server = Command::new(env!("CARGO_BIN_EXE_bank"))
+        .args(["--listen", "127.0.0.1:0", "--verbose"])
+        .stderr(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()
+        .expect("Failed to run the binary");
+    let mut stderr = BufReader::new(server.stderr.take().unwrap()).lines();
+    let addr = stderr
+        .find_map(|line| {
+            let line = line.unwrap();
+            line.split_once("Listening on ")
+                .map(|(_, addr)| addr.to_string())
+        })
+        .unwrap();
+
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream
+        .write_all(
+            b"{\"type\": \"deposit\", \"client\": 2, \"tx\": 1, \"amount\": 1.5}\n\
+              {\"type\": \"deposit\", \"client\": 1, \"tx\": 2, \"amount\": \"2\"}\n\
+              {\"type\": \"deposit\", \"client\": 1, \"tx\": 3, \"amount\": 0.25}\n\
+              {\"type\": \"deposti\", \"client\": 1, \"tx\": 4, \"amount\": 1}\n\
+              dump\n",
+        )
+        .unwrap();
+    let response = BufReader::new(&stream)
+        .lines()
+        .map(|line| line.unwrap())
+        .take_while(|line| !line.is_empty())
+        .collect::<Vec<_>>();
+    server.kill().unwrap();
+    server.wait().unwrap();
+
+    assert!(response[0].starts_with("Malformed row at line 4"));
+    assert_eq!(
+        response[1..],
+        [
+            "client,available,held,total,locked",
+            "1,2.2500,0.0000,2.2500,false",
+            "2,1.5000,0.0000,1.5000,false",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_serve() {
+    let mut server = Command::new(env!("CARGO_BIN_EXE_bank"))
+        .args(["serve", "--port", "0", "--verbose"])
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Failed to run the binary");
+    let mut stderr = BufReader::new(server.stderr.take().unwrap()).lines();
+    let addr = stderr
+        .find_map(|line| {
+            let line = line.unwrap();
+            line.split_once("Listening on ")
+                .map(|(_, addr)| addr.to_string())
+        })
+        .unwrap();
+
+    let client = reqwest::Client::new();
+    let url = |path: &str| format!("http://{addr}{path}");
+    let submit = |body: &'static str| client.post(url("/transactions")).body(body).send();
+    let response = submit(r#"{"type": "deposit", "client": 1, "tx": 1, "amount": 2.5}"#)
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    let response = submit(r#"{"type": "withdrawal", "client": 1, "tx": 2, "amount": "5"}"#)
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 400);
+    assert_eq!(
+        response.text().await.unwrap(),
+        "Insufficient funds for transaction: requested 5.0000 but only 2.5000 available"
+    );
+    let response = submit(r#"{"type": "deposti", "client": 1, "tx": 3}"#)
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 400);
+
+    let accounts = client.get(url("/accounts")).send().await.unwrap();
+    assert_eq!(
+        accounts.text().await.unwrap(),
+        r#"[{"client":1,"available":"2.5000","held":"0.0000","total":"2.5000","locked":false}]"#
+    );
+    let account = client.get(url("/accounts/1")).send().await.unwrap();
+    assert_eq!(
+        account.text().await.unwrap(),
+        r#"[{"client":1,"available":"2.5000","held":"0.0000","total":"2.5000","locked":false}]"#
+    );
+    let missing = client.get(url("/accounts/2")).send().await.unwrap();
+    assert_eq!(missing.status(), 404);
+
+    server.kill().unwrap();
+    server.wait().unwrap();
+}
+
+#[test]
+fn test_compressed_input() {
+    let plain = run(&[&fixture("mixed.csv")]);
+    let gzip = run(&[&fixture("mixed.csv.gz")]);
+    let zstd = run(&[&fixture("mixed.jsonl.zst")]);
+    assert!(gzip.status.success());
+    assert!(zstd.status.success());
+    assert_eq!(gzip.stdout, plain.stdout);
+    assert_eq!(zstd.stdout, plain.stdout);
+
+    // Stdin has no extension, the compression has to be given.
+    let stdin = Command::new(env!("CARGO_BIN_EXE_bank"))
+        .args(["--compression", "gzip"])
+        .stdin(std::fs::File::open(fixture("mixed.csv.gz")).unwrap())
+        .output()
+        .unwrap();
+    assert!(stdin.status.success());
+    assert_eq!(stdin.stdout, plain.stdout);
+}
+
+#[test]
+fn test_corrupted_archive() {
+    let output = run(&[&fixture("truncated.csv.gz")]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("Failed to decompress the input"),
+        "{stderr}"
+    );
+    assert!(!stderr.contains("panicked"));
+
+    // A file that isn't compressed at all fails the same way.
+    let output = run(&["--compression", "zstd", &fixture("mixed.csv")]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("Failed to decompress the input"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn test_multiple_inputs() {
+    let single = run(&[&fixture("mixed.csv")]);
+    let split = run(&[
+        &fixture("mixed_1.csv"),
+        &fixture("mixed_2.csv"),
+        &fixture("mixed_3.csv"),
+    ]);
+    assert!(split.status.success());
+    assert_eq!(split.stdout, single.stdout);
+
+    // The dispute in the second file holds the deposit of the first, also when the files go through the channel.
+    let first = std::fs::read_to_string(fixture("mixed_1.csv")).unwrap();
+    let output = run_with_stdin(&["-", &fixture("mixed_2.csv")], &first);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,available,held,total,locked\n\
+         1,-2.2500,10.5000,8.2500,false\n\
+         2,3.1234,0.0000,3.1234,false\n\
+         3,0.0001,0.0000,0.0001,false\n"
+    );
+
+    // The lines are prefixed with the file they are in.
+    let output = run(&[
+        "--validate",
+        &fixture("mixed_1.csv"),
+        &fixture("mixed_2.csv"),
+        &fixture("mixed_3.csv"),
+    ]);
+    assert_eq!(output.status.code(), Some(1));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(
+        stdout.starts_with(&format!(
+            "Line {}:2: The withdrawal of client 2 with ID 4 was rejected: Insufficient funds for transaction: requested 5.0000 but only 3.1234 available\n",
+            fixture("mixed_2.csv")
+        )),
+        "{stdout}"
+    );
+}
+
+#[test]
+fn test_max_amount() {
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,1000\n\
+                 deposit,1,2,1000.0001\n\
+                 withdrawal,1,3,999.9999\n";
+    let output = run_with_stdin(&["--max-amount", "1000", "--stats"], input);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,available,held,total,locked\n1,0.0001,0.0000,0.0001,false\n"
+    );
+    assert!(
+        String::from_utf8(output.stderr)
+            .unwrap()
+            .contains("AmountTooLarge")
+    );
+
+    let output = run_with_stdin(&["--max-amount", "-1"], input);
+    assert!(!output.status.success());
+}
+
+#[test]
+fn test_audit_log_reconstructs_report() {
+    let path = std::env::temp_dir().join(format!("bank-audit-{}.csv", std::process::id()));
+    let output = run(&["--audit-log", path.to_str().unwrap(), &fixture("mixed.csv")]);
+    assert!(output.status.success());
+    // The rejected withdrawal is still reported on stderr.
+    assert!(
+        String::from_utf8(output.stderr)
+            .unwrap()
+            .contains("Transaction rejected")
+    );
+    let audit_log = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    // The last row of each client has its final balances, and a chargeback locks the account.
+    let mut accounts = std::collections::BTreeMap::new();
+    for row in audit_log.lines().skip(1) {
+        let fields = row.split(',').collect::<Vec<_>>();
+        let client = fields[0].parse::<u16>().unwrap();
+        let locked =
+            accounts.get(&client).is_some_and(|(_, locked)| *locked) || fields[2] == "chargeback";
+        accounts.insert(client, (fields[5..8].join(","), locked));
+    }
+    let report = accounts
+        .into_iter()
+        .map(|(client, (balances, locked))| format!("{client},{balances},{locked}\n"))
+        .collect::<String>();
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        format!("client,available,held,total,locked\n{report}")
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_watch() {
+    use std::{fs::OpenOptions, sync::mpsc, thread, time::Duration};
+
+    let path = std::env::temp_dir().join(format!("bank-cli-watch-{}.csv", std::process::id()));
+    std::fs::write(&path, "type,client,tx,amount\ndeposit,1,1,1.0\n").unwrap();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_bank"))
+        .args(["--watch", path.to_str().unwrap(), "--report-interval", "1"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("Failed to run the binary");
+    let (sender, lines) = mpsc::channel();
+    let stdout = child.stdout.take().unwrap();
+    thread::spawn(move || {
+        for line in BufReader::new(stdout).lines() {
+            let _ = sender.send(line.unwrap());
+        }
+    });
+    // Waits for a report with the row, failing instead of hanging if none comes.
+    let wait_for = |row: &str| loop {
+        let line = lines.recv_timeout(Duration::from_secs(10)).unwrap();
+        if line == row {
+            break;
+        }
+    };
+    let signal = |name: &str| {
+        let status = Command::new("kill")
+            .args([name, &child.id().to_string()])
+            .status()
+            .unwrap();
+        assert!(status.success());
+    };
+
+    wait_for("1,1.0000,0.0000,1.0000,false");
+    let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+    file.write_all(b"deposit,1,2,2.5\nwithdrawal,1,3,0.5\n")
+        .unwrap();
+    wait_for("1,3.0000,0.0000,3.0000,false");
+
+    // A replaced file is read from its start, on top of the accounts so far.
+    std::fs::write(&path, "type,client,tx,amount\ndeposit,2,4,4.0\n").unwrap();
+    signal("-HUP");
+    wait_for("2,4.0000,0.0000,4.0000,false");
+
+    signal("-INT");
+    let status = child.wait().unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert!(status.success());
+    // The final report comes last, after the periodic ones.
+    let rest = lines.into_iter().collect::<Vec<_>>();
+    assert_eq!(
+        rest[rest.len() - 3..],
+        [
+            "client,available,held,total,locked",
+            "1,3.0000,0.0000,3.0000,false",
+            "2,4.0000,0.0000,4.0000,false",
+        ]
+    );
+}
+
+#[test]
+fn test_watch_needs_a_file() {
+    let output = run_with_stdin(&["--watch"], "");
+    assert_eq!(output.status.code(), Some(2));
+    assert!(
+        String::from_utf8(output.stderr)
+            .unwrap()
+            .contains("--watch follows a single input file")
+    );
+    let output = run(&["--report-interval", "1", &fixture("mixed.csv")]);
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn test_version() {
+    // The input isn't read, stdin is left open.
+    let output = run_with_stdin(&["--version"], "");
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.starts_with(&format!("bank {}\n", env!("CARGO_PKG_VERSION"))));
+    assert!(stdout.contains("input formats: csv, jsonl\n"));
+    assert_eq!(run(&["version"]).stdout, stdout.as_bytes());
+
+    let output = run(&["--version", "--json"]);
+    assert!(output.status.success());
+    let info: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(info["version"], env!("CARGO_PKG_VERSION"));
+    assert_eq!(
+        info["features"],
+        serde_json::json!(if cfg!(feature = "decimal") {
+            vec!["decimal"]
+        } else {
+            vec![]
+        })
+    );
+    assert_eq!(
+        info["output_formats"],
+        serde_json::json!(["csv", "json", "ndjson"])
+    );
+    assert_eq!(run(&["version", "--json"]).stdout, output.stdout);
+
+    assert_eq!(run(&["--json"]).status.code(), Some(2));
+}
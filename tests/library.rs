@@ -0,0 +1,513 @@
+//! Integration tests driving the engine through the public library API.
+use std::sync::{Arc, Mutex};
+
+use bank::{
+    Account, AuditEntry, ConcurrentState, Config, LockPolicy, Money, ShardedState, State,
+    Transaction, TransactionError, TransactionObserver, TransactionReader, TransactionType,
+};
+use tokio::sync::mpsc;
+
+/// Formats the summary of an account as `available/held/total/locked`.
+fn balances(state: &State, client_id: u16) -> String {
+    let summary = state.get_account(client_id).unwrap().summary(4);
+    format!(
+        "{}/{}/{}/{}",
+        summary.available, summary.held, summary.total, summary.locked
+    )
+}
+
+#[test]
+fn test_process_all() {
+    let mut state = State::default();
+    state.process_all(
+        [
+            Transaction::new(TransactionType::Deposit, 1, 1, Some(Money::from_raw(20000))),
+            Transaction::new(TransactionType::Deposit, 2, 2, Some(Money::from_raw(10000))),
+            Transaction::new(
+                TransactionType::Withdrawal,
+                1,
+                3,
+                Some(Money::from_raw(5000)),
+            ),
+            Transaction::new(TransactionType::Dispute, 2, 2, None),
+            Transaction::new(TransactionType::Chargeback, 2, 2, None),
+        ]
+        .into_iter(),
+    );
+    assert_eq!(state.get_all_accounts().len(), 2);
+    assert_eq!(balances(&state, 1), "1.5000/0.0000/1.5000/false");
+    assert_eq!(balances(&state, 2), "0.0000/0.0000/0.0000/true");
+}
+
+#[tokio::test]
+async fn test_run_with_config() {
+    let (sender, receiver) = mpsc::channel(10);
+    let config = Config {
+        lock_policy: LockPolicy::BlockAll,
+        ..Default::default()
+    };
+    let mut state = State::new(receiver).with_config(config);
+    let handle = tokio::spawn(async move {
+        state.run().await;
+        state
+    });
+    for transaction in [
+        Transaction::new(TransactionType::Deposit, 1, 1, Some(Money::from_raw(10000))),
+        Transaction::new(TransactionType::Deposit, 1, 2, Some(Money::from_raw(10000))),
+        Transaction::new(TransactionType::Dispute, 1, 1, None),
+        Transaction::new(TransactionType::Dispute, 1, 2, None),
+        Transaction::new(TransactionType::Chargeback, 1, 1, None),
+        // Blocked by the lock policy.
+        Transaction::new(TransactionType::Resolve, 1, 2, None),
+    ] {
+        sender.send(transaction).await.unwrap();
+    }
+    drop(sender);
+    let state = handle.await.unwrap();
+    assert_eq!(balances(&state, 1), "0.0000/1.0000/1.0000/true");
+    let account = state.get_account(1).unwrap();
+    assert_eq!(account.disputed_transactions().collect::<Vec<_>>(), [2]);
+}
+
+#[test]
+fn test_process() {
+    let accounts = bank::process([
+        Transaction::new(TransactionType::Deposit, 3, 1, Some(Money::from_raw(12345))),
+        Transaction::new(
+            TransactionType::Withdrawal,
+            3,
+            2,
+            Some(Money::from_raw(2345)),
+        ),
+    ]);
+    let summary = accounts[&3].summary(2);
+    assert_eq!(summary.client, 3);
+    assert_eq!(summary.total.to_string(), "1.00");
+    assert_eq!(accounts[&3].available(), Money::from_raw(10000));
+    assert!(!accounts[&3].is_locked());
+}
+
+/// Generates a deterministic stream of deposits, withdrawals and disputes over a thousand clients.
+fn generate_transactions(count: u32) -> Vec<Transaction> {
+    let client = |tx: u32| (tx.wrapping_mul(2654435761) % 1000) as u16;
+    (0..count)
+        .map(|tx| {
+            // Disputes reference an earlier transaction of the same client, which may have failed.
+            let disputed = |offset: u32| tx.saturating_sub(offset);
+            match tx % 10 {
+                7 => Transaction::new(TransactionType::Dispute, client(tx - 7), tx - 7, None),
+                8 => Transaction::new(
+                    TransactionType::Resolve,
+                    client(disputed(18)),
+                    disputed(18),
+                    None,
+                ),
+                9 => Transaction::new(
+                    TransactionType::Chargeback,
+                    client(disputed(29)),
+                    disputed(29),
+                    None,
+                ),
+                2 | 5 => Transaction::new(
+                    TransactionType::Withdrawal,
+                    client(tx),
+                    tx,
+                    Some(Money::from_raw(7000)),
+                ),
+                _ => Transaction::new(
+                    TransactionType::Deposit,
+                    client(tx),
+                    tx,
+                    Some(Money::from_raw(5000)),
+                ),
+            }
+        })
+        .collect()
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_sharded_matches_single_state() {
+    let transactions = generate_transactions(1_000_000);
+
+    let (error_sink, mut errors) = mpsc::unbounded_channel();
+    let mut single = State::new_standalone().with_error_sink(error_sink);
+    single.process_all(transactions.iter().cloned());
+    let mut single_errors = 0;
+    while errors.try_recv().is_ok() {
+        single_errors += 1;
+    }
+
+    let (error_sink, mut errors) = mpsc::unbounded_channel();
+    let sharded = ShardedState::spawn(State::new_standalone().with_error_sink(error_sink), 4, 100);
+    for transaction in transactions {
+        sharded.send(transaction).await.unwrap();
+    }
+    let sharded = sharded.finish().await;
+    let mut sharded_errors = 0;
+    while errors.try_recv().is_ok() {
+        sharded_errors += 1;
+    }
+
+    assert_eq!(sharded.get_all_accounts().len(), 1000);
+    assert_eq!(sharded.snapshot(), single.snapshot());
+    assert_eq!(sharded_errors, single_errors);
+}
+
+/// Generates transactions whose IDs collide across clients: deposits and withdrawals reusing the IDs of other clients,
+/// some of them after a withdrawal with that ID failed, and disputes of IDs that belong to other clients.
+fn generate_colliding_transactions(count: u32) -> Vec<Transaction> {
+    // Deterministic pseudo-random numbers (xorshift64).
+    let mut seed = 0x5eed_u64;
+    let mut below = |bound: u32| {
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        (seed % u64::from(bound)) as u32
+    };
+    (0..count)
+        .map(|_| {
+            let client = below(64) as u16;
+            let tx = below(count / 8);
+            let amount = Some(Money::from_raw(i64::from(below(20_000)) + 1));
+            match below(10) {
+                0..4 => Transaction::new(TransactionType::Deposit, client, tx, amount),
+                4..7 => Transaction::new(TransactionType::Withdrawal, client, tx, amount),
+                7 => Transaction::new(TransactionType::Dispute, client, tx, None),
+                8 => Transaction::new(TransactionType::Resolve, client, tx, None),
+                _ => Transaction::new(TransactionType::Chargeback, client, tx, None),
+            }
+        })
+        .collect()
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_sharded_duplicate_ids_match_single_state() {
+    let transactions = generate_colliding_transactions(400_000);
+    let mut single = State::new_standalone();
+    single.process_all(transactions.iter().cloned());
+    let rejections = single.stats().rejections().clone();
+    assert!(rejections["DuplicateTransactionId"] > 10_000);
+    assert!(rejections["DisputeClientMismatch"] > 10_000);
+
+    // A small channel lets the workers drift apart less than a large one, the result must be the same either way.
+    for channel_size in [1, 1000] {
+        let sharded = ShardedState::spawn(State::new_standalone(), 4, channel_size);
+        for transaction in transactions.iter().cloned() {
+            sharded.send(transaction).await.unwrap();
+        }
+        let sharded = sharded.finish().await;
+        assert_eq!(sharded.stats(), single.stats());
+        assert_eq!(sharded.snapshot(), single.snapshot());
+    }
+}
+
+#[test]
+fn test_concurrent_matches_sequential() {
+    let transactions = generate_transactions(200_000);
+    let mut sequential = State::new_standalone();
+    let mut sequential_errors = 0;
+    for transaction in transactions.iter().cloned() {
+        if sequential.process_transaction(transaction).is_err() {
+            sequential_errors += 1;
+        }
+    }
+
+    // Every thread submits the transactions of its clients in order, all of them sharing the same state.
+    let threads = 8;
+    let concurrent = ConcurrentState::new();
+    let errors = std::thread::scope(|scope| {
+        let handles = (0..threads)
+            .map(|index| {
+                let (concurrent, transactions) = (&concurrent, &transactions);
+                scope.spawn(move || {
+                    transactions
+                        .iter()
+                        .filter(|transaction| {
+                            usize::from(transaction.get_client_id()) % threads == index
+                        })
+                        .filter(|transaction| {
+                            concurrent
+                                .process_transaction((*transaction).clone())
+                                .is_err()
+                        })
+                        .count()
+                })
+            })
+            .collect::<Vec<_>>();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .sum::<usize>()
+    });
+
+    assert_eq!(concurrent.snapshot(), sequential.snapshot());
+    assert_eq!(errors, sequential_errors);
+    let state = concurrent.into_state();
+    assert_eq!(state.get_all_accounts().len(), 1000);
+    assert_eq!(state.stats(), sequential.stats());
+}
+
+#[test]
+fn test_concurrent_transaction_ids_are_unique() {
+    // Every client deposits with the same IDs at the same time, only one of them gets each ID.
+    let concurrent = ConcurrentState::new();
+    std::thread::scope(|scope| {
+        for client in 0..8 {
+            let concurrent = &concurrent;
+            scope.spawn(move || {
+                for tx in 0..1000 {
+                    let deposit = Transaction::new(
+                        TransactionType::Deposit,
+                        client,
+                        tx,
+                        Some(Money::from_raw(10000)),
+                    );
+                    let _ = concurrent.process_transaction(deposit);
+                }
+            });
+        }
+    });
+    let state = concurrent.into_state();
+    let total = state
+        .get_all_accounts()
+        .values()
+        .map(|account| account.total())
+        .fold(Money::ZERO, |sum, total| sum + total);
+    assert_eq!(total, Money::from_raw(1000 * 10000));
+    assert_eq!(state.stats().rejected(), 7000);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_channel_preserves_order() {
+    let transactions = generate_transactions(100_000);
+
+    let mut sequential = State::new_standalone();
+    for transaction in transactions.iter().cloned() {
+        // Failures are expected, the order they happen in is what matters.
+        let _ = sequential.process_transaction(transaction);
+    }
+
+    let (error_sink, _errors) = mpsc::unbounded_channel();
+    let (sender, receiver) = mpsc::channel(16);
+    let mut state = State::new(receiver).with_error_sink(error_sink);
+    let handle = tokio::spawn(async move {
+        state.run().await;
+        state
+    });
+    for transaction in transactions {
+        sender.send(transaction).await.unwrap();
+    }
+    drop(sender);
+    let state = handle.await.unwrap();
+
+    assert_eq!(state.snapshot(), sequential.snapshot());
+}
+
+#[test]
+fn test_stats() {
+    let input = std::fs::File::open(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/fixtures/mixed.csv"
+    ))
+    .unwrap();
+    let mut state = State::new_standalone();
+    state.process_all(
+        TransactionReader::new(input)
+            .unwrap()
+            .map(|row| row.unwrap()),
+    );
+
+    let stats = state.stats();
+    assert_eq!(stats.transactions(), 9);
+    assert_eq!(stats.count(TransactionType::Deposit), 3);
+    assert_eq!(stats.count(TransactionType::Withdrawal), 2);
+    assert_eq!(stats.count(TransactionType::Dispute), 2);
+    assert_eq!(stats.count(TransactionType::Resolve), 1);
+    assert_eq!(stats.count(TransactionType::Chargeback), 1);
+    assert_eq!(stats.count(TransactionType::Unlock), 0);
+    assert_eq!(stats.failed(TransactionType::Withdrawal), 1);
+    for tx_type in TransactionType::ALL {
+        if tx_type != TransactionType::Withdrawal {
+            assert_eq!(stats.failed(tx_type), 0, "{tx_type}");
+        }
+    }
+    assert_eq!(stats.rejected(), 1);
+    assert_eq!(
+        stats.rejections().iter().collect::<Vec<_>>(),
+        [(&"InsufficientFunds", &1)]
+    );
+    assert_eq!(stats.accounts_created(), 3);
+    assert_eq!(stats.locked_accounts(), 1);
+}
+
+/// Records every callback as `accepted <type> <tx>` or `rejected <type> <tx>: <error>`.
+#[derive(Clone, Default)]
+struct Recorder(Arc<Mutex<Vec<String>>>);
+
+impl TransactionObserver for Recorder {
+    fn on_accepted(&mut self, transaction: &Transaction, _entry: &AuditEntry) {
+        self.0.lock().unwrap().push(format!(
+            "accepted {} {}",
+            transaction.get_type(),
+            transaction.get_transaction_id()
+        ));
+    }
+
+    fn on_rejected(&mut self, transaction: &Transaction, error: &TransactionError) {
+        self.0.lock().unwrap().push(format!(
+            "rejected {} {}: {error}",
+            transaction.get_type(),
+            transaction.get_transaction_id()
+        ));
+    }
+}
+
+#[tokio::test]
+async fn test_transaction_observer() {
+    let input = std::fs::File::open(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/fixtures/mixed.csv"
+    ))
+    .unwrap();
+    let recorder = Recorder::default();
+    let (sender, receiver) = mpsc::channel(10);
+    let mut state = State::new(receiver).with_transaction_observer(recorder.clone());
+    let handle = tokio::spawn(async move {
+        state.run().await;
+        state
+    });
+    for transaction in TransactionReader::new(input).unwrap() {
+        sender.send(transaction.unwrap()).await.unwrap();
+    }
+    drop(sender);
+    let mut state = handle.await.unwrap();
+
+    // Transactions processed directly are reported as well.
+    let err = state
+        .process_transaction(Transaction::new(TransactionType::Dispute, 3, 7, None))
+        .unwrap_err();
+    assert_eq!(err.error, TransactionError::TransactionDoesNotExist);
+    assert_eq!(
+        *recorder.0.lock().unwrap(),
+        [
+            "accepted deposit 1",
+            "accepted deposit 2",
+            "accepted withdrawal 3",
+            "rejected withdrawal 4: Insufficient funds for transaction: requested 5.0000 but only 3.1234 available",
+            "accepted dispute 1",
+            "accepted deposit 5",
+            "accepted dispute 2",
+            "accepted chargeback 2",
+            "accepted resolve 1",
+            "rejected dispute 7: Transaction does not exist",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_sharded_observer() {
+    let recorder = Recorder::default();
+    let state = State::new_standalone().with_transaction_observer(recorder.clone());
+    let sharded = ShardedState::spawn(state, 3, 10);
+    for tx in 1..=6 {
+        sharded
+            .send(Transaction::new(
+                TransactionType::Withdrawal,
+                tx as u16,
+                tx,
+                Some(Money::from_raw(1)),
+            ))
+            .await
+            .unwrap();
+    }
+    sharded.finish().await;
+
+    let mut events = recorder.0.lock().unwrap().clone();
+    events.sort();
+    assert_eq!(
+        events,
+        (1..=6)
+            .map(|tx| format!(
+                "rejected withdrawal {tx}: Insufficient funds for transaction: requested 0.0001 but only 0.0000 available"
+            ))
+            .collect::<Vec<_>>()
+    );
+}
+
+#[tokio::test]
+async fn test_account_observer() {
+    let observed = Arc::new(Mutex::new(vec![]));
+    let record = |observed: &Arc<Mutex<Vec<(u16, Money)>>>| {
+        let observed = observed.clone();
+        move |client, account: &Account| observed.lock().unwrap().push((client, account.total()))
+    };
+    let deposits = [(1, 20000), (2, 5000), (1, 15000), (3, 1)];
+    let transactions = || {
+        deposits
+            .iter()
+            .zip(1..)
+            .flat_map(|(&(client, amount), tx)| {
+                [
+                    Transaction::new(
+                        TransactionType::Deposit,
+                        client,
+                        tx,
+                        Some(Money::from_raw(amount)),
+                    ),
+                    // Rejected, so not observed.
+                    Transaction::new(
+                        TransactionType::Deposit,
+                        client,
+                        tx,
+                        Some(Money::from_raw(1)),
+                    ),
+                ]
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let mut state = State::new_standalone().with_observer(record(&observed));
+    state.process_all(transactions().into_iter());
+    assert_eq!(
+        *observed.lock().unwrap(),
+        [(1, 20000), (2, 5000), (1, 35000), (3, 1)]
+            .map(|(client, total)| (client, Money::from_raw(total)))
+    );
+
+    // The workers share the callback, which sees the transactions of each client in order.
+    let sharded_observed = Arc::new(Mutex::new(vec![]));
+    let state = State::new_standalone().with_observer(record(&sharded_observed));
+    let sharded = ShardedState::spawn(state, 2, 10);
+    for transaction in transactions() {
+        sharded.send(transaction).await.unwrap();
+    }
+    sharded.finish().await;
+    let client_1 = sharded_observed
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|(client, _)| *client == 1)
+        .map(|(_, total)| *total)
+        .collect::<Vec<_>>();
+    assert_eq!(client_1, [Money::from_raw(20000), Money::from_raw(35000)]);
+    assert_eq!(sharded_observed.lock().unwrap().len(), 4);
+}
+
+#[test]
+fn test_transactions_round_trip() {
+    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/roundtrip.csv");
+    let input = std::fs::read_to_string(path).unwrap();
+    let mut writer = csv::Writer::from_writer(vec![]);
+    for transaction in TransactionReader::new(input.as_bytes()).unwrap() {
+        writer.serialize(transaction.unwrap()).unwrap();
+    }
+    let output = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+    assert_eq!(output, input);
+
+    let transaction = Transaction::new(TransactionType::Deposit, 1, 2, "1.5".parse().ok())
+        .with_currency("usd".parse().unwrap());
+    assert_eq!(
+        serde_json::to_string(&transaction).unwrap(),
+        r#"{"type":"deposit","client":1,"tx":2,"amount":"1.5000","currency":"USD"}"#
+    );
+}
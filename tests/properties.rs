@@ -0,0 +1,135 @@
+//! Property tests replaying random sequences of transactions against a single account.
+use std::collections::HashMap;
+
+use bank::{
+    Account, Config, DisputePolicy, LockPolicy, Money, Transaction, TransactionError,
+    TransactionId, TransactionType, WithdrawalDisputePolicy,
+};
+use proptest::prelude::*;
+
+/// Any transaction type, with the ones changing balances more likely.
+fn tx_type() -> impl Strategy<Value = TransactionType> {
+    prop_oneof![
+        3 => Just(TransactionType::Deposit),
+        3 => Just(TransactionType::Withdrawal),
+        3 => Just(TransactionType::Dispute),
+        2 => Just(TransactionType::Resolve),
+        2 => Just(TransactionType::Chargeback),
+        1 => Just(TransactionType::Unlock),
+        1 => Just(TransactionType::Hold),
+        1 => Just(TransactionType::Release),
+        1 => Just(TransactionType::Freeze),
+        1 => Just(TransactionType::Unfreeze),
+    ]
+}
+
+/// Transactions of mostly the account's client, with few IDs so they are often reused and referenced.
+/// The amounts include missing, zero and negative ones.
+fn transaction() -> impl Strategy<Value = Transaction> {
+    (
+        tx_type(),
+        prop_oneof![9 => Just(1u16), 1 => Just(2u16)],
+        0..8u32,
+        prop_oneof![1 => Just(None), 1 => Just(Some(0)), 1 => Just(Some(-10000)), 6 => (1..50000i64).prop_map(Some)]
+            .prop_map(|amount| amount.map(Money::from_raw)),
+    )
+        .prop_map(|(tx_type, client, tx, amount)| Transaction::new(tx_type, client, tx, amount))
+}
+
+/// Any engine configuration.
+fn config() -> impl Strategy<Value = Config> {
+    (
+        prop_oneof![
+            Just(DisputePolicy::AllowNegativeAvailable),
+            Just(DisputePolicy::RejectIfInsufficientAvailable)
+        ],
+        prop_oneof![
+            Just(WithdrawalDisputePolicy::HoldFromTotal),
+            Just(WithdrawalDisputePolicy::CreditOnChargeback),
+            Just(WithdrawalDisputePolicy::Reject)
+        ],
+        any::<bool>(),
+        prop_oneof![
+            Just(LockPolicy::BlockFundsMovement),
+            Just(LockPolicy::BlockAll)
+        ],
+        any::<bool>(),
+        prop_oneof![Just(Money::ZERO), Just(Money::from_raw(20000))],
+        any::<bool>(),
+        prop_oneof![Just(None), Just(Some(2))],
+        prop_oneof![Just(None), Just(Some(Money::from_raw(20000)))],
+    )
+        .prop_map(
+            |(
+                dispute_policy,
+                withdrawal_dispute_policy,
+                verify_dispute_amounts,
+                lock_policy,
+                park_unknown_disputes,
+                overdraft_limit,
+                allow_zero_amounts,
+                finality_window,
+                max_amount,
+            )| {
+                Config {
+                    dispute_policy,
+                    withdrawal_dispute_policy,
+                    verify_dispute_amounts,
+                    lock_policy,
+                    park_unknown_disputes,
+                    overdraft_limit,
+                    allow_zero_amounts,
+                    finality_window,
+                    max_amount,
+                }
+            },
+        )
+}
+
+proptest! {
+    #[test]
+    fn test_account_invariants(
+        config in config(),
+        transactions in prop::collection::vec(transaction(), 1..60),
+    ) {
+        let mut account = Account::with_config(1, config);
+        // The amounts of the applied deposits and withdrawals, to know what a dispute holds.
+        let mut recorded: HashMap<TransactionId, (TransactionType, Money)> = HashMap::new();
+        let holds_withdrawals =
+            config.withdrawal_dispute_policy != WithdrawalDisputePolicy::CreditOnChargeback;
+        for transaction in transactions {
+            let before = (account.available(), account.held(), account.total());
+            let was_locked = account.is_locked();
+            let tx_type = *transaction.get_type();
+            let own = transaction.get_client_id() == account.client_id();
+            let (tx, amount) = (transaction.get_transaction_id(), transaction.get_amount());
+            let result = account.process_transaction(transaction);
+            if result.is_ok()
+                && matches!(tx_type, TransactionType::Deposit | TransactionType::Withdrawal)
+            {
+                recorded.insert(tx, (tx_type, amount.unwrap()));
+            }
+
+            prop_assert_eq!(account.check_invariants(), Ok(()));
+            prop_assert!(!account.held().is_negative());
+            let disputed = account
+                .disputed_transactions()
+                .map(|tx| recorded[&tx])
+                .filter(|(tx_type, _)| holds_withdrawals || *tx_type == TransactionType::Deposit)
+                .map(|(_, amount)| amount)
+                .sum::<Money>();
+            prop_assert_eq!(account.held(), disputed + account.manual_held());
+            // Transactions of other clients are rejected before the lock is checked.
+            if was_locked
+                && own
+                && matches!(tx_type, TransactionType::Deposit | TransactionType::Withdrawal)
+            {
+                prop_assert!(matches!(result, Err(TransactionError::AccountLocked)));
+                prop_assert_eq!((account.available(), account.held(), account.total()), before);
+            }
+            if result.is_err() {
+                prop_assert_eq!((account.available(), account.held(), account.total()), before);
+            }
+        }
+    }
+}